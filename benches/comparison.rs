@@ -32,6 +32,7 @@ impl ThorDBWrapper {
         let config = core::lsm::LsmConfig {
             data_dir: dir.path().to_path_buf(),
             memtable_size_threshold: 4 * 1024 * 1024,
+            ..Default::default()
         };
         let db = core::lsm::LsmTree::open(config).unwrap();
         Self { db, dir }
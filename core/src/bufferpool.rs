@@ -1,8 +1,9 @@
 use dashmap::{DashMap, mapref::one::Ref};
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::{constants::*, pagefile::PageFile};
+use crate::{constants::*, page, pagefile::PageFile};
 use std::{
+    ops::{Deref, DerefMut},
     path::PathBuf,
     sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
@@ -13,9 +14,15 @@ pub struct PageAddr {
     page_id: u64,
 }
 
+/// Page buffer aligned to the page size, as required by `O_DIRECT` reads and writes.
+#[repr(C, align(4096))]
+pub(crate) struct AlignedPage([u8; PAGE_SIZE]);
+
+const _: () = assert!(std::mem::align_of::<AlignedPage>() == PAGE_SIZE);
+
 pub(crate) struct BufferSlot {
     pub(crate) page_address: PageAddr,
-    pub(crate) page_data: [u8; PAGE_SIZE],
+    pub(crate) page_data: AlignedPage,
     pub(crate) is_dirty: bool,
 }
 
@@ -26,6 +33,7 @@ pub struct BufferPool {
     next_slot: AtomicUsize,
     page_files_map: DashMap<u64, PageFile>,
     page_files_dir: PathBuf,
+    direct_io: bool,
 }
 
 impl PageAddr {
@@ -33,6 +41,14 @@ impl PageAddr {
         Self { file_id, page_id }
     }
 
+    pub fn file_id(&self) -> u64 {
+        self.file_id
+    }
+
+    pub fn page_id(&self) -> u64 {
+        self.page_id
+    }
+
     pub fn next_page(&self) -> Self {
         Self {
             file_id: self.file_id,
@@ -41,11 +57,25 @@ impl PageAddr {
     }
 }
 
+impl Deref for AlignedPage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DerefMut for AlignedPage {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
 impl BufferSlot {
     fn new() -> Self {
         Self {
             page_address: PageAddr { file_id: 0, page_id: 0 },
-            page_data: [0; PAGE_SIZE as usize],
+            page_data: AlignedPage([0; PAGE_SIZE]),
             is_dirty: false,
         }
     }
@@ -54,11 +84,18 @@ impl BufferSlot {
         self.page_address = *page_address;
         page_file.read_page(page_address.page_id, &mut self.page_data, create)?;
         self.is_dirty = false;
+        if let Err(corruption) = page::verify_checksum(*page_address, &self.page_data) {
+            // Leave the slot empty so the corrupt page is never served from the pool
+            self.page_address = PageAddr { file_id: 0, page_id: 0 };
+            self.page_data.fill(0);
+            return Err(corruption.into());
+        }
         Ok(())
     }
 
     fn write_page(&mut self, page_file: &PageFile) -> Result<(), std::io::Error> {
-        page_file.write_page(self.page_address.page_id, &mut self.page_data)?;
+        page::stamp_checksum(&mut self.page_data);
+        page_file.write_page(self.page_address.page_id, &self.page_data)?;
         self.is_dirty = false;
         Ok(())
     }
@@ -66,6 +103,12 @@ impl BufferSlot {
 
 impl<'a> BufferPool {
     pub fn new(page_files_dir: String) -> Result<Self, std::io::Error> {
+        Self::with_direct_io(page_files_dir, false)
+    }
+
+    /// Create a buffer pool whose page files are opened with `O_DIRECT`, making the pool
+    /// the only cache for page data.
+    pub fn with_direct_io(page_files_dir: String, direct_io: bool) -> Result<Self, std::io::Error> {
         std::fs::create_dir_all(&page_files_dir)?;
         Ok(Self {
             slots: std::array::from_fn(|_| RwLock::new(Box::new(BufferSlot::new()))),
@@ -74,6 +117,7 @@ impl<'a> BufferPool {
             next_slot: AtomicUsize::new(0),
             page_files_map: DashMap::new(),
             page_files_dir: PathBuf::from(page_files_dir),
+            direct_io,
         })
     }
 
@@ -164,14 +208,37 @@ impl<'a> BufferPool {
                 return Ok(page_file);
             }
             dashmap::mapref::entry::Entry::Vacant(vacant) => {
-                let file_name = format!("{:0PAGE_FILE_NUM_DIGITS$}.pagefile", file_id);
-                let file_path = self.page_files_dir.join(file_name);
-                let page_file = vacant.insert(PageFile::new(&file_path)?).downgrade();
+                let file_path = self.page_file_path(file_id);
+                let page_file = vacant.insert(PageFile::new(&file_path, self.direct_io)?).downgrade();
                 return Ok(page_file);
             }
         }
     }
 
+    pub(crate) fn page_file_path(&self, file_id: u64) -> PathBuf {
+        let file_name = format!("{:0PAGE_FILE_NUM_DIGITS$}.pagefile", file_id);
+        self.page_files_dir.join(file_name)
+    }
+
+    /// Drop all cached pages of a page file without writing them back, then delete the file.
+    /// The caller must not hold any page of the pool.
+    pub fn remove_file(&self, file_id: u64) -> Result<(), std::io::Error> {
+        for (slot_index, slot) in self.slots.iter().enumerate() {
+            let mut slot = slot.write();
+            if slot.page_address.file_id == file_id {
+                self.page_to_slot.remove(&slot.page_address);
+                slot.page_address = PageAddr { file_id: 0, page_id: 0 };
+                slot.is_dirty = false;
+                self.slots_touched[slot_index].store(false, Ordering::Relaxed);
+            }
+        }
+        self.page_files_map.remove(&file_id);
+        match std::fs::remove_file(self.page_file_path(file_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     pub fn flush(&self) -> Result<(), std::io::Error> {
         for slot in self.slots.iter() {
             if let Some(mut slot) = slot.try_write() {
@@ -254,4 +321,59 @@ mod tests {
         // Clean up
         let _ = std::fs::remove_dir_all(dir);
     }
+
+    #[test]
+    fn test_buffer_pool_detects_checksum_mismatch() {
+        let dir = get_temp_dir();
+        let page_addr = PageAddr::new(1, 2);
+
+        {
+            let pool = BufferPool::new(dir.clone()).unwrap();
+            let mut slot = pool.pin_write(page_addr).unwrap();
+            slot.page_data[100] = 7;
+            drop(slot);
+            pool.flush().unwrap();
+        }
+
+        // Flip a bit on disk
+        let file_path = format!("{}/{:0PAGE_FILE_NUM_DIGITS$}.pagefile", dir, 1);
+        let mut bytes = std::fs::read(&file_path).unwrap();
+        bytes[2 * PAGE_SIZE + 100] ^= 0x01;
+        std::fs::write(&file_path, bytes).unwrap();
+
+        let pool = BufferPool::new(dir.clone()).unwrap();
+        let err = pool.pin_read(page_addr).err().unwrap();
+        assert!(matches!(
+            crate::error::Corruption::from_io_error(&err),
+            Some(crate::error::Corruption::PageChecksumMismatch { .. })
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_buffer_pool_direct_io() {
+        let dir = get_temp_dir();
+
+        {
+            let pool = BufferPool::with_direct_io(dir.clone(), true).unwrap();
+            for i in 0..BUFFER_POOL_SIZE + 10 {
+                let mut slot = pool.pin_write(PageAddr::new(1, i as u64)).unwrap();
+                assert_eq!(slot.page_data.as_ptr() as usize % PAGE_SIZE, 0);
+                slot.page_data[0] = (i % 255) as u8;
+            }
+            pool.flush().unwrap();
+        }
+
+        // A fresh pool has an empty cache, so every page must come back from disk
+        {
+            let pool = BufferPool::with_direct_io(dir.clone(), true).unwrap();
+            for i in 0..BUFFER_POOL_SIZE + 10 {
+                let slot = pool.pin_read(PageAddr::new(1, i as u64)).unwrap();
+                assert_eq!(slot.page_data[0], (i % 255) as u8);
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
@@ -0,0 +1,93 @@
+//! Typed errors for on-disk corruption.
+//!
+//! All storage APIs return `std::io::Error`. Corruption is reported as an
+//! `ErrorKind::InvalidData` error wrapping a [`Corruption`], which callers can
+//! recover with [`Corruption::from_io_error`].
+
+use std::fmt;
+
+use crate::bufferpool::PageAddr;
+
+/// Describes what was found to be corrupt on disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// The checksum stored in the page header does not match the page contents.
+    PageChecksumMismatch { page: PageAddr, stored: u32, computed: u32 },
+    /// The page header carries a format version this build cannot read.
+    UnsupportedPageVersion { page: PageAddr, version: u8 },
+    /// The page header carries an unknown page type.
+    UnknownPageType { page: PageAddr, page_type: u8 },
+    /// The free space pointers in the page header are out of bounds.
+    InvalidFreeSpace { page: PageAddr, free_start: usize, free_end: usize },
+    /// A cell pointer points outside the cell area of the page.
+    InvalidCellPointer { page: PageAddr, cell: usize, start: usize, end: usize },
+    /// An overflow chain ends early or links to a page that is not an overflow page.
+    BrokenOverflowChain { page: PageAddr },
+    /// A record cell does not have the expected layout.
+    InvalidRecord { page: PageAddr },
+    /// A value read from a blob file does not match its checksum.
+    BlobChecksumMismatch { file_id: u64, offset: u64 },
+    /// The meta block of an SSTable does not start with the SSTable magic number.
+    BadTableMagic { file_id: u64 },
+    /// The SSTable was written in a format version this build cannot read.
+    UnsupportedTableVersion { file_id: u64, version: u32 },
+    /// The meta block of an SSTable is malformed or misses a required property.
+    InvalidTableMeta { file_id: u64 },
+}
+
+impl Corruption {
+    /// Returns the corruption wrapped in `err`, if any.
+    pub fn from_io_error(err: &std::io::Error) -> Option<&Corruption> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<Corruption>())
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Corruption::PageChecksumMismatch { page, stored, computed } => write!(
+                f,
+                "checksum mismatch on page {:?}: stored {:#010x}, computed {:#010x}",
+                page, stored, computed
+            ),
+            Corruption::UnsupportedPageVersion { page, version } => {
+                write!(f, "unsupported format version {} on page {:?}", version, page)
+            }
+            Corruption::UnknownPageType { page, page_type } => {
+                write!(f, "unknown page type {} on page {:?}", page_type, page)
+            }
+            Corruption::InvalidFreeSpace {
+                page,
+                free_start,
+                free_end,
+            } => write!(
+                f,
+                "invalid free space [{}, {}) on page {:?}",
+                free_start, free_end, page
+            ),
+            Corruption::InvalidCellPointer { page, cell, start, end } => write!(
+                f,
+                "cell {} on page {:?} points to invalid range [{}, {})",
+                cell, page, start, end
+            ),
+            Corruption::BrokenOverflowChain { page } => write!(f, "broken overflow chain at page {:?}", page),
+            Corruption::InvalidRecord { page } => write!(f, "invalid record cell on page {:?}", page),
+            Corruption::BlobChecksumMismatch { file_id, offset } => {
+                write!(f, "checksum mismatch in blob file {} at offset {}", file_id, offset)
+            }
+            Corruption::BadTableMagic { file_id } => write!(f, "file {} is not an SSTable (bad magic)", file_id),
+            Corruption::UnsupportedTableVersion { file_id, version } => {
+                write!(f, "unsupported format version {} in SSTable {}", version, file_id)
+            }
+            Corruption::InvalidTableMeta { file_id } => write!(f, "invalid meta block in SSTable {}", file_id),
+        }
+    }
+}
+
+impl std::error::Error for Corruption {}
+
+impl From<Corruption> for std::io::Error {
+    fn from(corruption: Corruption) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, corruption)
+    }
+}
@@ -1,5 +1,6 @@
 // Public API
 pub mod bufferpool;
+pub mod error;
 pub mod lsm;
 pub mod page;
 pub mod serialpages;
@@ -0,0 +1,464 @@
+//! Backup engine - numbered, incremental backups of an `LsmTree`.
+//!
+//! A backup starts from a checkpoint of the tree. Table page files and blob
+//! files never change once written, so each is stored once under `shared/`,
+//! named by checksum and size, and shared by every backup holding it. The
+//! manifest and WAL segments of a backup go to `private/<id>/`.
+//!
+//! The catalog `meta/<id>` lists every file of a backup with its size and CRC32,
+//! which verify and restore check. It is written last, once the files are synced,
+//! so a backup interrupted midway has no catalog and is ignored.
+//!
+//! The checkpoint a backup starts from is written to `checkpoint/`, a scratch
+//! directory the engine owns and clears.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::lsm::{sync_path, LsmTree};
+
+/// Directories of a tree holding immutable files, which backups share.
+const SHARED_DIRS: [&str; 2] = ["pages", "blobs"];
+
+/// Summary of a backup in the catalog.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u32,
+    /// Creation time, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Total size of the backup's files, shared ones included.
+    pub size: u64,
+    pub num_files: usize,
+}
+
+/// A file of a backup, as listed in its catalog.
+struct BackupFile {
+    /// Path relative to the tree's data directory.
+    db_path: String,
+    /// Path relative to the backup directory.
+    stored_path: String,
+    size: u64,
+    crc: u32,
+}
+
+impl BackupFile {
+    /// Parse a catalog line: `<crc> <size> <stored path> <db path>`.
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [crc, size, stored_path, db_path] = fields[..] else {
+            return None;
+        };
+        Some(Self {
+            db_path: db_path.to_string(),
+            stored_path: stored_path.to_string(),
+            size: size.parse().ok()?,
+            crc: u32::from_str_radix(crc, 16).ok()?,
+        })
+    }
+}
+
+/// Creates, verifies and restores the backups kept in one directory.
+pub struct BackupEngine {
+    dir: PathBuf,
+    /// Serializes the operations on the directory, so that a purge can't delete
+    /// files a backup in progress holds, and backups don't share an id.
+    lock: Mutex<()>,
+}
+
+impl BackupEngine {
+    /// Open the backup directory, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        for sub_dir in ["shared", "private", "meta"] {
+            std::fs::create_dir_all(dir.join(sub_dir))?;
+        }
+        Ok(Self {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Back up `db`, copying only the table and blob files no earlier backup holds.
+    /// Returns the id of the new backup.
+    pub fn create_backup(&self, db: &LsmTree) -> Result<u32, std::io::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let id = self.backup_ids()?.last().map_or(1, |id| id + 1);
+        // Left behind only by an interrupted backup
+        let checkpoint_dir = self.dir.join("checkpoint");
+        if checkpoint_dir.exists() {
+            std::fs::remove_dir_all(&checkpoint_dir)?;
+        }
+        std::fs::create_dir(&checkpoint_dir)?;
+        let result = db
+            .write_checkpoint(&checkpoint_dir)
+            .and_then(|_| self.store_checkpoint(id, &checkpoint_dir));
+        let _ = std::fs::remove_dir_all(&checkpoint_dir);
+        result.map(|_| id)
+    }
+
+    fn store_checkpoint(&self, id: u32, checkpoint_dir: &Path) -> Result<(), std::io::Error> {
+        let mut files = Vec::new();
+        for sub_dir in SHARED_DIRS {
+            let mut paths = std::fs::read_dir(checkpoint_dir.join(sub_dir))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.sort();
+            for path in paths {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                let (size, crc) = checksum(&path)?;
+                let stored_path = format!("shared/{:08x}_{}_{}", crc, size, name);
+                let stored = self.dir.join(&stored_path);
+                if !stored.exists() {
+                    copy_atomically(&path, &stored)?;
+                }
+                files.push(BackupFile {
+                    db_path: format!("{}/{}", sub_dir, name),
+                    stored_path,
+                    size,
+                    crc,
+                });
+            }
+        }
+
+        let private_dir = self.dir.join("private").join(id.to_string());
+        if private_dir.exists() {
+            std::fs::remove_dir_all(&private_dir)?;
+        }
+        std::fs::create_dir_all(&private_dir)?;
+        // The files outside the shared directories change over time: the manifest and WAL segments
+        let mut private_paths = Vec::new();
+        for entry in std::fs::read_dir(checkpoint_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                private_paths.push(entry.path());
+            }
+        }
+        private_paths.sort();
+        for path in private_paths {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let (size, crc) = checksum(&path)?;
+            let stored_path = format!("private/{}/{}", id, name);
+            let stored = self.dir.join(&stored_path);
+            std::fs::copy(&path, &stored)?;
+            sync_path(&stored)?;
+            files.push(BackupFile {
+                db_path: name,
+                stored_path,
+                size,
+                crc,
+            });
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut lines = vec![format!("timestamp {}", timestamp)];
+        lines.extend(
+            files
+                .iter()
+                .map(|file| format!("{:08x} {} {} {}", file.crc, file.size, file.stored_path, file.db_path)),
+        );
+        // The files reach the disk before the catalog marks the backup complete
+        for dir in [self.dir.join("shared"), private_dir, self.dir.join("private")] {
+            sync_path(&dir)?;
+        }
+        let catalog = self.catalog_path(id);
+        let tmp = catalog.with_extension("tmp");
+        std::fs::write(&tmp, lines.join("\n"))?;
+        sync_path(&tmp)?;
+        std::fs::rename(tmp, catalog)?;
+        sync_path(&self.dir.join("meta"))
+    }
+
+    /// All complete backups, oldest first.
+    pub fn backup_infos(&self) -> Result<Vec<BackupInfo>, std::io::Error> {
+        let _lock = self.lock.lock().unwrap();
+        self.backup_ids()?
+            .into_iter()
+            .map(|id| {
+                let (timestamp, files) = self.read_catalog(id)?;
+                Ok(BackupInfo {
+                    id,
+                    timestamp,
+                    size: files.iter().map(|file| file.size).sum(),
+                    num_files: files.len(),
+                })
+            })
+            .collect()
+    }
+
+    /// Check that every file of a backup is present with the size and checksum
+    /// recorded in the catalog.
+    pub fn verify_backup(&self, id: u32) -> Result<(), std::io::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let (_, files) = self.read_catalog(id)?;
+        for file in &files {
+            if checksum(&self.dir.join(&file.stored_path))? != (file.size, file.crc) {
+                return Err(corrupt_file(id, &file.stored_path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete all but the newest `num_to_keep` backups, and the shared files only
+    /// they held.
+    pub fn purge_old_backups(&self, num_to_keep: usize) -> Result<(), std::io::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let ids = self.backup_ids()?;
+        let purged = ids.len().saturating_sub(num_to_keep);
+        for &id in &ids[..purged] {
+            // Without its catalog, the backup is gone even if the rest is left behind
+            std::fs::remove_file(self.catalog_path(id))?;
+            let private_dir = self.dir.join("private").join(id.to_string());
+            if private_dir.exists() {
+                std::fs::remove_dir_all(private_dir)?;
+            }
+        }
+
+        let mut referenced = HashSet::new();
+        for &id in &ids[purged..] {
+            referenced.extend(self.read_catalog(id)?.1.into_iter().map(|file| file.stored_path));
+        }
+        for entry in std::fs::read_dir(self.dir.join("shared"))? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !referenced.contains(&format!("shared/{}", name)) {
+                std::fs::remove_file(self.dir.join("shared").join(name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore a backup to `dest_dir`, which must not exist yet, verifying every
+    /// file copied. The restored directory can be opened with `LsmTree::open`.
+    pub fn restore_backup(&self, id: u32, dest_dir: impl AsRef<Path>) -> Result<(), std::io::Error> {
+        let _lock = self.lock.lock().unwrap();
+        self.restore(id, dest_dir.as_ref())
+    }
+
+    /// Restore the newest backup to `dest_dir`.
+    pub fn restore_latest_backup(&self, dest_dir: impl AsRef<Path>) -> Result<(), std::io::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let id = self.backup_ids()?.last().copied().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "No backup to restore")
+        })?;
+        self.restore(id, dest_dir.as_ref())
+    }
+
+    /// Restore a backup, with the lock held.
+    fn restore(&self, id: u32, dest_dir: &Path) -> Result<(), std::io::Error> {
+        if dest_dir.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Restore directory {} already exists", dest_dir.display()),
+            ));
+        }
+        let (_, files) = self.read_catalog(id)?;
+        for sub_dir in SHARED_DIRS {
+            std::fs::create_dir_all(dest_dir.join(sub_dir))?;
+        }
+        for file in &files {
+            let dest = dest_dir.join(&file.db_path);
+            std::fs::copy(self.dir.join(&file.stored_path), &dest)?;
+            if checksum(&dest)? != (file.size, file.crc) {
+                return Err(corrupt_file(id, &file.stored_path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of the complete backups, ascending.
+    fn backup_ids(&self) -> Result<Vec<u32>, std::io::Error> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(self.dir.join("meta"))? {
+            if let Some(id) = entry?.file_name().to_str().and_then(|name| name.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn catalog_path(&self, id: u32) -> PathBuf {
+        self.dir.join("meta").join(id.to_string())
+    }
+
+    /// Read the creation time and files of a backup.
+    fn read_catalog(&self, id: u32) -> Result<(u64, Vec<BackupFile>), std::io::Error> {
+        let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid catalog of backup {}", id));
+        let contents = std::fs::read_to_string(self.catalog_path(id))?;
+        let mut lines = contents.lines();
+        let timestamp = lines
+            .next()
+            .and_then(|line| line.strip_prefix("timestamp "))
+            .and_then(|secs| secs.parse().ok())
+            .ok_or_else(invalid)?;
+        let files = lines
+            .map(|line| BackupFile::parse(line).ok_or_else(invalid))
+            .collect::<Result<_, _>>()?;
+        Ok((timestamp, files))
+    }
+}
+
+/// Size and CRC32 of a file.
+fn checksum(path: &Path) -> Result<(u64, u32), std::io::Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok((size, hasher.finalize()));
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+}
+
+/// Copy `src` to `dst` so that `dst` never exists half-written.
+fn copy_atomically(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
+    let tmp = dst.with_extension("tmp");
+    std::fs::copy(src, &tmp)?;
+    sync_path(&tmp)?;
+    std::fs::rename(tmp, dst)
+}
+
+fn corrupt_file(id: u32, stored_path: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Backup {} file {} does not match its checksum", id, stored_path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::lsm::LsmConfig;
+    use super::super::types::{Key, Value};
+
+    fn get_temp_dir() -> PathBuf {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        PathBuf::from(format!("/tmp/thordb_backup_test_{}", since_epoch.as_nanos()))
+    }
+
+    fn shared_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir.join("shared")).unwrap().count()
+    }
+
+    #[test]
+    fn test_incremental_backup_and_restore() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.join("db"),
+            ..Default::default()
+        };
+        let backup_dir = dir.join("backups");
+        let engine = BackupEngine::open(&backup_dir).unwrap();
+
+        let lsm = LsmTree::open(config.clone()).unwrap();
+        lsm.put(Key::from("a"), Value::from("1")).unwrap();
+        lsm.flush().unwrap();
+        // A checkpoint left by an interrupted backup is cleared
+        std::fs::create_dir_all(backup_dir.join("checkpoint").join("pages")).unwrap();
+        assert_eq!(engine.create_backup(&lsm).unwrap(), 1);
+        assert!(!backup_dir.join("checkpoint").exists());
+        let first_shared = shared_files(&backup_dir);
+
+        // The second backup only adds the new table
+        lsm.put(Key::from("b"), Value::from("2")).unwrap();
+        lsm.flush().unwrap();
+        lsm.put(Key::from("c"), Value::from("3")).unwrap();
+        assert_eq!(engine.create_backup(&lsm).unwrap(), 2);
+        assert_eq!(shared_files(&backup_dir), first_shared + 1);
+        drop(lsm);
+
+        let infos = engine.backup_infos().unwrap();
+        assert_eq!(infos.iter().map(|info| info.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(infos[1].num_files > infos[0].num_files);
+        engine.verify_backup(1).unwrap();
+        engine.verify_backup(2).unwrap();
+
+        let open_restored = |dest: PathBuf| {
+            LsmTree::open(LsmConfig {
+                data_dir: dest,
+                ..config.clone()
+            })
+            .unwrap()
+        };
+        engine.restore_backup(1, dir.join("restore1")).unwrap();
+        let restored = open_restored(dir.join("restore1"));
+        assert_eq!(restored.get(&Key::from("a")).unwrap(), Some(Value::from("1")));
+        assert_eq!(restored.get(&Key::from("b")).unwrap(), None);
+        drop(restored);
+
+        // Purging keeps the tables the newest backup still shares
+        engine.purge_old_backups(1).unwrap();
+        assert_eq!(engine.backup_infos().unwrap().len(), 1);
+        assert!(engine.restore_backup(1, dir.join("gone")).is_err());
+        engine.verify_backup(2).unwrap();
+        engine.restore_latest_backup(dir.join("restore2")).unwrap();
+        let restored = open_restored(dir.join("restore2"));
+        assert_eq!(restored.get(&Key::from("a")).unwrap(), Some(Value::from("1")));
+        assert_eq!(restored.get(&Key::from("b")).unwrap(), Some(Value::from("2")));
+        assert_eq!(restored.get(&Key::from("c")).unwrap(), Some(Value::from("3")));
+        drop(restored);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_concurrent_backups() {
+        let dir = get_temp_dir();
+        let lsm = LsmTree::open(LsmConfig {
+            data_dir: dir.join("db"),
+            ..Default::default()
+        })
+        .unwrap();
+        lsm.put(Key::from("a"), Value::from("1")).unwrap();
+        lsm.flush().unwrap();
+        let engine = BackupEngine::open(dir.join("backups")).unwrap();
+        engine.create_backup(&lsm).unwrap();
+
+        // Backups get distinct ids, and a purge keeps the files of one in progress
+        let mut ids = std::thread::scope(|scope| {
+            let backups: Vec<_> = (0..2).map(|_| scope.spawn(|| engine.create_backup(&lsm).unwrap())).collect();
+            scope.spawn(|| engine.purge_old_backups(1).unwrap());
+            backups.into_iter().map(|backup| backup.join().unwrap()).collect::<Vec<_>>()
+        });
+        ids.sort_unstable();
+        assert_eq!(ids, [2, 3]);
+        let newest = engine.backup_infos().unwrap().last().unwrap().id;
+        assert_eq!(newest, 3);
+        engine.verify_backup(newest).unwrap();
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let dir = get_temp_dir();
+        let lsm = LsmTree::open(LsmConfig {
+            data_dir: dir.join("db"),
+            ..Default::default()
+        })
+        .unwrap();
+        lsm.put(Key::from("a"), Value::from("1")).unwrap();
+        lsm.flush().unwrap();
+        let engine = BackupEngine::open(dir.join("backups")).unwrap();
+        let id = engine.create_backup(&lsm).unwrap();
+
+        let shared = std::fs::read_dir(dir.join("backups").join("shared")).unwrap().next().unwrap().unwrap().path();
+        let mut bytes = std::fs::read(&shared).unwrap();
+        bytes[0] ^= 0xff;
+        std::fs::write(&shared, bytes).unwrap();
+
+        assert_eq!(engine.verify_backup(id).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(engine.restore_backup(id, dir.join("restore")).is_err());
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,133 @@
+//! Write batches - groups of writes applied atomically.
+//!
+//! `LsmTree::write` assigns the writes of a batch consecutive sequence numbers
+//! under a single memtable lock, so readers see all of them or none, and logs
+//! them as one WAL record, so recovery replays all of them or none. A batch may
+//! write to several column families.
+
+use super::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
+use super::comparator::{compare_keys, Comparator};
+use super::merge::MergeFold;
+use super::types::{Entry, Key, Value};
+
+/// A single write in a `WriteBatch`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BatchOp {
+    Put(Key, Value),
+    Merge(Key, Value),
+    Delete(Key),
+    DeleteRange(Key, Key),
+}
+
+/// Writes to apply atomically, in order.
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    /// Writes with the id of their column family.
+    ops: Vec<(u32, BatchOp)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a key-value pair.
+    pub fn put(&mut self, key: Key, value: Value) {
+        self.ops.push((DEFAULT_COLUMN_FAMILY_ID, BatchOp::Put(key, value)));
+    }
+
+    /// Add a merge operand for a key.
+    pub fn merge(&mut self, key: Key, operand: Value) {
+        self.ops.push((DEFAULT_COLUMN_FAMILY_ID, BatchOp::Merge(key, operand)));
+    }
+
+    /// Delete a key.
+    pub fn delete(&mut self, key: Key) {
+        self.ops.push((DEFAULT_COLUMN_FAMILY_ID, BatchOp::Delete(key)));
+    }
+
+    /// Delete all keys in `[start, end)`.
+    pub fn delete_range(&mut self, start: Key, end: Key) {
+        self.ops.push((DEFAULT_COLUMN_FAMILY_ID, BatchOp::DeleteRange(start, end)));
+    }
+
+    /// Put a key-value pair in a column family.
+    pub fn put_cf(&mut self, cf: &ColumnFamily, key: Key, value: Value) {
+        self.ops.push((cf.id(), BatchOp::Put(key, value)));
+    }
+
+    /// Add a merge operand for a key of a column family.
+    pub fn merge_cf(&mut self, cf: &ColumnFamily, key: Key, operand: Value) {
+        self.ops.push((cf.id(), BatchOp::Merge(key, operand)));
+    }
+
+    /// Delete a key of a column family.
+    pub fn delete_cf(&mut self, cf: &ColumnFamily, key: Key) {
+        self.ops.push((cf.id(), BatchOp::Delete(key)));
+    }
+
+    /// Delete all keys of a column family in `[start, end)`.
+    pub fn delete_range_cf(&mut self, cf: &ColumnFamily, start: Key, end: Key) {
+        self.ops.push((cf.id(), BatchOp::DeleteRange(start, end)));
+    }
+
+    /// Number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    pub(crate) fn ops(&self) -> &[(u32, BatchOp)] {
+        &self.ops
+    }
+
+    /// Writes to the default column family.
+    fn default_ops(&self) -> impl DoubleEndedIterator<Item = &BatchOp> {
+        self.ops.iter().filter(|(cf_id, _)| *cf_id == DEFAULT_COLUMN_FAMILY_ID).map(|(_, op)| op)
+    }
+
+    /// Add the batch's writes to `key` of the default column family to `fold`,
+    /// newest first. Returns true once they determine the value, so the stored
+    /// versions need not be read. Range deletes are matched in `comparator` order.
+    pub(crate) fn fold_writes(&self, key: &Key, fold: &mut MergeFold<'_>, comparator: &dyn Comparator) -> bool {
+        for op in self.default_ops().rev() {
+            match op {
+                BatchOp::Put(k, value) if k == key => return fold.add(Entry::put(key.clone(), 0, value.clone())),
+                BatchOp::Merge(k, operand) if k == key => {
+                    fold.add(Entry::merge(key.clone(), 0, operand.clone()));
+                }
+                BatchOp::Delete(k) if k == key => {
+                    fold.add_deleted();
+                    return true;
+                }
+                BatchOp::DeleteRange(start, end)
+                    if compare_keys(comparator, start, key).is_le() && compare_keys(comparator, key, end).is_lt() =>
+                {
+                    fold.add_deleted();
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Keys of the default column family written by puts, merges and deletes.
+    pub(crate) fn written_keys(&self) -> impl Iterator<Item = &Key> {
+        self.default_ops().filter_map(|op| match op {
+            BatchOp::Put(key, _) | BatchOp::Merge(key, _) | BatchOp::Delete(key) => Some(key),
+            BatchOp::DeleteRange(..) => None,
+        })
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<(u32, BatchOp)> {
+        self.ops
+    }
+}
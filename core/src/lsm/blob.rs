@@ -0,0 +1,250 @@
+//! Blob files for key-value separation.
+//!
+//! Values above `LsmConfig::blob_value_threshold` are moved out of SSTables at
+//! flush time into append-only blob files. The SSTable keeps a small `BlobRef`
+//! instead, so compaction and table rewrites only move keys and references.
+//!
+//! Blob file format: a sequence of records, each
+//! - value: [u8; len]
+//! - checksum: u32 (crc32 of the value)
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+
+use crate::error::Corruption;
+use crate::tuple::varint::{decode_varint, encode_varint};
+
+use super::types::Value;
+
+const BLOB_FILE_EXTENSION: &str = "blob";
+const BLOB_CHECKSUM_SIZE: u64 = 4;
+
+/// Location of a value stored in a blob file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlobRef {
+    pub file_id: u64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl BlobRef {
+    /// Encode the reference as the value bytes of a blob index entry.
+    pub fn encode(&self) -> Value {
+        let mut bytes = Vec::with_capacity(16);
+        encode_varint(self.file_id, &mut bytes).expect("Vec write cannot fail");
+        encode_varint(self.offset, &mut bytes).expect("Vec write cannot fail");
+        encode_varint(self.len, &mut bytes).expect("Vec write cannot fail");
+        Value::new(bytes)
+    }
+
+    /// Decode a reference written by `encode`.
+    pub fn decode(value: &Value) -> Result<Self, std::io::Error> {
+        let data = value.as_bytes();
+        let (file_id, n1) = decode_varint(data)?;
+        let (offset, n2) = decode_varint(&data[n1..])?;
+        let (len, _) = decode_varint(&data[n1 + n2..])?;
+        Ok(Self { file_id, offset, len })
+    }
+
+    /// Bytes this value occupies in its blob file, including the checksum.
+    pub fn record_size(&self) -> u64 {
+        self.len + BLOB_CHECKSUM_SIZE
+    }
+}
+
+/// Appends values to a new blob file.
+pub struct BlobFileWriter {
+    file_id: u64,
+    writer: BufWriter<File>,
+    offset: u64,
+}
+
+impl BlobFileWriter {
+    /// Append a value and return its location.
+    pub fn append(&mut self, value: &[u8]) -> Result<BlobRef, std::io::Error> {
+        let blob_ref = BlobRef {
+            file_id: self.file_id,
+            offset: self.offset,
+            len: value.len() as u64,
+        };
+        self.writer.write_all(value)?;
+        self.writer.write_all(&crc32fast::hash(value).to_le_bytes())?;
+        self.offset += blob_ref.record_size();
+        Ok(blob_ref)
+    }
+
+    /// Flush and sync the file. Must be called before any reference to it is persisted.
+    pub fn finish(mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()
+    }
+}
+
+/// The set of blob files of one LSM tree.
+pub struct BlobStore {
+    dir: PathBuf,
+    next_file_id: AtomicU64,
+    /// Open read handles, by file id.
+    files: DashMap<u64, File>,
+}
+
+impl BlobStore {
+    /// Open the blob directory, creating it if needed.
+    pub fn open(dir: PathBuf) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&dir)?;
+        let store = Self {
+            dir,
+            next_file_id: AtomicU64::new(1),
+            files: DashMap::new(),
+        };
+        let max_id = store.file_ids()?.into_iter().max().unwrap_or(0);
+        store.next_file_id.store(max_id + 1, Ordering::SeqCst);
+        Ok(store)
+    }
+
+    pub(crate) fn file_path(&self, file_id: u64) -> PathBuf {
+        self.dir.join(format!("{:010}.{}", file_id, BLOB_FILE_EXTENSION))
+    }
+
+    /// Ids of all blob files on disk.
+    pub fn file_ids(&self) -> Result<Vec<u64>, std::io::Error> {
+        let mut ids = Vec::new();
+        for dir_entry in std::fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(BLOB_FILE_EXTENSION) {
+                continue;
+            }
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Size of a blob file in bytes.
+    pub fn file_size(&self, file_id: u64) -> Result<u64, std::io::Error> {
+        Ok(std::fs::metadata(self.file_path(file_id))?.len())
+    }
+
+    /// Start a new blob file.
+    pub fn new_file(&self) -> Result<BlobFileWriter, std::io::Error> {
+        let file_id = self.next_file_id.fetch_add(1, Ordering::SeqCst);
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(self.file_path(file_id))?;
+        Ok(BlobFileWriter {
+            file_id,
+            writer: BufWriter::new(file),
+            offset: 0,
+        })
+    }
+
+    /// Read the value a reference points to, verifying its checksum.
+    pub fn read(&self, blob_ref: &BlobRef) -> Result<Value, std::io::Error> {
+        let file = match self.files.get(&blob_ref.file_id) {
+            Some(file) => file,
+            None => {
+                let file = File::open(self.file_path(blob_ref.file_id))?;
+                self.files.entry(blob_ref.file_id).or_insert(file).downgrade()
+            }
+        };
+
+        let mut buffer = vec![0u8; blob_ref.record_size() as usize];
+        file.read_exact_at(&mut buffer, blob_ref.offset)?;
+        let checksum_start = blob_ref.len as usize;
+        let stored = u32::from_le_bytes(buffer[checksum_start..].try_into().unwrap());
+        buffer.truncate(checksum_start);
+        if crc32fast::hash(&buffer) != stored {
+            return Err(Corruption::BlobChecksumMismatch {
+                file_id: blob_ref.file_id,
+                offset: blob_ref.offset,
+            }
+            .into());
+        }
+        Ok(Value::new(buffer))
+    }
+
+    /// Delete a blob file. Callers must ensure no live table references it.
+    pub fn delete_file(&self, file_id: u64) -> Result<(), std::io::Error> {
+        self.files.remove(&file_id);
+        match std::fs::remove_file(self.file_path(file_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Result of a blob garbage collection run.
+#[derive(Debug, Clone, Default)]
+pub struct BlobGcStats {
+    /// Blob files whose live values were copied to a new file.
+    pub files_rewritten: usize,
+    /// Blob files deleted (rewritten or without any live value).
+    pub files_deleted: usize,
+    /// Bytes freed on disk.
+    pub bytes_reclaimed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn get_temp_dir() -> PathBuf {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        PathBuf::from(format!("/tmp/thordb_blob_test_{}", since_epoch.as_nanos()))
+    }
+
+    #[test]
+    fn test_blob_write_and_read() {
+        let dir = get_temp_dir();
+        let store = BlobStore::open(dir.clone()).unwrap();
+
+        let mut writer = store.new_file().unwrap();
+        let r1 = writer.append(b"first value").unwrap();
+        let r2 = writer.append(&vec![7u8; 100_000]).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(store.read(&r1).unwrap().as_bytes(), b"first value");
+        assert_eq!(store.read(&r2).unwrap().as_bytes(), &vec![7u8; 100_000][..]);
+        assert_eq!(BlobRef::decode(&r2.encode()).unwrap(), r2);
+
+        // Reopening picks up the next file id
+        drop(store);
+        let store = BlobStore::open(dir.clone()).unwrap();
+        assert_eq!(store.file_ids().unwrap(), vec![r1.file_id]);
+        assert_eq!(store.new_file().unwrap().append(b"x").unwrap().file_id, r1.file_id + 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_blob_checksum_mismatch() {
+        let dir = get_temp_dir();
+        let store = BlobStore::open(dir.clone()).unwrap();
+
+        let mut writer = store.new_file().unwrap();
+        let blob_ref = writer.append(b"some value").unwrap();
+        writer.finish().unwrap();
+
+        let path = store.file_path(blob_ref.file_id);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[3] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let err = store.read(&blob_ref).unwrap_err();
+        assert!(matches!(
+            Corruption::from_io_error(&err),
+            Some(Corruption::BlobChecksumMismatch { .. })
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
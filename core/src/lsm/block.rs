@@ -0,0 +1,446 @@
+//! Data blocks - groups of sorted entries stored as one SSTable record.
+//!
+//! Block record format:
+//! - codec: u8 (`CompressionType`)
+//! - entry_count: varint
+//! - uncompressed_len: varint (length of the body before compression)
+//! - first_key_len: varint, first_key: [u8]
+//! - last_key_len: varint, last_key: [u8]
+//! - body: compressed with `codec`
+//!
+//! The keys in the header let readers skip a block without decompressing it.
+//!
+//! Body format (before compression):
+//! - entries, each: shared_len: varint, unshared_len: varint, key_suffix: [u8], entry body
+//! - restarts: [u32; num_restarts] (offsets of entries whose key is stored in full)
+//! - num_restarts: u32
+//!
+//! Keys are stored as the suffix that differs from the previous key. Every
+//! `restart_interval` entries the full key is stored, so lookups can binary
+//! search the restart points and only decode entries from the nearest one.
+
+use crate::tuple::varint::{decode_varint, encode_varint, varint_len};
+
+use super::comparator::{compare_keys, Comparator};
+use super::types::{Entry, Key, SeqNum};
+
+/// Default target size of a block before compression. Small enough that an
+/// uncompressed block usually fits in a single page cell.
+pub const DEFAULT_BLOCK_SIZE: usize = 3 * 1024;
+
+/// Default number of entries between restart points.
+pub const DEFAULT_RESTART_INTERVAL: usize = 16;
+
+/// Compression codec applied to block bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CompressionType {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl CompressionType {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Result<Self, std::io::Error> {
+        match byte {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Lz4),
+            2 => Ok(CompressionType::Zstd),
+            _ => Err(invalid_block("unknown compression codec")),
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Lz4 => Ok(lz4_flex::block::compress(data)),
+            // Level 0 selects zstd's default level
+            CompressionType::Zstd => zstd::bulk::compress(data, 0),
+        }
+    }
+
+    fn decompress(self, data: &[u8], uncompressed_len: usize) -> Result<Vec<u8>, std::io::Error> {
+        let body = match self {
+            CompressionType::None => data.to_vec(),
+            CompressionType::Lz4 => lz4_flex::block::decompress(data, uncompressed_len)
+                .map_err(|_| invalid_block("lz4 decompression failed"))?,
+            CompressionType::Zstd => zstd::bulk::decompress(data, uncompressed_len)?,
+        };
+        if body.len() != uncompressed_len {
+            return Err(invalid_block("uncompressed length mismatch"));
+        }
+        Ok(body)
+    }
+}
+
+fn invalid_block(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid block: {}", reason))
+}
+
+/// Header of an encoded block.
+#[derive(Clone, Debug)]
+pub struct BlockHeader {
+    pub compression: CompressionType,
+    pub entry_count: u64,
+    pub uncompressed_len: usize,
+    pub first_key: Key,
+    pub last_key: Key,
+}
+
+impl BlockHeader {
+    /// Decode the header from the leading bytes of a block record.
+    /// Returns the header and its encoded length, or None if `data` is too short.
+    pub fn peek(data: &[u8]) -> Option<(Self, usize)> {
+        let compression = CompressionType::from_byte(*data.first()?).ok()?;
+        let mut offset = 1;
+        let (entry_count, n) = decode_varint(data.get(offset..)?).ok()?;
+        offset += n;
+        let (uncompressed_len, n) = decode_varint(data.get(offset..)?).ok()?;
+        offset += n;
+        let first_key = Entry::peek_key(data.get(offset..)?)?;
+        offset += encoded_key_len(&first_key);
+        let last_key = Entry::peek_key(data.get(offset..)?)?;
+        offset += encoded_key_len(&last_key);
+        Some((
+            Self {
+                compression,
+                entry_count,
+                uncompressed_len: uncompressed_len as usize,
+                first_key,
+                last_key,
+            },
+            offset,
+        ))
+    }
+}
+
+fn encoded_key_len(key: &Key) -> usize {
+    varint_len(key.len() as u64) + key.len()
+}
+
+fn write_key(key: &Key, out: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    encode_varint(key.len() as u64, out)?;
+    out.extend_from_slice(key.as_bytes());
+    Ok(())
+}
+
+/// Accumulates sorted entries into a block.
+pub struct BlockBuilder {
+    body: Vec<u8>,
+    restart_interval: usize,
+    restarts: Vec<u32>,
+    entry_count: u64,
+    first_key: Option<Key>,
+    last_key: Option<Key>,
+    max_seq: SeqNum,
+}
+
+impl BlockBuilder {
+    pub fn new(restart_interval: usize) -> Self {
+        Self {
+            body: Vec::new(),
+            restart_interval: restart_interval.max(1),
+            restarts: Vec::new(),
+            entry_count: 0,
+            first_key: None,
+            last_key: None,
+            max_seq: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    /// Size of the body so far, including the restart array, before compression.
+    pub fn size(&self) -> usize {
+        self.body.len() + (self.restarts.len() + 1) * 4
+    }
+
+    /// Highest sequence number in the block.
+    pub fn max_seq(&self) -> SeqNum {
+        self.max_seq
+    }
+
+    /// Append an entry. Entries must be added in sorted order.
+    pub fn add(&mut self, entry: &Entry) -> Result<(), std::io::Error> {
+        let key = entry.key.as_bytes();
+        let shared = if self.entry_count.is_multiple_of(self.restart_interval as u64) {
+            self.restarts.push(self.body.len() as u32);
+            0
+        } else {
+            let last_key = self.last_key.as_ref().map_or(&[][..], |k| k.as_bytes());
+            key.iter().zip(last_key).take_while(|(a, b)| a == b).count()
+        };
+
+        encode_varint(shared as u64, &mut self.body)?;
+        encode_varint((key.len() - shared) as u64, &mut self.body)?;
+        self.body.extend_from_slice(&key[shared..]);
+        entry.write_body_to(&mut self.body)?;
+
+        self.entry_count += 1;
+        if self.first_key.is_none() {
+            self.first_key = Some(entry.key.clone());
+        }
+        self.last_key = Some(entry.key.clone());
+        self.max_seq = self.max_seq.max(entry.seq_num);
+        Ok(())
+    }
+
+    /// Encode the block, compressing the body with `compression`.
+    /// Falls back to an uncompressed body when compression does not save space.
+    /// Returns the encoded record and the stored body length.
+    pub fn finish(mut self, compression: CompressionType) -> Result<(Vec<u8>, usize), std::io::Error> {
+        for restart in &self.restarts {
+            self.body.extend_from_slice(&restart.to_le_bytes());
+        }
+        self.body.extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+
+        let body_len = self.body.len();
+        let (compression, stored_body) = match compression.compress(&self.body)? {
+            compressed if compressed.len() < body_len => (compression, compressed),
+            _ => (CompressionType::None, self.body),
+        };
+
+        let empty = Key::new(vec![]);
+        let mut record = Vec::with_capacity(stored_body.len() + 32);
+        record.push(compression.to_byte());
+        encode_varint(self.entry_count, &mut record)?;
+        encode_varint(body_len as u64, &mut record)?;
+        write_key(self.first_key.as_ref().unwrap_or(&empty), &mut record)?;
+        write_key(self.last_key.as_ref().unwrap_or(&empty), &mut record)?;
+        record.extend_from_slice(&stored_body);
+        Ok((record, stored_body.len()))
+    }
+}
+
+impl Default for BlockBuilder {
+    fn default() -> Self {
+        Self::new(DEFAULT_RESTART_INTERVAL)
+    }
+}
+
+/// A decoded (decompressed) block.
+pub struct Block {
+    body: Vec<u8>,
+    /// End of the entries, where the restart array begins.
+    entries_end: usize,
+    restarts: Vec<u32>,
+    entry_count: u64,
+}
+
+impl Block {
+    /// Decompress a block record and parse its restart array.
+    pub fn decode(record: &[u8]) -> Result<Self, std::io::Error> {
+        let (header, header_len) = BlockHeader::peek(record).ok_or_else(|| invalid_block("truncated header"))?;
+        let body = header
+            .compression
+            .decompress(&record[header_len..], header.uncompressed_len)?;
+
+        let read_u32 = |offset: usize| -> Result<u32, std::io::Error> {
+            body.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(|| invalid_block("truncated restart array"))
+        };
+        let count_offset = body.len().checked_sub(4).ok_or_else(|| invalid_block("truncated restart array"))?;
+        let num_restarts = read_u32(count_offset)? as usize;
+        let entries_end = num_restarts
+            .checked_mul(4)
+            .and_then(|len| count_offset.checked_sub(len))
+            .ok_or_else(|| invalid_block("truncated restart array"))?;
+        let restarts = (0..num_restarts)
+            .map(|i| read_u32(entries_end + i * 4))
+            .collect::<Result<Vec<_>, _>>()?;
+        if restarts.iter().any(|&restart| restart as usize >= entries_end) {
+            return Err(invalid_block("restart point out of bounds"));
+        }
+
+        Ok(Self {
+            body,
+            entries_end,
+            restarts,
+            entry_count: header.entry_count,
+        })
+    }
+
+    /// Iterate over all entries of the block.
+    pub fn iter(&self) -> BlockIter<'_> {
+        BlockIter {
+            block: self,
+            offset: 0,
+            key: Vec::new(),
+        }
+    }
+
+    /// All entries for `key` in a block sorted by `comparator`, newest first.
+    pub fn get(&self, key: &Key, comparator: &dyn Comparator) -> Result<Vec<Entry>, std::io::Error> {
+        // Find the last restart point whose key is below `key`; the first entry
+        // for `key`, if any, follows it.
+        let mut left = 0;
+        let mut right = self.restarts.len();
+        while left < right {
+            let mid = left + (right - left) / 2;
+            if comparator.compare(&self.restart_key(mid)?, key.as_bytes()).is_lt() {
+                left = mid + 1;
+            } else {
+                right = mid;
+            }
+        }
+        let start = self.restarts.get(left.saturating_sub(1)).copied().unwrap_or(0) as usize;
+
+        let mut iter = BlockIter {
+            block: self,
+            offset: start,
+            key: Vec::new(),
+        };
+        let mut results = Vec::new();
+        for entry in &mut iter {
+            let entry = entry?;
+            match compare_keys(comparator, &entry.key, key) {
+                std::cmp::Ordering::Less => continue,
+                std::cmp::Ordering::Equal => results.push(entry),
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        Ok(results)
+    }
+
+    /// Full key stored at restart point `index`.
+    fn restart_key(&self, index: usize) -> Result<Vec<u8>, std::io::Error> {
+        let offset = self.restarts[index] as usize;
+        let (shared, n1) = decode_varint(&self.body[offset..self.entries_end])?;
+        let (unshared, n2) = decode_varint(&self.body[offset + n1..self.entries_end])?;
+        let start = offset + n1 + n2;
+        match (shared, self.body.get(start..start + unshared as usize)) {
+            (0, Some(key)) => Ok(key.to_vec()),
+            _ => Err(invalid_block("bad restart point")),
+        }
+    }
+}
+
+/// Iterator over the entries of a block, reconstructing prefix-compressed keys.
+pub struct BlockIter<'a> {
+    block: &'a Block,
+    offset: usize,
+    key: Vec<u8>,
+}
+
+impl BlockIter<'_> {
+    fn read_entry(&mut self) -> Result<Entry, std::io::Error> {
+        let data = &self.block.body[self.offset..self.block.entries_end];
+        let (shared, n1) = decode_varint(data)?;
+        let (unshared, n2) = decode_varint(&data[n1..])?;
+        let suffix_start = n1 + n2;
+        let suffix = data
+            .get(suffix_start..suffix_start + unshared as usize)
+            .ok_or_else(|| invalid_block("truncated key"))?;
+        if shared as usize > self.key.len() {
+            return Err(invalid_block("shared prefix longer than previous key"));
+        }
+        self.key.truncate(shared as usize);
+        self.key.extend_from_slice(suffix);
+
+        let body_start = suffix_start + unshared as usize;
+        let (entry, body_len) = Entry::read_body(Key::from_slice(&self.key), &data[body_start..])?;
+        self.offset += body_start + body_len;
+        Ok(entry)
+    }
+}
+
+impl Iterator for BlockIter<'_> {
+    type Item = Result<Entry, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.block.entries_end {
+            return None;
+        }
+        let result = self.read_entry();
+        if result.is_err() {
+            // Stop after a decoding error
+            self.offset = self.block.entries_end;
+        }
+        Some(result)
+    }
+}
+
+/// Decode all entries of a block record.
+pub fn decode_block(record: &[u8]) -> Result<Vec<Entry>, std::io::Error> {
+    let block = Block::decode(record)?;
+    let entries = block.iter().collect::<Result<Vec<_>, _>>()?;
+    if entries.len() as u64 != block.entry_count {
+        return Err(invalid_block("entry count mismatch"));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::types::Value;
+    use super::super::comparator::BytewiseComparator;
+
+    #[test]
+    fn test_block_roundtrip_all_codecs() {
+        for compression in [CompressionType::None, CompressionType::Lz4, CompressionType::Zstd] {
+            let mut builder = BlockBuilder::default();
+            for i in 0..50u64 {
+                let key = Key::from(format!("key{:04}", i).as_str());
+                builder.add(&Entry::put(key, i, Value::from("a fairly repetitive value"))).unwrap();
+            }
+            builder.add(&Entry::delete(Key::from("key9999"), 99)).unwrap();
+            let uncompressed = builder.size();
+            let (record, stored) = builder.finish(compression).unwrap();
+
+            let (header, _) = BlockHeader::peek(&record).unwrap();
+            assert_eq!(header.compression, compression);
+            assert_eq!(header.first_key, Key::from("key0000"));
+            assert_eq!(header.last_key, Key::from("key9999"));
+            if compression != CompressionType::None {
+                assert!(stored < uncompressed);
+            }
+
+            let entries = decode_block(&record).unwrap();
+            assert_eq!(entries.len(), 51);
+            assert_eq!(entries[10].value, Some(Value::from("a fairly repetitive value")));
+            assert!(entries[50].is_tombstone());
+        }
+    }
+
+    #[test]
+    fn test_block_prefix_compression_and_restarts() {
+        let mut builder = BlockBuilder::new(4);
+        let mut full_size = 0;
+        for i in 0..100u64 {
+            let key = Key::from(format!("users/0000{:04}/profile", i / 2).as_str());
+            let entry = Entry::put(key, 1000 - i, Value::from("v"));
+            full_size += entry.serialized_size();
+            builder.add(&entry).unwrap();
+        }
+        // Shared prefixes are stored once per run, not once per entry
+        assert!(builder.size() < full_size * 2 / 3);
+        let (record, _) = builder.finish(CompressionType::None).unwrap();
+
+        let block = Block::decode(&record).unwrap();
+        assert_eq!(block.restarts.len(), 25);
+        let entries: Vec<Entry> = block.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 100);
+        assert_eq!(entries[51].key, Key::from("users/00000025/profile"));
+
+        for i in [0u64, 1, 17, 25, 49] {
+            let found = block.get(&Key::from(format!("users/0000{:04}/profile", i).as_str()), &BytewiseComparator).unwrap();
+            assert_eq!(found.len(), 2);
+            assert_eq!(found[0].seq_num, 1000 - 2 * i);
+            assert_eq!(found[1].seq_num, 1000 - 2 * i - 1);
+        }
+        assert!(block.get(&Key::from("users/00000050/profile"), &BytewiseComparator).unwrap().is_empty());
+        assert!(block.get(&Key::from("a"), &BytewiseComparator).unwrap().is_empty());
+    }
+}
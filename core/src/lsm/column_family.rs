@@ -0,0 +1,75 @@
+//! Column families - independent key spaces within one `LsmTree`.
+//!
+//! Every column family has its own memtable, SSTables and `LsmConfig` knobs
+//! (memtable threshold, compaction, compression), while all of them share the
+//! tree's WAL, buffer pool, blob files and manifest. Sequence numbers come from
+//! one counter, so a `WriteBatch` spanning families is applied atomically and
+//! snapshots cover every family.
+//!
+//! The WAL is only rotated once every memtable is flushed. Until then, each
+//! family's manifest entry records the last sequence number it flushed, and
+//! recovery skips the WAL records at or below it.
+//!
+//! Readers see a family's tables through its current `SuperVersion`.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, RwLock};
+
+use super::lsm::LsmConfig;
+use super::memtable::MemTable;
+use super::types::SeqNum;
+use super::version::SuperVersion;
+
+/// Name of the column family every tree has, used by the methods without a
+/// column family argument.
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+/// Id of the default column family.
+pub(crate) const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+/// A column family of an `LsmTree`. Handles are obtained from
+/// `LsmTree::create_column_family` or `LsmTree::column_family`.
+pub struct ColumnFamily {
+    pub(crate) id: u32,
+    pub(crate) name: String,
+    /// Knobs of this family. The data directory and I/O options of the tree apply.
+    pub(crate) config: LsmConfig,
+    /// Active memtable for writes.
+    pub(crate) memtable: RwLock<MemTable>,
+    /// Sealed memtables and SSTables, replaced as a whole by flush and compaction.
+    super_version: RwLock<Arc<SuperVersion>>,
+    /// Last sequence number written to an SSTable; WAL records up to it are not replayed.
+    pub(crate) flushed_seq: AtomicU64,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: String, config: LsmConfig, memtable: MemTable, flushed_seq: SeqNum) -> Self {
+        Self {
+            id,
+            name,
+            config,
+            memtable: RwLock::new(memtable),
+            super_version: RwLock::default(),
+            flushed_seq: AtomicU64::new(flushed_seq),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The current super version. Readers must lock the active memtable first.
+    pub(crate) fn super_version(&self) -> Arc<SuperVersion> {
+        self.super_version.read().unwrap().clone()
+    }
+
+    /// Replace the current super version with one derived from it.
+    pub(crate) fn edit_super_version(&self, edit: impl FnOnce(&SuperVersion) -> SuperVersion) {
+        let mut current = self.super_version.write().unwrap();
+        *current = Arc::new(edit(&current));
+    }
+}
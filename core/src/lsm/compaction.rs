@@ -0,0 +1,264 @@
+//! Compaction - merging SSTables to reclaim space.
+//!
+//! Flushes write tables to level 0. Compaction merges every table into a single
+//! table on level 1, the bottommost level. Versions of a key are kept as the
+//! retention policy allows (duplicate key support), except versions deleted by a
+//! newer tombstone:
+//! - entries covered by a range tombstone are dropped
+//! - versions shadowed by a point tombstone are dropped, and at the bottommost
+//!   level the tombstone itself as well
+//! - expired entries become tombstones, so they shadow older versions the same way
+//!
+//! A version stays as long as a snapshot can see it, i.e. a snapshot lies
+//! between the version and the tombstone deleting it. Tombstones are kept
+//! while they are needed to hide such versions from newer reads.
+//!
+//! A `CompactionFilter` then lets the application drop or rewrite the remaining
+//! values. Versions a snapshot can see are not passed to the filter.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use super::sstable::SSTableIterator;
+use super::types::{Entry, Key, SeqNum, Value};
+
+/// Level written by memtable flushes.
+pub const FLUSH_LEVEL: usize = 0;
+
+/// Level written by compaction.
+pub const BOTTOMMOST_LEVEL: usize = 1;
+
+/// Result of a compaction run.
+#[derive(Debug, Clone, Default)]
+pub struct CompactionStats {
+    /// Number of tables merged.
+    pub input_tables: usize,
+    /// Entries written to the output table.
+    pub entries_written: u64,
+    /// Entries dropped because a range tombstone deletes them.
+    pub entries_dropped: u64,
+    /// Point tombstones and the versions they shadow, dropped.
+    pub entries_reclaimed: u64,
+    /// Entries whose TTL had passed, turned into tombstones.
+    pub entries_expired: u64,
+    /// Merge operands folded into a value or another operand.
+    pub operands_merged: u64,
+    /// Historic versions dropped by the retention policy.
+    pub versions_pruned: u64,
+    /// Entries removed by the compaction filter.
+    pub entries_filtered: u64,
+    /// Values changed by the compaction filter.
+    pub values_changed: u64,
+}
+
+/// What a `CompactionFilter` does with an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionDecision {
+    /// Write the entry unchanged.
+    Keep,
+    /// Drop the entry. Older versions of the key, if kept, become visible.
+    Remove,
+    /// Write the entry with a new value.
+    ChangeValue(Value),
+}
+
+/// Application logic deciding which entries compaction keeps.
+pub trait CompactionFilter: Send + Sync {
+    /// Name of the filter, for diagnostics.
+    fn name(&self) -> &str;
+
+    /// Decide on `entry`, a value or merge operand written to `level`.
+    /// Separated values are read from their blob file first. Tombstones are not
+    /// passed to the filter.
+    fn filter(&self, level: usize, bottommost: bool, entry: &Entry) -> CompactionDecision;
+}
+
+/// First error hit by any `TableSource`, checked once the merge is done.
+pub(crate) type SourceError = Rc<RefCell<Option<std::io::Error>>>;
+
+/// Adapts an SSTable iterator to the infallible `MergeIterator` input. The
+/// first read error ends the source and is stored in `error`.
+pub(crate) struct TableSource {
+    inner: SSTableIterator,
+    error: SourceError,
+}
+
+impl TableSource {
+    pub(crate) fn new(inner: SSTableIterator, error: SourceError) -> Self {
+        Self { inner, error }
+    }
+}
+
+impl Iterator for TableSource {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        match self.inner.next()? {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                self.error.borrow_mut().get_or_insert(e);
+                None
+            }
+        }
+    }
+}
+
+/// Whether a snapshot in `snapshots` (ascending) can see a version written at
+/// `seq_num` that was deleted at `deleted_at`.
+pub(crate) fn snapshot_between(snapshots: &[SeqNum], seq_num: SeqNum, deleted_at: SeqNum) -> bool {
+    let idx = snapshots.partition_point(|&s| s < seq_num);
+    snapshots.get(idx).is_some_and(|&s| s < deleted_at)
+}
+
+/// Range tombstones that must be carried into a bottommost output, because a
+/// snapshot older than them may still see entries they delete.
+pub(crate) fn retained_range_tombstones(
+    range_tombstones: &FragmentedRangeTombstones,
+    snapshots: &[SeqNum],
+) -> Vec<RangeTombstone> {
+    range_tombstones
+        .tombstones()
+        .iter()
+        .filter(|t| snapshots.first().is_some_and(|&s| s < t.seq_num))
+        .cloned()
+        .collect()
+}
+
+/// Filters the merged input of a compaction, dropping entries deleted by
+/// tombstones that no snapshot needs.
+pub(crate) struct CompactionIterator<I> {
+    inner: I,
+    range_tombstones: FragmentedRangeTombstones,
+    /// Live snapshot sequence numbers, ascending.
+    snapshots: Vec<SeqNum>,
+    bottommost: bool,
+    /// Seconds since the Unix epoch, for TTL expiry.
+    now: u64,
+    current_key: Option<Key>,
+    /// Newest point tombstone seen for the current key.
+    shadowing_tombstone: Option<SeqNum>,
+    entries_dropped: u64,
+    entries_reclaimed: u64,
+    entries_expired: u64,
+}
+
+impl<I> CompactionIterator<I>
+where
+    I: Iterator<Item = Entry>,
+{
+    pub(crate) fn new(
+        inner: I,
+        range_tombstones: FragmentedRangeTombstones,
+        snapshots: Vec<SeqNum>,
+        bottommost: bool,
+        now: u64,
+    ) -> Self {
+        Self {
+            inner,
+            range_tombstones,
+            snapshots,
+            bottommost,
+            now,
+            current_key: None,
+            shadowing_tombstone: None,
+            entries_dropped: 0,
+            entries_reclaimed: 0,
+            entries_expired: 0,
+        }
+    }
+
+    pub(crate) fn entries_dropped(&self) -> u64 {
+        self.entries_dropped
+    }
+
+    pub(crate) fn entries_reclaimed(&self) -> u64 {
+        self.entries_reclaimed
+    }
+
+    pub(crate) fn entries_expired(&self) -> u64 {
+        self.entries_expired
+    }
+
+    fn is_range_deleted(&self, entry: &Entry) -> bool {
+        self.range_tombstones
+            .covering_seqs(&entry.key)
+            .iter()
+            .any(|&t| t > entry.seq_num && !snapshot_between(&self.snapshots, entry.seq_num, t))
+    }
+}
+
+impl<I> Iterator for CompactionIterator<I>
+where
+    I: Iterator<Item = Entry>,
+{
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            let mut entry = self.inner.next()?;
+            if entry.is_expired(self.now) {
+                entry = Entry::delete(entry.key, entry.seq_num);
+                self.entries_expired += 1;
+            }
+            if self.current_key.as_ref() != Some(&entry.key) {
+                self.current_key = Some(entry.key.clone());
+                self.shadowing_tombstone = None;
+            }
+
+            if self.is_range_deleted(&entry) {
+                self.entries_dropped += 1;
+                continue;
+            }
+            // Versions come newest first, so a tombstone shadows everything after it
+            if let Some(tombstone_seq) = self.shadowing_tombstone
+                && !snapshot_between(&self.snapshots, entry.seq_num, tombstone_seq)
+            {
+                self.entries_reclaimed += 1;
+                continue;
+            }
+            if entry.is_tombstone() {
+                self.shadowing_tombstone = Some(entry.seq_num);
+                // Nothing older is left for a bottommost tombstone to hide, unless
+                // a snapshot keeps an older version
+                if self.bottommost && self.snapshots.first().is_none_or(|&s| s >= entry.seq_num) {
+                    self.entries_reclaimed += 1;
+                    continue;
+                }
+            }
+            return Some(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::types::Value;
+
+    fn run(entries: Vec<Entry>, snapshots: Vec<SeqNum>) -> (Vec<SeqNum>, u64) {
+        let mut iter =
+            CompactionIterator::new(entries.into_iter(), FragmentedRangeTombstones::default(), snapshots, true, 0);
+        let kept = iter.by_ref().map(|e| e.seq_num).collect();
+        (kept, iter.entries_reclaimed())
+    }
+
+    #[test]
+    fn test_tombstone_gc_respects_snapshots() {
+        let key = Key::from("k");
+        let entries = vec![
+            Entry::put(key.clone(), 40, Value::from("v4")),
+            Entry::delete(key.clone(), 30),
+            Entry::put(key.clone(), 20, Value::from("v2")),
+            Entry::put(key.clone(), 10, Value::from("v1")),
+            Entry::delete(Key::from("other"), 5),
+        ];
+
+        // No snapshots: the tombstones and everything they shadow go away
+        assert_eq!(run(entries.clone(), vec![]), (vec![40], 4));
+        // A snapshot at 25 still sees version 20 (and so 10), so the tombstone stays
+        assert_eq!(run(entries.clone(), vec![25]), (vec![40, 30, 20, 10], 1));
+        // A snapshot newer than the tombstone does not need anything below it
+        assert_eq!(run(entries, vec![35]), (vec![40], 4));
+    }
+}
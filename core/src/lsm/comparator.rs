@@ -0,0 +1,104 @@
+//! Key comparators - the order keys are stored and scanned in.
+//!
+//! Memtables, SSTable lookups, merging iterators and range tombstones all order
+//! keys with the comparator of their column family. The comparator's name is
+//! recorded in the manifest, and opening a column family with a comparator of
+//! another name fails, since its tables would be misread.
+
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use super::types::Key;
+
+/// A total order of keys. Keys comparing `Equal` must be byte-wise equal.
+pub trait Comparator: Send + Sync {
+    /// Name of the order, recorded in the manifest.
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+/// Lexicographic order of the key bytes, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "thordb.BytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Reverse lexicographic order of the key bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "thordb.ReverseBytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+pub(crate) fn bytewise() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
+pub(crate) fn compare_keys(comparator: &dyn Comparator, a: &Key, b: &Key) -> Ordering {
+    comparator.compare(a.as_bytes(), b.as_bytes())
+}
+
+/// Whether `key` lies in `range` under `comparator`.
+pub(crate) fn range_contains(comparator: &dyn Comparator, range: &impl RangeBounds<Key>, key: &Key) -> bool {
+    let after_start = match range.start_bound() {
+        Bound::Included(start) => compare_keys(comparator, start, key).is_le(),
+        Bound::Excluded(start) => compare_keys(comparator, start, key).is_lt(),
+        Bound::Unbounded => true,
+    };
+    let before_end = match range.end_bound() {
+        Bound::Included(end) => compare_keys(comparator, key, end).is_le(),
+        Bound::Excluded(end) => compare_keys(comparator, key, end).is_lt(),
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+/// A key ordered by a comparator, for sorted collections.
+#[derive(Clone)]
+pub(crate) struct OrderedKey {
+    pub(crate) key: Key,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl OrderedKey {
+    pub(crate) fn new(key: Key, comparator: Arc<dyn Comparator>) -> Self {
+        Self { key, comparator }
+    }
+}
+
+impl PartialEq for OrderedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for OrderedKey {}
+
+impl Ord for OrderedKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_keys(self.comparator.as_ref(), &self.key, &other.key)
+    }
+}
+
+impl PartialOrd for OrderedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
@@ -0,0 +1,125 @@
+//! External SSTables - tables written outside a live tree, for bulk loading.
+//!
+//! `ExternalSstWriter` writes sorted entries to a standalone file in the SSTable
+//! format, bypassing the WAL and memtable. `LsmTree::ingest_external_files` then
+//! copies such files into a tree's table set, giving all their entries one new
+//! sequence number.
+//!
+//! The table is built in a private buffer pool under `<path>.build`, and `finish`
+//! moves it to the requested path.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::bufferpool::BufferPool;
+
+use super::comparator::{bytewise, compare_keys, Comparator};
+use super::sstable::{SSTableMeta, SSTableOptions, SSTableWriter};
+use super::types::{Entry, Key, Value};
+
+/// File id of the table inside the writer's private buffer pool.
+const EXTERNAL_FILE_ID: u64 = 1;
+
+/// Writes a standalone SSTable for `LsmTree::ingest_external_files`. Keys must be
+/// added in strictly increasing order; entries carry no sequence number until ingested.
+pub struct ExternalSstWriter {
+    path: PathBuf,
+    /// Directory of the private buffer pool, created by the writer.
+    build_dir: PathBuf,
+    buffer_pool: Arc<BufferPool>,
+    comparator: Arc<dyn Comparator>,
+    /// None once finished.
+    writer: Option<SSTableWriter>,
+    last_key: Option<Key>,
+    entry_count: u64,
+}
+
+impl ExternalSstWriter {
+    /// Start writing an external table at `path`, which must not exist yet, with
+    /// byte-wise ordered keys.
+    pub fn create(path: impl AsRef<Path>, options: SSTableOptions) -> Result<Self, std::io::Error> {
+        Self::with_comparator(path, options, bytewise())
+    }
+
+    /// Start writing an external table with keys in `comparator` order, for a
+    /// column family using that comparator. Neither `path` nor `<path>.build`
+    /// may exist yet.
+    pub fn with_comparator(
+        path: impl AsRef<Path>,
+        options: SSTableOptions,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self, std::io::Error> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("External table {} already exists", path.display()),
+            ));
+        }
+        let build_dir = PathBuf::from(format!("{}.build", path.display()));
+        std::fs::create_dir(&build_dir)?;
+        let opened = BufferPool::new(build_dir.to_string_lossy().to_string())
+            .map(Arc::new)
+            .and_then(|pool| Ok((pool.clone(), SSTableWriter::with_options(pool, EXTERNAL_FILE_ID, options)?)));
+        let (buffer_pool, writer) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&build_dir);
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            path,
+            build_dir,
+            buffer_pool,
+            comparator,
+            writer: Some(writer),
+            last_key: None,
+            entry_count: 0,
+        })
+    }
+
+    /// Add a key-value pair.
+    pub fn put(&mut self, key: Key, value: Value) -> Result<(), std::io::Error> {
+        self.add(Entry::put(key, 0, value))
+    }
+
+    /// Add a tombstone for a key.
+    pub fn delete(&mut self, key: Key) -> Result<(), std::io::Error> {
+        self.add(Entry::delete(key, 0))
+    }
+
+    fn add(&mut self, entry: Entry) -> Result<(), std::io::Error> {
+        if let Some(last_key) = &self.last_key
+            && compare_keys(self.comparator.as_ref(), last_key, &entry.key).is_ge()
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Keys must be added in strictly increasing order",
+            ));
+        }
+        let writer = self.writer.as_mut().ok_or_else(|| std::io::Error::other("External table is finished"))?;
+        writer.write_entry(&entry)?;
+        self.last_key = Some(entry.key);
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    /// Build the table at the writer's path and return its metadata.
+    pub fn finish(mut self) -> Result<SSTableMeta, std::io::Error> {
+        if self.entry_count == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "External table has no entries"));
+        }
+        let writer = self.writer.take().ok_or_else(|| std::io::Error::other("External table is finished"))?;
+        let meta = writer.finish()?;
+        self.buffer_pool.flush()?;
+        std::fs::rename(self.buffer_pool.page_file_path(EXTERNAL_FILE_ID), &self.path)?;
+        Ok(meta)
+    }
+}
+
+impl Drop for ExternalSstWriter {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.build_dir);
+    }
+}
@@ -4,13 +4,16 @@
 
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
+use std::sync::Arc;
 
+use super::comparator::{bytewise, compare_keys, Comparator};
 use super::types::Entry;
 
 /// A wrapper for entries that implements reverse ordering for the min-heap.
 struct HeapEntry {
     entry: Entry,
     source_idx: usize,
+    comparator: Arc<dyn Comparator>,
 }
 
 impl Eq for HeapEntry {}
@@ -25,7 +28,7 @@ impl Ord for HeapEntry {
     fn cmp(&self, other: &Self) -> Ordering {
         // Reverse ordering for min-heap behavior
         // First compare by key (ascending), then by seq_num (descending)
-        match other.entry.key.cmp(&self.entry.key) {
+        match compare_keys(self.comparator.as_ref(), &other.entry.key, &self.entry.key) {
             Ordering::Equal => self.entry.seq_num.cmp(&other.entry.seq_num),
             ord => ord,
         }
@@ -49,6 +52,7 @@ pub struct MergeIterator<I> {
     sources: Vec<I>,
     heap: BinaryHeap<HeapEntry>,
     initialized: bool,
+    comparator: Arc<dyn Comparator>,
 }
 
 impl<I> MergeIterator<I>
@@ -57,10 +61,16 @@ where
 {
     /// Create a new merge iterator from multiple sources.
     pub fn new(sources: Vec<I>) -> Self {
+        Self::with_comparator(sources, bytewise())
+    }
+
+    /// Create a merge iterator from sources sorted by `comparator`.
+    pub fn with_comparator(sources: Vec<I>, comparator: Arc<dyn Comparator>) -> Self {
         Self {
             sources,
             heap: BinaryHeap::new(),
             initialized: false,
+            comparator,
         }
     }
 
@@ -76,6 +86,7 @@ where
                 self.heap.push(HeapEntry {
                     entry,
                     source_idx: idx,
+                    comparator: self.comparator.clone(),
                 });
             }
         }
@@ -97,6 +108,7 @@ where
                 self.heap.push(HeapEntry {
                     entry: next_entry,
                     source_idx: heap_entry.source_idx,
+                    comparator: heap_entry.comparator.clone(),
                 });
             }
 
@@ -107,48 +119,6 @@ where
     }
 }
 
-/// Iterator adapter that filters out older versions of duplicate keys.
-/// Only returns the entry with the highest seq_num for each key.
-pub struct LatestVersionIterator<I> {
-    inner: I,
-    last_key: Option<super::types::Key>,
-}
-
-impl<I> LatestVersionIterator<I>
-where
-    I: Iterator<Item = Entry>,
-{
-    pub fn new(inner: I) -> Self {
-        Self {
-            inner,
-            last_key: None,
-        }
-    }
-}
-
-impl<I> Iterator for LatestVersionIterator<I>
-where
-    I: Iterator<Item = Entry>,
-{
-    type Item = Entry;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.inner.next() {
-                Some(entry) => {
-                    // Skip if same key as last (we already returned the newer version)
-                    if self.last_key.as_ref() == Some(&entry.key) {
-                        continue;
-                    }
-                    self.last_key = Some(entry.key.clone());
-                    return Some(entry);
-                }
-                None => return None,
-            }
-        }
-    }
-}
-
 /// Iterator adapter that filters out tombstones.
 pub struct LiveEntriesIterator<I> {
     inner: I,
@@ -220,22 +190,6 @@ mod tests {
         assert_eq!(merged[1].seq_num, 1);
     }
 
-    #[test]
-    fn test_latest_version_iterator() {
-        let entries = vec![
-            Entry::put(Key::from("a"), 2, Value::from("new")),
-            Entry::put(Key::from("a"), 1, Value::from("old")),
-            Entry::put(Key::from("b"), 3, Value::from("val")),
-        ];
-
-        let latest: Vec<_> = LatestVersionIterator::new(entries.into_iter()).collect();
-
-        assert_eq!(latest.len(), 2);
-        assert_eq!(latest[0].key.as_bytes(), b"a");
-        assert_eq!(latest[0].seq_num, 2);
-        assert_eq!(latest[1].key.as_bytes(), b"b");
-    }
-
     #[test]
     fn test_live_entries_iterator() {
         let entries = vec![
@@ -0,0 +1,225 @@
+//! Row lock manager for pessimistic transactions.
+//!
+//! Keys are locked shared or exclusive by transaction id. Locks are spread over
+//! stripes by key hash, each a mutex-guarded map with a condvar to wait on.
+//! A transaction that has to wait records the holders it waits for in a
+//! waits-for graph; if the holders (transitively) wait for it, waiting would
+//! deadlock and the request fails instead.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::transaction::TransactionError;
+use super::types::Key;
+
+pub(crate) type TxnId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+#[derive(Debug, Default)]
+struct KeyLock {
+    exclusive: Option<TxnId>,
+    shared: HashSet<TxnId>,
+}
+
+impl KeyLock {
+    /// Transactions that must release the key before `txn` can lock it in `mode`.
+    fn blockers(&self, txn: TxnId, mode: LockMode) -> Vec<TxnId> {
+        let mut blockers: Vec<TxnId> = self.exclusive.iter().copied().filter(|&t| t != txn).collect();
+        if mode == LockMode::Exclusive {
+            blockers.extend(self.shared.iter().copied().filter(|&t| t != txn));
+        }
+        blockers
+    }
+
+    fn grant(&mut self, txn: TxnId, mode: LockMode) {
+        match mode {
+            LockMode::Exclusive => {
+                self.exclusive = Some(txn);
+                self.shared.remove(&txn);
+            }
+            LockMode::Shared if self.exclusive != Some(txn) => {
+                self.shared.insert(txn);
+            }
+            LockMode::Shared => {}
+        }
+    }
+
+    fn release(&mut self, txn: TxnId) {
+        if self.exclusive == Some(txn) {
+            self.exclusive = None;
+        }
+        self.shared.remove(&txn);
+    }
+
+    fn is_free(&self) -> bool {
+        self.exclusive.is_none() && self.shared.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct Stripe {
+    locks: Mutex<HashMap<Key, KeyLock>>,
+    released: Condvar,
+}
+
+/// The lock a transaction waits for.
+struct Waiting {
+    key: Key,
+    /// Transactions holding the key in a conflicting mode.
+    blockers: Vec<TxnId>,
+}
+
+pub(crate) struct LockManager {
+    stripes: Vec<Stripe>,
+    /// Waiting transaction -> the lock it waits for.
+    waits_for: Mutex<HashMap<TxnId, Waiting>>,
+}
+
+impl LockManager {
+    pub(crate) fn new(num_stripes: usize) -> Self {
+        Self {
+            stripes: (0..num_stripes.max(1)).map(|_| Stripe::default()).collect(),
+            waits_for: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn stripe(&self, key: &Key) -> &Stripe {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.stripes[(hasher.finish() % self.stripes.len() as u64) as usize]
+    }
+
+    /// Lock `key` for `txn`, waiting up to `timeout` for other holders. Locking a
+    /// key exclusive that `txn` holds shared upgrades the lock.
+    pub(crate) fn lock(&self, txn: TxnId, key: &Key, mode: LockMode, timeout: Duration) -> Result<(), std::io::Error> {
+        let stripe = self.stripe(key);
+        let deadline = Instant::now() + timeout;
+        let mut locks = stripe.locks.lock().unwrap();
+        loop {
+            let lock = locks.entry(key.clone()).or_default();
+            let blockers = lock.blockers(txn, mode);
+            if blockers.is_empty() {
+                lock.grant(txn, mode);
+                self.waits_for.lock().unwrap().remove(&txn);
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            let err = if self.would_deadlock(txn, key, blockers) {
+                Some(TransactionError::Deadlock { key: key.clone() })
+            } else if now >= deadline {
+                Some(TransactionError::LockTimeout { key: key.clone() })
+            } else {
+                None
+            };
+            if let Some(err) = err {
+                self.waits_for.lock().unwrap().remove(&txn);
+                if lock.is_free() {
+                    locks.remove(key);
+                }
+                return Err(err.into());
+            }
+            locks = stripe.released.wait_timeout(locks, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Record that `txn` waits for `blockers` to release `key`. Returns true if one
+    /// of them already waits for `txn`, directly or through other transactions.
+    fn would_deadlock(&self, txn: TxnId, key: &Key, blockers: Vec<TxnId>) -> bool {
+        let mut waits_for = self.waits_for.lock().unwrap();
+        let mut stack = blockers.clone();
+        let mut visited = HashSet::new();
+        while let Some(t) = stack.pop() {
+            if t == txn {
+                return true;
+            }
+            if visited.insert(t)
+                && let Some(next) = waits_for.get(&t)
+            {
+                stack.extend(&next.blockers);
+            }
+        }
+        waits_for.insert(txn, Waiting { key: key.clone(), blockers });
+        false
+    }
+
+    /// Release the lock `txn` holds on `key`.
+    pub(crate) fn unlock(&self, txn: TxnId, key: &Key) {
+        let stripe = self.stripe(key);
+        let mut locks = stripe.locks.lock().unwrap();
+        if let Some(lock) = locks.get_mut(key) {
+            lock.release(txn);
+            if lock.is_free() {
+                locks.remove(key);
+            }
+        }
+        // Waiters for the key no longer wait for `txn`, even before they wake up
+        for waiting in self.waits_for.lock().unwrap().values_mut().filter(|w| &w.key == key) {
+            waiting.blockers.retain(|&t| t != txn);
+        }
+        stripe.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_lock_modes_and_deadlock() {
+        let manager = Arc::new(LockManager::new(4));
+        let (a, b) = (Key::from("a"), Key::from("b"));
+        let short = Duration::from_millis(20);
+
+        // Shared locks are compatible, exclusive ones wait for all holders
+        manager.lock(1, &a, LockMode::Shared, short).unwrap();
+        manager.lock(2, &a, LockMode::Shared, short).unwrap();
+        let err = manager.lock(2, &a, LockMode::Exclusive, short).unwrap_err();
+        assert!(matches!(TransactionError::from_io_error(&err), Some(TransactionError::LockTimeout { .. })));
+        manager.unlock(1, &a);
+        manager.lock(2, &a, LockMode::Exclusive, short).unwrap();
+
+        // 3 holds b and waits for a; 2 waiting for b would close the cycle
+        manager.lock(3, &b, LockMode::Exclusive, short).unwrap();
+        let waiter = {
+            let manager = manager.clone();
+            let a = a.clone();
+            thread::spawn(move || manager.lock(3, &a, LockMode::Exclusive, Duration::from_secs(10)))
+        };
+        while !manager.waits_for.lock().unwrap().contains_key(&3) {
+            thread::yield_now();
+        }
+        let err = manager.lock(2, &b, LockMode::Shared, Duration::from_secs(10)).unwrap_err();
+        assert!(matches!(TransactionError::from_io_error(&err), Some(TransactionError::Deadlock { .. })));
+
+        manager.unlock(2, &a);
+        waiter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_unlock_clears_waits_for() {
+        let manager = LockManager::new(4);
+        let (a, b) = (Key::from("a"), Key::from("b"));
+        let short = Duration::from_millis(20);
+
+        // 1 holds b and waits for 2 to release a, but hasn't woken up yet
+        manager.lock(1, &b, LockMode::Exclusive, short).unwrap();
+        manager.lock(2, &a, LockMode::Exclusive, short).unwrap();
+        assert!(!manager.would_deadlock(1, &a, vec![2]));
+        manager.unlock(2, &a);
+
+        // 2 now waits for 1, which waits for nobody: a timeout, not a deadlock
+        let err = manager.lock(2, &b, LockMode::Exclusive, short).unwrap_err();
+        assert!(matches!(TransactionError::from_io_error(&err), Some(TransactionError::LockTimeout { .. })));
+    }
+}
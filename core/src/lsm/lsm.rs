@@ -2,17 +2,38 @@
 //!
 //! Manages memtable lifecycle, SSTable creation, and read path.
 
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
 
 use crate::bufferpool::BufferPool;
 
-use super::iterator::{LatestVersionIterator, LiveEntriesIterator, MergeIterator};
+use super::batch::{BatchOp, WriteBatch};
+use super::blob::{BlobGcStats, BlobStore};
+use super::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY, DEFAULT_COLUMN_FAMILY_ID};
+use super::block::{CompressionType, DEFAULT_BLOCK_SIZE, DEFAULT_RESTART_INTERVAL};
+use super::comparator::{bytewise, compare_keys, range_contains, BytewiseComparator, Comparator};
+use super::compaction::{
+    retained_range_tombstones, snapshot_between, BOTTOMMOST_LEVEL, CompactionDecision, CompactionFilter,
+    CompactionIterator, CompactionStats, FLUSH_LEVEL, SourceError, TableSource,
+};
+use super::iterator::{LiveEntriesIterator, MergeIterator};
 use super::memtable::MemTable;
-use super::sstable::{SSTableReader, SSTableWriter};
-use super::types::{Entry, Key, SeqNum, Value};
-use super::wal::{delete_wal, Wal, WalReader};
+use super::merge::{missing_operator, MergeCollapseIterator, MergeFold, MergeOperator, MergeResolveIterator};
+use super::properties::{TableProperties, TablePropertiesCollectorFactory};
+use super::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use super::retention::{unix_now, RetentionIterator, RetentionPolicies, SeqTimeMapping};
+use super::snapshot::{Snapshot, SnapshotList};
+use super::ssi::SsiTracker;
+use super::transaction::OptimisticTransaction;
+use super::sstable::{SSTableOptions, SSTableReader, SSTableWriter};
+use super::types::{Entry, Key, SeqNum, Value, ValueKind};
+use super::version::SuperVersion;
+use super::wal::{WalRecord, WalSegments};
 
 /// Configuration for the LSM tree.
 #[derive(Clone)]
@@ -21,6 +42,34 @@ pub struct LsmConfig {
     pub memtable_size_threshold: usize,
     /// Directory for data files.
     pub data_dir: PathBuf,
+    /// Open page files with `O_DIRECT` so the buffer pool is the only page cache.
+    pub direct_io: bool,
+    /// Values of at least this many bytes are moved to blob files on flush, and the
+    /// SSTable stores a reference instead. `None` keeps every value inline.
+    pub blob_value_threshold: Option<usize>,
+    /// Blob GC rewrites blob files whose live bytes fall below this fraction of the file.
+    pub blob_gc_live_ratio: f64,
+    /// Target size of an SSTable block before compression.
+    pub block_size: usize,
+    /// Number of entries between restart points in a block. Keys between restart
+    /// points are stored as a suffix of the previous key.
+    pub block_restart_interval: usize,
+    /// Block compression by level. Levels past the end use the last entry.
+    pub compression_per_level: Vec<CompressionType>,
+    /// Collectors of user-defined properties, run for every SSTable written.
+    pub table_properties_collectors: Vec<Arc<dyn TablePropertiesCollectorFactory>>,
+    /// Compact automatically once this many tables are on level 0. `None` only
+    /// compacts on `LsmTree::compact`.
+    pub l0_compaction_trigger: Option<usize>,
+    /// Historic versions to keep, by key prefix. Applied on flush and compaction.
+    pub retention: RetentionPolicies,
+    /// Combines the operands written by `LsmTree::merge`. Required to use merges.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Called by compaction for every value it writes, to keep, remove or change it.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    /// Order of the keys. A tree must always be opened with a comparator of the
+    /// same name.
+    pub comparator: Arc<dyn Comparator>,
 }
 
 impl Default for LsmConfig {
@@ -28,131 +77,676 @@ impl Default for LsmConfig {
         Self {
             memtable_size_threshold: 4 * 1024 * 1024, // 4MB
             data_dir: PathBuf::from("./data"),
+            direct_io: false,
+            blob_value_threshold: None,
+            blob_gc_live_ratio: 0.5,
+            block_size: DEFAULT_BLOCK_SIZE,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            compression_per_level: vec![CompressionType::None],
+            table_properties_collectors: Vec::new(),
+            l0_compaction_trigger: None,
+            retention: RetentionPolicies::default(),
+            merge_operator: None,
+            compaction_filter: None,
+            comparator: bytewise(),
         }
     }
 }
 
+impl LsmConfig {
+    /// Block compression used for tables written at `level`.
+    pub fn compression_for_level(&self, level: usize) -> CompressionType {
+        self.compression_per_level
+            .get(level)
+            .or(self.compression_per_level.last())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn table_options(&self, level: usize) -> SSTableOptions {
+        SSTableOptions {
+            block_size: self.block_size,
+            restart_interval: self.block_restart_interval,
+            compression: self.compression_for_level(level),
+        }
+    }
+}
+
+/// The versions of a key read so far, newest first, folded into its value.
+struct VersionFold<'a> {
+    fold: MergeFold<'a>,
+    /// Versions written after this are not visible.
+    visible: SeqNum,
+    /// Versions older than this are range deleted.
+    range_deleted_seq: Option<SeqNum>,
+    /// Sequence number of the newest write read, a delete or range delete included.
+    observed: Option<SeqNum>,
+    now: u64,
+    /// The versions read determine the value.
+    done: bool,
+}
+
+impl<'a> VersionFold<'a> {
+    fn new(merge_operator: Option<&'a Arc<dyn MergeOperator>>, visible: SeqNum, range_deleted_seq: Option<SeqNum>) -> Self {
+        Self {
+            fold: MergeFold::new(merge_operator),
+            visible,
+            range_deleted_seq,
+            observed: range_deleted_seq,
+            now: unix_now(),
+            done: false,
+        }
+    }
+
+    /// Add the next older version. Returns true once the value is determined.
+    fn add(&mut self, tree: &LsmTree, entry: Entry) -> Result<bool, std::io::Error> {
+        if entry.seq_num > self.visible {
+            return Ok(false);
+        }
+        self.observed = self.observed.max(Some(entry.seq_num));
+        // Older versions are covered by any range tombstone covering this one,
+        // and an expired version reads as deleted
+        if self.range_deleted_seq.is_some_and(|t| t > entry.seq_num) || entry.is_tombstone() || entry.is_expired(self.now)
+        {
+            self.fold.add_deleted();
+            self.done = true;
+        } else {
+            self.done = self.fold.add(tree.resolve_value(entry)?);
+        }
+        Ok(self.done)
+    }
+}
+
+/// The versions of a key in the active memtable, copied out under its lock.
+struct ActiveVersions {
+    /// Newest first.
+    entries: Vec<Entry>,
+    /// Sequence numbers of the range deletions covering the key, highest first.
+    range_deletion_seqs: Vec<SeqNum>,
+}
+
+/// A column family as listed in the manifest.
+struct ManifestFamily {
+    id: u32,
+    name: String,
+    flushed_seq: SeqNum,
+    /// Name of the comparator the family was created with.
+    comparator: String,
+    /// Ids, levels and global sequence numbers of the family's tables, newest first.
+    tables: Vec<(u64, usize, Option<SeqNum>)>,
+}
+
+impl ManifestFamily {
+    fn new(id: u32, name: String, comparator: &dyn Comparator) -> Self {
+        Self {
+            id,
+            name,
+            flushed_seq: 0,
+            comparator: comparator.name().to_string(),
+            tables: Vec::new(),
+        }
+    }
+}
+
+/// Hard-link `src` to `dst`, copying it instead across file systems.
+fn link_or_copy(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
+    match std::fs::hard_link(src, dst) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => std::fs::copy(src, dst).and_then(|_| sync_path(dst)),
+        result => result,
+    }
+}
+
+/// Flush a file or directory to disk.
+pub(super) fn sync_path(path: &Path) -> Result<(), std::io::Error> {
+    std::fs::File::open(path)?.sync_all()
+}
+
+/// Column family names are stored in the manifest as a single field.
+fn validate_column_family_name(name: &str) -> Result<(), std::io::Error> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Column family name must be non-empty and without whitespace",
+        ));
+    }
+    Ok(())
+}
+
 /// LSM Tree key-value store with duplicate key support.
 pub struct LsmTree {
     config: LsmConfig,
     buffer_pool: Arc<BufferPool>,
+
+    /// Column family used by the methods without a column family argument.
+    default_cf: Arc<ColumnFamily>,
+
+    /// All column families, by id.
+    column_families: RwLock<BTreeMap<u32, Arc<ColumnFamily>>>,
     
-    /// Active memtable for writes.
-    memtable: RwLock<MemTable>,
-    
-    /// Write-ahead log.
-    wal: RwLock<Wal>,
-    
-    /// Immutable SSTables (newest first).
-    sstables: RwLock<Vec<SSTableReader>>,
+    /// Write-ahead log segments, shared by all column families.
+    wal: RwLock<WalSegments>,
     
     /// Next SSTable file ID.
     next_sstable_id: AtomicU64,
+
+    /// Blob files holding separated values.
+    blob_store: BlobStore,
+
+    /// Serializes operations that write tables or blob files (flush, blob GC).
+    maintenance_lock: Mutex<()>,
+
+    /// Tables that left the tree but may still be read through an old super version.
+    obsolete_tables: Mutex<Vec<Weak<SSTableReader>>>,
+
+    /// Live snapshots, whose versions compaction must keep.
+    snapshots: Arc<SnapshotList>,
+
+    /// Reads and writes of serializable transactions.
+    ssi: SsiTracker,
+
+    /// Entries reclaimed by tombstone garbage collection since open.
+    entries_reclaimed: AtomicU64,
 }
 
 impl LsmTree {
     /// Create a new LSM tree or open an existing one.
     pub fn open(config: LsmConfig) -> Result<Self, std::io::Error> {
+        Self::open_with_column_families(config, Vec::new())
+    }
+
+    /// Open a tree with column families besides the default one, each with its own
+    /// knobs. Listed families that don't exist yet are created; existing families
+    /// that aren't listed are opened with the knobs of `config`.
+    pub fn open_with_column_families(
+        config: LsmConfig,
+        column_families: Vec<(String, LsmConfig)>,
+    ) -> Result<Self, std::io::Error> {
         std::fs::create_dir_all(&config.data_dir)?;
         
         let page_store_dir = config.data_dir.join("pages");
         std::fs::create_dir_all(&page_store_dir)?;
         
-        let buffer_pool = Arc::new(BufferPool::new(
-            page_store_dir.to_string_lossy().to_string()
+        let buffer_pool = Arc::new(BufferPool::with_direct_io(
+            page_store_dir.to_string_lossy().to_string(),
+            config.direct_io,
         )?);
 
-        // Open WAL
-        let wal_path = config.data_dir.join("wal.log");
-        let wal = Wal::open(&wal_path)?;
+        // Load the column families of the manifest, and add the missing ones
+        let (mut manifest, next_id) = Self::load_manifest(&config.data_dir)?;
+        if !manifest.iter().any(|family| family.id == DEFAULT_COLUMN_FAMILY_ID) {
+            let default = ManifestFamily::new(DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY.to_string(), config.comparator.as_ref());
+            manifest.insert(0, default);
+        }
+        let mut created = false;
+        for (name, cf_config) in &column_families {
+            if !manifest.iter().any(|family| family.name == *name) {
+                validate_column_family_name(name)?;
+                let id = manifest.iter().map(|family| family.id).max().unwrap_or(0) + 1;
+                manifest.push(ManifestFamily::new(id, name.clone(), cf_config.comparator.as_ref()));
+                created = true;
+            }
+        }
+
+        // Open the tables of each family, ordered by its comparator
+        let mut opened = Vec::with_capacity(manifest.len());
+        for family in manifest {
+            let cf_config = column_families
+                .iter()
+                .find(|(name, _)| *name == family.name && family.id != DEFAULT_COLUMN_FAMILY_ID)
+                .map(|(_, cf_config)| cf_config.clone())
+                .unwrap_or_else(|| config.clone());
+            if family.comparator != cf_config.comparator.name() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Column family {} uses comparator {}, not {}",
+                        family.name,
+                        family.comparator,
+                        cf_config.comparator.name()
+                    ),
+                ));
+            }
+            // The WAL no longer holds the writes of listed tables, so one that fails
+            // to open fails the open rather than going missing
+            let mut sstables = Vec::with_capacity(family.tables.len());
+            for &(id, level, global_seq) in &family.tables {
+                let mut reader = SSTableReader::open_with_comparator(buffer_pool.clone(), id, cf_config.comparator.clone())
+                    .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to open SSTable {}: {}", id, e)))?;
+                reader.level = level;
+                if let Some(seq) = global_seq {
+                    reader.set_global_seq(seq);
+                }
+                sstables.push(Arc::new(reader));
+            }
+            opened.push((family, cf_config, sstables));
+        }
+
+        // Read the WAL before opening it for appends
+        let (mut wal, wal_records) = WalSegments::open(&config.data_dir)?;
 
-        // Recover memtable from WAL if exists
-        let memtable = Self::recover_memtable(&wal_path)?;
+        // Sequence numbers continue after the newest write, logged or flushed
+        let max_seq = wal_records
+            .iter()
+            .map(|record| record.seq_num())
+            .chain(opened.iter().flat_map(|(_, _, sstables)| sstables.iter().map(|s| s.meta.max_seq)))
+            .max()
+            .unwrap_or(0);
+        let seq_counter = Arc::new(AtomicU64::new(max_seq + 1));
+
+        let mut families = BTreeMap::new();
+        for (family, cf_config, sstables) in opened {
+            let memtable = MemTable::with_seq_counter(seq_counter.clone(), cf_config.comparator.clone());
+            let cf = ColumnFamily::new(family.id, family.name, cf_config, memtable, family.flushed_seq);
+            cf.edit_super_version(|_| SuperVersion {
+                immutable_memtables: Vec::new(),
+                sstables,
+            });
+            families.insert(family.id, Arc::new(cf));
+        }
+
+        // Replay the WAL records each family has not flushed yet
+        for record in wal_records {
+            let (cf_id, record) = match record {
+                WalRecord::ColumnFamily(cf_id, record) => (cf_id, *record),
+                record => (DEFAULT_COLUMN_FAMILY_ID, record),
+            };
+            if let Some(cf) = families.get(&cf_id)
+                && record.seq_num() > cf.flushed_seq.load(Ordering::SeqCst)
+            {
+                Self::replay(&mut cf.memtable.write().unwrap(), record);
+            }
+        }
+        wal.purge(|cf_id| families.get(&cf_id).map(|cf| cf.flushed_seq.load(Ordering::SeqCst)))?;
 
-        // Load existing SSTables
-        let (sstables, next_id) = Self::load_sstables(&config.data_dir, buffer_pool.clone())?;
+        let blob_store = BlobStore::open(config.data_dir.join("blobs"))?;
+        let ssi = SsiTracker::new(config.comparator.clone());
 
-        Ok(Self {
+        let tree = Self {
             config,
             buffer_pool,
-            memtable: RwLock::new(memtable),
+            default_cf: families[&DEFAULT_COLUMN_FAMILY_ID].clone(),
+            column_families: RwLock::new(families),
             wal: RwLock::new(wal),
-            sstables: RwLock::new(sstables),
             next_sstable_id: AtomicU64::new(next_id),
-        })
+            blob_store,
+            maintenance_lock: Mutex::new(()),
+            obsolete_tables: Mutex::default(),
+            snapshots: Arc::default(),
+            ssi,
+            entries_reclaimed: AtomicU64::new(0),
+        };
+        if created {
+            tree.save_manifest()?;
+        }
+        Ok(tree)
     }
 
-    fn recover_memtable(wal_path: &PathBuf) -> Result<MemTable, std::io::Error> {
-        let mut memtable = MemTable::new();
-        
-        if wal_path.exists() {
-            match WalReader::open(wal_path) {
-                Ok(mut reader) => {
-                    let entries = reader.read_all()?;
-                    for entry in entries {
-                        if let Some(value) = entry.value {
-                            memtable.put_with_seq(entry.key, value, entry.seq_num);
-                        } else {
-                            memtable.delete_with_seq(entry.key, entry.seq_num);
-                        }
-                    }
+    /// Apply a WAL record to a memtable, with its logged sequence number.
+    fn replay(memtable: &mut MemTable, record: WalRecord) {
+        match record {
+            WalRecord::Entry(entry) => match entry.value {
+                Some(value) if entry.value_kind == ValueKind::Merge => {
+                    memtable.merge_with_seq(entry.key, value, entry.seq_num)
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
-                Err(e) => return Err(e),
+                Some(value) => memtable.put_with_seq_and_expiry(entry.key, value, entry.seq_num, entry.expires_at),
+                None => memtable.delete_with_seq(entry.key, entry.seq_num),
+            },
+            WalRecord::DeleteRange(tombstone) => {
+                memtable.delete_range_with_seq(tombstone.start, tombstone.end, tombstone.seq_num)
             }
+            WalRecord::ColumnFamily(_, record) => Self::replay(memtable, *record),
         }
-        
-        Ok(memtable)
     }
 
-    fn load_sstables(
-        data_dir: &PathBuf,
-        buffer_pool: Arc<BufferPool>,
-    ) -> Result<(Vec<SSTableReader>, u64), std::io::Error> {
-        let mut sstables = Vec::new();
+    fn load_manifest(data_dir: &Path) -> Result<(Vec<ManifestFamily>, u64), std::io::Error> {
+        let mut families: Vec<ManifestFamily> = Vec::new();
         let mut max_id = 0u64;
 
         // Look for SSTable metadata files
         let manifest_path = data_dir.join("manifest");
         if manifest_path.exists() {
             let manifest_content = std::fs::read_to_string(&manifest_path)?;
+            // "family <id> <name> <flushed_seq> <comparator>" starts the tables of a
+            // column family. Each table line is "<id> <level>", followed by the global
+            // sequence number of an ingested table; lines without a level are level 0.
+            // Tables listed before any family belong to the default
+            // family, and families without a comparator are byte-wise ordered.
             for line in manifest_content.lines() {
-                if let Ok(id) = line.parse::<u64>() {
-                    match SSTableReader::open(buffer_pool.clone(), id) {
-                        Ok(reader) => {
-                            max_id = max_id.max(id);
-                            sstables.push(reader);
-                        }
-                        Err(e) => {
-                            eprintln!("Warning: Failed to open SSTable {}: {}", id, e);
-                        }
+                if let Some(family) = line.strip_prefix("family ") {
+                    let mut fields = family.splitn(4, ' ');
+                    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid manifest family");
+                    let id = fields.next().and_then(|f| f.parse::<u32>().ok()).ok_or_else(invalid)?;
+                    let name = fields.next().ok_or_else(invalid)?.to_string();
+                    let flushed_seq = fields.next().and_then(|f| f.parse::<SeqNum>().ok()).ok_or_else(invalid)?;
+                    let mut family = ManifestFamily::new(id, name, &BytewiseComparator);
+                    family.flushed_seq = flushed_seq;
+                    if let Some(comparator) = fields.next() {
+                        family.comparator = comparator.to_string();
+                    }
+                    families.push(family);
+                    continue;
+                }
+                let mut fields = line.split_whitespace();
+                let id = fields.next().and_then(|f| f.parse::<u64>().ok());
+                let level = fields.next().and_then(|f| f.parse::<usize>().ok()).unwrap_or(FLUSH_LEVEL);
+                let global_seq = fields.next().and_then(|f| f.parse::<SeqNum>().ok());
+                if let Some(id) = id {
+                    max_id = max_id.max(id);
+                    if families.is_empty() {
+                        let name = DEFAULT_COLUMN_FAMILY.to_string();
+                        families.push(ManifestFamily::new(DEFAULT_COLUMN_FAMILY_ID, name, &BytewiseComparator));
                     }
+                    families.last_mut().unwrap().tables.push((id, level, global_seq));
                 }
             }
         }
 
-        // Sort by ID descending (newest first)
-        sstables.sort_by(|a, b| b.meta.id.cmp(&a.meta.id));
-
-        Ok((sstables, max_id + 1))
+        // The manifest lists tables newest first. IDs don't reflect data age once
+        // tables get rewritten, so the manifest order is kept as is.
+        Ok((families, max_id + 1))
     }
 
+    /// Replace the manifest atomically: WAL segments are deleted once it records their
+    /// writes as flushed, so a torn manifest would lose them. Callers hold the
+    /// maintenance lock, which keeps the temporary file to one writer.
     fn save_manifest(&self) -> Result<(), std::io::Error> {
-        let manifest_path = self.config.data_dir.join("manifest");
-        let sstables = self.sstables.read().unwrap();
-        let content: String = sstables
-            .iter()
-            .map(|s| s.meta.id.to_string())
-            .collect::<Vec<_>>()
-            .join("\n");
-        std::fs::write(manifest_path, content)
+        let tmp_path = self.config.data_dir.join("manifest.tmp");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(self.manifest_contents().as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, self.config.data_dir.join("manifest"))?;
+        sync_path(&self.config.data_dir)
+    }
+
+    fn manifest_contents(&self) -> String {
+        let families = self.column_families.read().unwrap();
+        let mut lines = Vec::new();
+        for cf in families.values() {
+            lines.push(format!(
+                "family {} {} {} {}",
+                cf.id,
+                cf.name,
+                cf.flushed_seq.load(Ordering::SeqCst),
+                cf.config.comparator.name()
+            ));
+            lines.extend(cf.super_version().sstables.iter().map(|s| match s.global_seq() {
+                Some(seq) => format!("{} {} {}", s.meta.id, s.level, seq),
+                None => format!("{} {}", s.meta.id, s.level),
+            }));
+        }
+        lines.join("\n")
+    }
+
+    /// Add tables written by `ExternalSstWriter` to the tree, bypassing the WAL and
+    /// memtable. The files are copied in, checked to be sorted and not
+    /// to overlap each other, and added at once, all their entries getting one new
+    /// sequence number. Returns that sequence number.
+    pub fn ingest_external_files(&self, paths: &[impl AsRef<Path>]) -> Result<SeqNum, std::io::Error> {
+        self.ingest_external_files_cf(&self.default_cf, paths)
+    }
+
+    /// Add tables written by `ExternalSstWriter` to a column family.
+    pub fn ingest_external_files_cf(
+        &self,
+        cf: &ColumnFamily,
+        paths: &[impl AsRef<Path>],
+    ) -> Result<SeqNum, std::io::Error> {
+        if paths.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No files to ingest"));
+        }
+        // Tables not added to the tree are deleted when dropped
+        let mut tables = Vec::with_capacity(paths.len());
+        let result = self
+            .open_external_files(cf, paths, &mut tables)
+            .and_then(|_| Self::validate_external_tables(cf.config.comparator.as_ref(), &mut tables))
+            .and_then(|_| self.install_external_tables(cf, &mut tables));
+        tables.iter().for_each(SSTableReader::mark_obsolete);
+        let global_seq = result?;
+        self.maybe_compact(cf)?;
+        Ok(global_seq)
+    }
+
+    /// Copy external files into the tree's page store under new ids, and open them.
+    /// Copies rather than links keep later changes to the caller's files out of the tree.
+    fn open_external_files(
+        &self,
+        cf: &ColumnFamily,
+        paths: &[impl AsRef<Path>],
+        tables: &mut Vec<SSTableReader>,
+    ) -> Result<(), std::io::Error> {
+        for path in paths {
+            let id = self.next_sstable_id.fetch_add(1, Ordering::SeqCst);
+            let dest = self.buffer_pool.page_file_path(id);
+            let opened = std::fs::copy(path.as_ref(), &dest).and_then(|_| sync_path(&dest)).and_then(|_| {
+                SSTableReader::open_with_comparator(self.buffer_pool.clone(), id, cf.config.comparator.clone())
+            });
+            match opened {
+                Ok(table) => tables.push(table),
+                Err(e) => {
+                    let _ = self.buffer_pool.remove_file(id);
+                    return Err(e);
+                }
+            }
+        }
+        sync_path(&self.config.data_dir.join("pages"))
+    }
+
+    /// Add validated external tables to a column family with a new global sequence
+    /// number, taking them out of `tables`. Returns the sequence number.
+    fn install_external_tables(
+        &self,
+        cf: &ColumnFamily,
+        tables: &mut Vec<SSTableReader>,
+    ) -> Result<SeqNum, std::io::Error> {
+        loop {
+            // Memtable entries are read before the tables, so none may share a key
+            // with the ingested tables while older than them
+            let overlapping = {
+                let memtable = cf.memtable.read().unwrap();
+                let version = cf.super_version();
+                Self::memtables_overlap(&version.memtables(&memtable), cf.config.comparator.as_ref(), tables)
+            };
+            if overlapping {
+                self.flush_memtable(cf)?;
+            }
+
+            let _maintenance = self.maintenance_lock.lock().unwrap();
+            // Every write takes the default memtable write lock, so none lands meanwhile
+            let default_memtable = self.default_cf.memtable.write().unwrap();
+            let cf_memtable = (cf.id != DEFAULT_COLUMN_FAMILY_ID).then(|| cf.memtable.read().unwrap());
+            let memtable = cf_memtable.as_deref().unwrap_or(&default_memtable);
+            let version = cf.super_version();
+            if Self::memtables_overlap(&version.memtables(memtable), cf.config.comparator.as_ref(), tables) {
+                continue;
+            }
+
+            let global_seq = memtable.seq_counter().fetch_add(1, Ordering::SeqCst);
+            for table in tables.iter_mut() {
+                table.set_global_seq(global_seq);
+            }
+            cf.edit_super_version(|version| SuperVersion {
+                immutable_memtables: version.immutable_memtables.clone(),
+                sstables: tables.drain(..).map(Arc::new).chain(version.sstables.iter().cloned()).collect(),
+            });
+            drop(cf_memtable);
+            drop(default_memtable);
+            self.save_manifest()?;
+            return Ok(global_seq);
+        }
+    }
+
+    /// Check that external tables hold keys in strictly increasing order and no range
+    /// deletions, and that their key ranges don't overlap. Sorts them by key range.
+    fn validate_external_tables(
+        comparator: &dyn Comparator,
+        tables: &mut [SSTableReader],
+    ) -> Result<(), std::io::Error> {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        for table in tables.iter() {
+            if table.meta.entry_count == 0 || !table.range_tombstones().is_empty() {
+                return Err(invalid(format!(
+                    "External table {} must hold entries and no range deletions",
+                    table.meta.id
+                )));
+            }
+            let mut last_key: Option<Key> = None;
+            for entry in table.iter() {
+                let entry = entry?;
+                if last_key.as_ref().is_some_and(|last| compare_keys(comparator, last, &entry.key).is_ge()) {
+                    return Err(invalid(format!("External table {} is not sorted by the comparator", table.meta.id)));
+                }
+                last_key = Some(entry.key);
+            }
+        }
+        tables.sort_by(|a, b| compare_keys(comparator, &a.meta.min_key, &b.meta.min_key));
+        for pair in tables.windows(2) {
+            if compare_keys(comparator, &pair[0].meta.max_key, &pair[1].meta.min_key).is_ge() {
+                return Err(invalid(format!(
+                    "External tables {} and {} overlap",
+                    pair[0].meta.id, pair[1].meta.id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the memtables hold a write to a key in the range of any of `tables`.
+    fn memtables_overlap(memtables: &[&MemTable], comparator: &dyn Comparator, tables: &[SSTableReader]) -> bool {
+        let in_table = |key: &Key| {
+            tables.iter().any(|t| {
+                compare_keys(comparator, key, &t.meta.min_key).is_ge()
+                    && compare_keys(comparator, key, &t.meta.max_key).is_le()
+            })
+        };
+        let tombstone_overlaps = |tombstone: &RangeTombstone| {
+            tables.iter().any(|t| {
+                compare_keys(comparator, &tombstone.start, &t.meta.max_key).is_le()
+                    && compare_keys(comparator, &tombstone.end, &t.meta.min_key).is_gt()
+            })
+        };
+        memtables.iter().any(|memtable| {
+            memtable.iter().any(|entry| in_table(&entry.key))
+                || memtable.range_tombstones().tombstones().iter().any(tombstone_overlaps)
+        })
+    }
+
+    /// Write a consistent copy of the tree to `dest_dir`, which must not exist yet.
+    /// The copy can be opened with `LsmTree::open` independently of this tree.
+    /// Table and blob files are immutable, so they are hard-linked; the WAL holding
+    /// the writes not flushed yet is copied. Writes may go on meanwhile. The copy is
+    /// built in `<dest_dir>.tmp`, which must not exist either, and renamed into place
+    /// once synced, so a failed checkpoint leaves nothing at `dest_dir`.
+    pub fn checkpoint(&self, dest_dir: impl AsRef<Path>) -> Result<(), std::io::Error> {
+        let dest_dir = dest_dir.as_ref();
+        if dest_dir.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Checkpoint directory {} already exists", dest_dir.display()),
+            ));
+        }
+        let tmp_dir = PathBuf::from(format!("{}.tmp", dest_dir.display()));
+        std::fs::create_dir(&tmp_dir).map_err(|e| {
+            std::io::Error::new(e.kind(), format!("Checkpoint directory {}: {}", tmp_dir.display(), e))
+        })?;
+
+        let result = self.write_checkpoint(&tmp_dir).and_then(|_| {
+            std::fs::rename(&tmp_dir, dest_dir)?;
+            match dest_dir.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                Some(parent) => sync_path(parent),
+                None => Ok(()),
+            }
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&tmp_dir);
+        }
+        result
+    }
+
+    /// Write a checkpoint into the empty directory `dir`, syncing it but not its parent.
+    pub(super) fn write_checkpoint(&self, dir: &Path) -> Result<(), std::io::Error> {
+        // No table or blob file is written or deleted while maintenance is held
+        let _maintenance = self.maintenance_lock.lock().unwrap();
+        let manifest = self.manifest_contents();
+
+        let pages_dir = dir.join("pages");
+        std::fs::create_dir_all(&pages_dir)?;
+        let families: Vec<Arc<ColumnFamily>> = self.column_families.read().unwrap().values().cloned().collect();
+        for cf in &families {
+            for sstable in cf.super_version().sstables.iter() {
+                let path = self.buffer_pool.page_file_path(sstable.meta.id);
+                link_or_copy(&path, &pages_dir.join(path.file_name().unwrap()))?;
+            }
+        }
+        let blobs_dir = dir.join("blobs");
+        std::fs::create_dir_all(&blobs_dir)?;
+        for file_id in self.blob_store.file_ids()? {
+            let path = self.blob_store.file_path(file_id);
+            link_or_copy(&path, &blobs_dir.join(path.file_name().unwrap()))?;
+        }
+
+        // WAL segments are only deleted by a flush, so they hold every write the tables
+        // don't. Writes go on while the synced part of each segment is copied.
+        let segments = self.wal.write().unwrap().sync_segments()?;
+        for (path, len) in segments {
+            let dest = dir.join(path.file_name().unwrap());
+            let mut file = std::fs::File::create(&dest)?;
+            std::io::copy(&mut std::fs::File::open(&path)?.take(len), &mut file)?;
+            file.sync_all()?;
+        }
+        let manifest_path = dir.join("manifest");
+        std::fs::write(&manifest_path, manifest)?;
+        // The copied files are synced above; their directory entries are synced here
+        for path in [manifest_path.as_path(), pages_dir.as_path(), blobs_dir.as_path(), dir] {
+            sync_path(path)?;
+        }
+        Ok(())
+    }
+
+    /// Create a column family with its own knobs. The data directory and I/O
+    /// options of the tree apply to it.
+    pub fn create_column_family(&self, name: &str, config: LsmConfig) -> Result<Arc<ColumnFamily>, std::io::Error> {
+        validate_column_family_name(name)?;
+        let _maintenance = self.maintenance_lock.lock().unwrap();
+        let cf = {
+            let mut families = self.column_families.write().unwrap();
+            if families.values().any(|cf| cf.name == name) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("Column family {} already exists", name),
+                ));
+            }
+            let id = families.keys().last().copied().unwrap_or(0) + 1;
+            let seq_counter = self.default_cf.memtable.read().unwrap().seq_counter();
+            let memtable = MemTable::with_seq_counter(seq_counter, config.comparator.clone());
+            let cf = Arc::new(ColumnFamily::new(id, name.to_string(), config, memtable, 0));
+            families.insert(id, cf.clone());
+            cf
+        };
+        self.save_manifest()?;
+        Ok(cf)
+    }
+
+    /// Get a column family by name.
+    pub fn column_family(&self, name: &str) -> Option<Arc<ColumnFamily>> {
+        self.column_families.read().unwrap().values().find(|cf| cf.name == name).cloned()
+    }
+
+    /// The column family used by the methods without a column family argument.
+    pub fn default_column_family(&self) -> Arc<ColumnFamily> {
+        self.default_cf.clone()
+    }
+
+    /// Names of all column families, the default one first.
+    pub fn column_family_names(&self) -> Vec<String> {
+        self.column_families.read().unwrap().values().map(|cf| cf.name.clone()).collect()
     }
 
     /// Put a key-value pair.
     pub fn put(&self, key: Key, value: Value) -> Result<SeqNum, std::io::Error> {
+        let cf = &self.default_cf;
         let seq_num;
         {
-            let mut memtable = self.memtable.write().unwrap();
+            let mut memtable = cf.memtable.write().unwrap();
             
             // Allocate seq_num via put() which handles incrementing
             seq_num = memtable.put(key.clone(), value.clone());
@@ -161,349 +755,2501 @@ impl LsmTree {
         // Log to WAL (after successful memtable write for seq_num, but we flush to ensure durability)
         {
             let mut wal = self.wal.write().unwrap();
-            wal.log_put(&key, &value, seq_num)?;
+            wal.active(DEFAULT_COLUMN_FAMILY_ID, seq_num).log_put(&key, &value, seq_num)?;
         }
 
         // Check if we need to flush
-        self.maybe_flush()?;
+        self.maybe_flush(cf)?;
 
         Ok(seq_num)
     }
 
-    /// Delete a key.
-    pub fn delete(&self, key: Key) -> Result<SeqNum, std::io::Error> {
+    /// Put a key-value pair that reads as deleted once `ttl` has passed. Expired
+    /// entries are removed by compaction.
+    pub fn put_with_ttl(&self, key: Key, value: Value, ttl: Duration) -> Result<SeqNum, std::io::Error> {
+        let cf = &self.default_cf;
+        // Round up, so an entry never expires early
+        let ttl_secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+        let expires_at = unix_now().saturating_add(ttl_secs);
         let seq_num;
         {
-            let mut memtable = self.memtable.write().unwrap();
-            seq_num = memtable.delete(key.clone());
+            let mut memtable = cf.memtable.write().unwrap();
+            seq_num = memtable.put_with_expiry(key.clone(), value.clone(), expires_at);
         }
 
         {
             let mut wal = self.wal.write().unwrap();
-            wal.log_delete(&key, seq_num)?;
+            wal.active(DEFAULT_COLUMN_FAMILY_ID, seq_num).log_put_with_expiry(&key, &value, expires_at, seq_num)?;
         }
 
-        self.maybe_flush()?;
+        self.maybe_flush(cf)?;
 
         Ok(seq_num)
     }
 
-    /// Get the latest value for a key.
-    /// Returns None if not found or deleted.
-    pub fn get(&self, key: &Key) -> Result<Option<Value>, std::io::Error> {
-        // Check memtable first
+    /// Add a merge operand for a key. Reads apply the operands to the key's value
+    /// with the configured `MergeOperator`.
+    pub fn merge(&self, key: Key, operand: Value) -> Result<SeqNum, std::io::Error> {
+        let cf = &self.default_cf;
+        if cf.config.merge_operator.is_none() {
+            return Err(missing_operator());
+        }
+
+        let seq_num;
         {
-            let memtable = self.memtable.read().unwrap();
-            if let Some(value_opt) = memtable.get(key) {
-                return Ok(value_opt.cloned());
-            }
+            let mut memtable = cf.memtable.write().unwrap();
+            seq_num = memtable.merge(key.clone(), operand.clone());
         }
 
-        // Check SSTables (newest to oldest)
-        let sstables = self.sstables.read().unwrap();
-        for sstable in sstables.iter() {
-            let entries = sstable.get(key)?;
-            if !entries.is_empty() {
-                // Return the newest entry's value (first in the list)
-                return Ok(entries[0].value.clone());
-            }
+        {
+            let mut wal = self.wal.write().unwrap();
+            wal.active(DEFAULT_COLUMN_FAMILY_ID, seq_num).log_merge(&key, &operand, seq_num)?;
         }
 
-        Ok(None)
-    }
+        self.maybe_flush(cf)?;
 
-    /// Get all values for a key (for duplicate key support).
-    /// Returns entries in seq_num descending order (newest first).
-    pub fn get_all(&self, key: &Key) -> Result<Vec<Entry>, std::io::Error> {
-        let mut all_entries = Vec::new();
+        Ok(seq_num)
+    }
 
-        // Get from memtable
+    /// Delete a key.
+    pub fn delete(&self, key: Key) -> Result<SeqNum, std::io::Error> {
+        let cf = &self.default_cf;
+        let seq_num;
         {
-            let memtable = self.memtable.read().unwrap();
-            for (seq_num, value_opt) in memtable.get_all(key) {
-                let entry = match value_opt {
-                    Some(v) => Entry::put(key.clone(), seq_num, v.clone()),
-                    None => Entry::delete(key.clone(), seq_num),
-                };
-                all_entries.push(entry);
-            }
+            let mut memtable = cf.memtable.write().unwrap();
+            seq_num = memtable.delete(key.clone());
         }
 
-        // Get from SSTables
-        let sstables = self.sstables.read().unwrap();
-        for sstable in sstables.iter() {
-            let entries = sstable.get(key)?;
-            all_entries.extend(entries);
+        {
+            let mut wal = self.wal.write().unwrap();
+            wal.active(DEFAULT_COLUMN_FAMILY_ID, seq_num).log_delete(&key, seq_num)?;
         }
 
-        // Sort by seq_num descending
-        all_entries.sort_by(|a, b| b.seq_num.cmp(&a.seq_num));
+        self.maybe_flush(cf)?;
 
-        Ok(all_entries)
+        Ok(seq_num)
     }
 
-    /// Scan all entries in sorted order.
-    /// Returns entries merged from memtable and all SSTables.
-    pub fn scan(&self) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
-        let mut sources: Vec<Box<dyn Iterator<Item = Entry>>> = Vec::new();
+    /// Delete all keys in `[start, end)` with a single range tombstone.
+    pub fn delete_range(&self, start: Key, end: Key) -> Result<SeqNum, std::io::Error> {
+        if compare_keys(self.default_cf.config.comparator.as_ref(), &start, &end).is_ge() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Range start must be before range end",
+            ));
+        }
 
-        // Add memtable entries
+        let cf = &self.default_cf;
+        let seq_num;
         {
-            let memtable = self.memtable.read().unwrap();
-            let entries: Vec<Entry> = memtable.iter().collect();
-            sources.push(Box::new(entries.into_iter()));
+            let mut memtable = cf.memtable.write().unwrap();
+            seq_num = memtable.delete_range(start.clone(), end.clone());
         }
 
-        // Add SSTable entries
-        let sstables = self.sstables.read().unwrap();
-        for sstable in sstables.iter() {
-            let iter = sstable.iter().filter_map(|r| r.ok());
-            sources.push(Box::new(iter.collect::<Vec<_>>().into_iter()));
+        {
+            let mut wal = self.wal.write().unwrap();
+            wal.active(DEFAULT_COLUMN_FAMILY_ID, seq_num).log_delete_range(&start, &end, seq_num)?;
         }
 
-        Ok(MergeIterator::new(sources))
+        self.maybe_flush(cf)?;
+
+        Ok(seq_num)
     }
 
-    /// Scan with only latest versions (no duplicates).
-    pub fn scan_latest(&self) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
-        Ok(LatestVersionIterator::new(self.scan()?))
+    /// Apply the writes of `batch` atomically: readers see all of them or none, and
+    /// so does recovery. Returns the sequence number of the last write.
+    pub fn write(&self, batch: WriteBatch) -> Result<SeqNum, std::io::Error> {
+        self.write_checked(batch, |_, _| Ok(()))
     }
 
-    /// Scan with only live entries (no tombstones).
-    pub fn scan_live(&self) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
-        Ok(LiveEntriesIterator::new(LatestVersionIterator::new(self.scan()?)))
+    /// Put a key-value pair in a column family.
+    pub fn put_cf(&self, cf: &ColumnFamily, key: Key, value: Value) -> Result<SeqNum, std::io::Error> {
+        let mut batch = WriteBatch::new();
+        batch.put_cf(cf, key, value);
+        self.write(batch)
     }
 
-    fn maybe_flush(&self) -> Result<(), std::io::Error> {
-        let should_flush = {
-            let memtable = self.memtable.read().unwrap();
-            memtable.size_bytes() >= self.config.memtable_size_threshold
-        };
+    /// Add a merge operand for a key of a column family.
+    pub fn merge_cf(&self, cf: &ColumnFamily, key: Key, operand: Value) -> Result<SeqNum, std::io::Error> {
+        let mut batch = WriteBatch::new();
+        batch.merge_cf(cf, key, operand);
+        self.write(batch)
+    }
 
-        if should_flush {
-            self.flush()?;
-        }
+    /// Delete a key of a column family.
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: Key) -> Result<SeqNum, std::io::Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete_cf(cf, key);
+        self.write(batch)
+    }
 
-        Ok(())
+    /// Delete all keys of a column family in `[start, end)`.
+    pub fn delete_range_cf(&self, cf: &ColumnFamily, start: Key, end: Key) -> Result<SeqNum, std::io::Error> {
+        let mut batch = WriteBatch::new();
+        batch.delete_range_cf(cf, start, end);
+        self.write(batch)
     }
 
-    /// Force flush the memtable to an SSTable.
-    pub fn flush(&self) -> Result<(), std::io::Error> {
-        let entries: Vec<Entry>;
-        let wal_path: String;
-        
-        {
-            let memtable = self.memtable.read().unwrap();
-            if memtable.is_empty() {
-                return Ok(());
+    /// Apply `batch` if `check` passes, with no other write in between. `check` is
+    /// given a lookup of the newest sequence number written to a key of the default
+    /// column family, and the sequence number the batch's last write will get.
+    pub(crate) fn write_checked(
+        &self,
+        batch: WriteBatch,
+        check: impl FnOnce(&dyn Fn(&Key) -> Result<Option<SeqNum>, std::io::Error>, SeqNum) -> Result<(), std::io::Error>,
+    ) -> Result<SeqNum, std::io::Error> {
+        // The written families in id order. The default family is always among
+        // them, so holding its memtable lock keeps out every write.
+        let families: Vec<Arc<ColumnFamily>> = {
+            let all = self.column_families.read().unwrap();
+            let ids: BTreeSet<u32> = std::iter::once(DEFAULT_COLUMN_FAMILY_ID)
+                .chain(batch.ops().iter().map(|(cf_id, _)| *cf_id))
+                .collect();
+            ids.iter()
+                .map(|cf_id| {
+                    all.get(cf_id).cloned().ok_or_else(|| {
+                        std::io::Error::new(std::io::ErrorKind::NotFound, format!("Unknown column family {}", cf_id))
+                    })
+                })
+                .collect::<Result<_, _>>()?
+        };
+        let family_index = |cf_id: u32| families.iter().position(|cf| cf.id == cf_id).unwrap();
+
+        for (cf_id, op) in batch.ops() {
+            let config = &families[family_index(*cf_id)].config;
+            match op {
+                BatchOp::Merge(..) if config.merge_operator.is_none() => return Err(missing_operator()),
+                BatchOp::DeleteRange(start, end) if compare_keys(config.comparator.as_ref(), start, end).is_ge() => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Range start must be before range end",
+                    ));
+                }
+                _ => {}
             }
-            entries = memtable.iter().collect();
-            
-            let wal = self.wal.read().unwrap();
-            wal_path = wal.path().to_string();
         }
 
-        // Create new SSTable
-        let sstable_id = self.next_sstable_id.fetch_add(1, Ordering::SeqCst);
-        
+        let mut records = Vec::with_capacity(batch.len());
+        let last_seq;
         {
-            let mut writer = SSTableWriter::new(&self.buffer_pool, sstable_id)?;
-            for entry in &entries {
-                writer.write_entry(entry)?;
+            let mut memtables: Vec<_> = families.iter().map(|cf| cf.memtable.write().unwrap()).collect();
+            {
+                let version = self.default_cf.super_version();
+                let sources = version.memtables(&memtables[0]);
+                let last_seq = (memtables[0].current_seq_num() + batch.len() as SeqNum).saturating_sub(1);
+                check(&|key| Self::latest_seq(&sources, &version.sstables, key), last_seq)?;
+            }
+            if batch.is_empty() {
+                return Ok(memtables[0].current_seq_num().saturating_sub(1));
             }
-            writer.finish()?;
-        }
-
-        // Flush buffer pool to ensure SSTable is persisted
-        self.buffer_pool.flush()?;
 
-        // Open the new SSTable for reading
-        let reader = SSTableReader::open(self.buffer_pool.clone(), sstable_id)?;
+            for (cf_id, op) in batch.into_ops() {
+                let memtable = &mut memtables[family_index(cf_id)];
+                let record = match op {
+                    BatchOp::Put(key, value) => {
+                        let seq_num = memtable.put(key.clone(), value.clone());
+                        WalRecord::Entry(Entry::put(key, seq_num, value))
+                    }
+                    BatchOp::Merge(key, operand) => {
+                        let seq_num = memtable.merge(key.clone(), operand.clone());
+                        WalRecord::Entry(Entry::merge(key, seq_num, operand))
+                    }
+                    BatchOp::Delete(key) => {
+                        let seq_num = memtable.delete(key.clone());
+                        WalRecord::Entry(Entry::delete(key, seq_num))
+                    }
+                    BatchOp::DeleteRange(start, end) => {
+                        let seq_num = memtable.delete_range(start.clone(), end.clone());
+                        WalRecord::DeleteRange(RangeTombstone::new(start, end, seq_num))
+                    }
+                };
+                records.push(match cf_id {
+                    DEFAULT_COLUMN_FAMILY_ID => record,
+                    _ => WalRecord::ColumnFamily(cf_id, Box::new(record)),
+                });
+            }
+            last_seq = memtables[0].current_seq_num() - 1;
+        }
 
-        // Add to SSTables list
         {
-            let mut sstables = self.sstables.write().unwrap();
-            sstables.insert(0, reader); // Insert at front (newest)
+            let mut wal = self.wal.write().unwrap();
+            wal.log_batch(&records)?;
         }
 
-        // Clear memtable and reset WAL
-        {
-            let mut memtable = self.memtable.write().unwrap();
-            let seq_num = memtable.current_seq_num();
-            memtable.clear();
-            // Preserve sequence number across flushes
-            *memtable = MemTable::with_seq_num(seq_num);
+        for cf in &families {
+            self.maybe_flush(cf)?;
         }
 
-        // Delete old WAL and create new one
-        drop(self.wal.write().unwrap());
-        let _ = delete_wal(&wal_path);
-        
-        let new_wal_path = self.config.data_dir.join("wal.log");
-        let new_wal = Wal::open(&new_wal_path)?;
-        *self.wal.write().unwrap() = new_wal;
+        Ok(last_seq)
+    }
 
-        // Update manifest
-        self.save_manifest()?;
+    /// Newest sequence number written to `key`, including deletes and range
+    /// deletes, across the memtables and all SSTables.
+    fn latest_seq(
+        memtables: &[&MemTable],
+        sstables: &[Arc<SSTableReader>],
+        key: &Key,
+    ) -> Result<Option<SeqNum>, std::io::Error> {
+        let mut latest = memtables.iter().find_map(|memtable| memtable.get_entries(key).first().map(|e| e.seq_num));
+        if latest.is_none() {
+            for sstable in sstables {
+                latest = sstable.get(key)?.iter().map(|e| e.seq_num).max();
+                if latest.is_some() {
+                    break;
+                }
+            }
+        }
+        Ok(latest.max(Self::max_range_tombstone_seq(memtables, sstables, key)))
+    }
 
-        Ok(())
+    /// Highest sequence number of a range tombstone covering `key`, across the
+    /// memtables and all SSTables.
+    fn max_range_tombstone_seq(memtables: &[&MemTable], sstables: &[Arc<SSTableReader>], key: &Key) -> Option<SeqNum> {
+        memtables
+            .iter()
+            .map(|m| m.range_tombstones())
+            .chain(sstables.iter().map(|s| s.range_tombstones()))
+            .filter_map(|tombstones| tombstones.max_covering_seq(key))
+            .max()
     }
 
-    /// Get statistics about the LSM tree.
-    pub fn stats(&self) -> LsmStats {
-        let memtable = self.memtable.read().unwrap();
-        let sstables = self.sstables.read().unwrap();
+    /// All range tombstones of the memtables and SSTables.
+    fn all_range_tombstones(memtables: &[&MemTable], sstables: &[Arc<SSTableReader>]) -> FragmentedRangeTombstones {
+        let tombstones = memtables
+            .iter()
+            .map(|m| m.range_tombstones())
+            .chain(sstables.iter().map(|s| s.range_tombstones()))
+            .flat_map(|tombstones| tombstones.tombstones().iter().cloned())
+            .collect();
+        FragmentedRangeTombstones::with_comparator(tombstones, memtables[0].range_tombstones().comparator().clone())
+    }
 
-        LsmStats {
-            memtable_entries: memtable.len(),
-            memtable_size_bytes: memtable.size_bytes(),
-            sstable_count: sstables.len(),
-            total_entries: sstables.iter().map(|s| s.meta.entry_count).sum::<u64>() as usize
-                + memtable.len(),
-        }
+    /// Get the latest value for a key, with merge operands applied.
+    /// Returns None if not found, deleted or expired.
+    pub fn get(&self, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        self.get_cf(&self.default_cf, key)
     }
-}
 
-/// Statistics about the LSM tree.
-#[derive(Debug, Clone)]
-pub struct LsmStats {
-    pub memtable_entries: usize,
-    pub memtable_size_bytes: usize,
-    pub sstable_count: usize,
-    pub total_entries: usize,
-}
+    /// Get the latest value for a key of a column family.
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        let (mut active, version) = Self::read_active(cf, &[key]);
+        let active = active.pop().unwrap();
+        let immutables: Vec<&MemTable> = version.immutable_memtables.iter().map(Arc::as_ref).collect();
+        let range_deleted_seq = Self::max_range_tombstone_seq(&immutables, &version.sstables, key)
+            .max(active.range_deletion_seqs.first().copied());
+        Ok(self.read_value(cf, key, active.entries, &version, SeqNum::MAX, range_deleted_seq)?.0)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::{SystemTime, UNIX_EPOCH};
+    /// Copy the versions of `keys` out of the active memtable of `cf`, and take the
+    /// current super version. The memtable lock is held only meanwhile, so probing
+    /// the super version holds up no write or flush.
+    fn read_active(cf: &ColumnFamily, keys: &[&Key]) -> (Vec<ActiveVersions>, Arc<SuperVersion>) {
+        let memtable = cf.memtable.read().unwrap();
+        let active = keys
+            .iter()
+            .map(|key| ActiveVersions {
+                entries: memtable.get_entries(key),
+                range_deletion_seqs: memtable.range_tombstones().covering_seqs(key).to_vec(),
+            })
+            .collect();
+        (active, cf.super_version())
+    }
 
-    fn get_temp_dir() -> PathBuf {
-        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        PathBuf::from(format!("/tmp/thordb_lsm_test_{}", since_epoch.as_nanos()))
+    /// Get the latest values of many keys at once, in the order of `keys`. The keys
+    /// are looked up in sorted order in one super version, probing each memtable once
+    /// and walking each SSTable in a single pass.
+    pub fn multi_get(&self, keys: &[Key]) -> Vec<Result<Option<Value>, std::io::Error>> {
+        self.multi_get_cf(&self.default_cf, keys)
+    }
+
+    /// Get the latest values of many keys of a column family at once.
+    pub fn multi_get_cf(&self, cf: &ColumnFamily, keys: &[Key]) -> Vec<Result<Option<Value>, std::io::Error>> {
+        let comparator = cf.config.comparator.as_ref();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| compare_keys(comparator, &keys[a], &keys[b]));
+
+        let sorted_keys: Vec<&Key> = order.iter().map(|&i| &keys[i]).collect();
+        let (active, version) = Self::read_active(cf, &sorted_keys);
+        let immutables: Vec<&MemTable> = version.immutable_memtables.iter().map(Arc::as_ref).collect();
+        let sstables = &version.sstables;
+        let mut folds: Vec<VersionFold<'_>> = active
+            .iter()
+            .zip(&sorted_keys)
+            .map(|(active, key)| {
+                let range_deleted_seq = Self::max_range_tombstone_seq(&immutables, sstables, key)
+                    .max(active.range_deletion_seqs.first().copied());
+                VersionFold::new(cf.config.merge_operator.as_ref(), SeqNum::MAX, range_deleted_seq)
+            })
+            .collect();
+        let mut errors: Vec<Option<std::io::Error>> = order.iter().map(|_| None).collect();
+        // Add a key's entries to its fold; a failure to read a value ends the key with an error
+        let add_all = |fold: &mut VersionFold<'_>, error: &mut Option<std::io::Error>, entries: Vec<Entry>| {
+            for entry in entries {
+                match fold.add(self, entry) {
+                    Ok(false) => {}
+                    Ok(true) => break,
+                    Err(e) => {
+                        *error = Some(e);
+                        fold.done = true;
+                        break;
+                    }
+                }
+            }
+        };
+
+        for (pos, active) in active.into_iter().enumerate() {
+            add_all(&mut folds[pos], &mut errors[pos], active.entries);
+        }
+        for memtable in &immutables {
+            for (pos, key) in sorted_keys.iter().enumerate() {
+                if !folds[pos].done {
+                    add_all(&mut folds[pos], &mut errors[pos], memtable.get_entries(key));
+                }
+            }
+        }
+        for sstable in sstables.iter() {
+            let pending: Vec<usize> = (0..order.len()).filter(|&pos| !folds[pos].done).collect();
+            if pending.is_empty() {
+                break;
+            }
+            let pending_keys: Vec<&Key> = pending.iter().map(|&pos| sorted_keys[pos]).collect();
+            match sstable.multi_get(&pending_keys) {
+                Ok(found) => {
+                    for (&pos, entries) in pending.iter().zip(found) {
+                        add_all(&mut folds[pos], &mut errors[pos], entries);
+                    }
+                }
+                // Look the keys up one by one, so only those the error affects fail
+                Err(_) => {
+                    for &pos in &pending {
+                        match sstable.get(sorted_keys[pos]) {
+                            Ok(entries) => add_all(&mut folds[pos], &mut errors[pos], entries),
+                            Err(e) => {
+                                errors[pos] = Some(e);
+                                folds[pos].done = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut results: Vec<Option<Result<Option<Value>, std::io::Error>>> = keys.iter().map(|_| None).collect();
+        for ((fold, error), i) in folds.into_iter().zip(errors).zip(order) {
+            results[i] = Some(match error {
+                Some(e) => Err(e),
+                None => fold.fold.finish(&keys[i]),
+            });
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Read the value of a key as of sequence number `visible`: the newest version,
+    /// with merge operands folded onto the versions below them. Versions older
+    /// than `range_deleted_seq` are deleted. Also returns the sequence number of
+    /// the newest write to the key that was read, a delete or range delete included.
+    fn read_value(
+        &self,
+        cf: &ColumnFamily,
+        key: &Key,
+        active_entries: Vec<Entry>,
+        version: &SuperVersion,
+        visible: SeqNum,
+        range_deleted_seq: Option<SeqNum>,
+    ) -> Result<(Option<Value>, Option<SeqNum>), std::io::Error> {
+        let mut fold = VersionFold::new(cf.config.merge_operator.as_ref(), visible, range_deleted_seq);
+        // Check memtables first, then SSTables (newest to oldest)
+        let memtable_entries = std::iter::once(active_entries)
+            .chain(version.immutable_memtables.iter().map(|memtable| memtable.get_entries(key)));
+        'memtables: for entries in memtable_entries {
+            for entry in entries {
+                if fold.add(self, entry)? {
+                    break 'memtables;
+                }
+            }
+        }
+        if !fold.done {
+            'tables: for sstable in &version.sstables {
+                for entry in sstable.get(key)? {
+                    if fold.add(self, entry)? {
+                        break 'tables;
+                    }
+                }
+            }
+        }
+        Ok((fold.fold.finish(key)?, fold.observed))
+    }
+
+    /// Take a snapshot of the current state. Reads through it ignore later writes
+    /// until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        // Hold the default memtable lock, which every write takes, so no write is half-applied
+        let memtable = self.default_cf.memtable.read().unwrap();
+        self.snapshots.acquire(memtable.current_seq_num().saturating_sub(1))
+    }
+
+    /// Begin a transaction that reads through a snapshot taken now and checks
+    /// for conflicting writes when it commits.
+    pub fn begin_optimistic_transaction(&self) -> OptimisticTransaction<'_> {
+        OptimisticTransaction::new(self, self.snapshot(), false)
+    }
+
+    /// Begin a transaction with serializable snapshot isolation: like an optimistic
+    /// transaction, but commit fails on read-write dependency cycles with concurrent
+    /// serializable transactions rather than on any newer write to a key it read.
+    pub fn begin_serializable_transaction(&self) -> OptimisticTransaction<'_> {
+        OptimisticTransaction::new(self, self.snapshot(), true)
+    }
+
+    /// Order of the keys of the default column family.
+    pub(crate) fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.default_cf.config.comparator
+    }
+
+    pub(crate) fn merge_operator(&self) -> Option<&Arc<dyn MergeOperator>> {
+        self.default_cf.config.merge_operator.as_ref()
+    }
+
+    pub(crate) fn ssi(&self) -> &SsiTracker {
+        &self.ssi
+    }
+
+    /// Sequence number of the oldest live snapshot.
+    pub(crate) fn oldest_snapshot_seq(&self) -> Option<SeqNum> {
+        self.snapshots.seqs().first().copied()
+    }
+
+    /// Get the value of a key as of `snapshot`.
+    pub fn get_at(&self, key: &Key, snapshot: &Snapshot) -> Result<Option<Value>, std::io::Error> {
+        Ok(self.get_at_with_seq(key, snapshot)?.0)
+    }
+
+    /// Get the value of a key of a column family as of `snapshot`.
+    pub fn get_at_cf(&self, cf: &ColumnFamily, key: &Key, snapshot: &Snapshot) -> Result<Option<Value>, std::io::Error> {
+        Ok(self.read_at(cf, key, snapshot)?.0)
+    }
+
+    /// Get the value of a key as of `snapshot`, with the sequence number of the
+    /// newest write to the key the snapshot sees.
+    pub(crate) fn get_at_with_seq(
+        &self,
+        key: &Key,
+        snapshot: &Snapshot,
+    ) -> Result<(Option<Value>, Option<SeqNum>), std::io::Error> {
+        self.read_at(&self.default_cf, key, snapshot)
+    }
+
+    fn read_at(
+        &self,
+        cf: &ColumnFamily,
+        key: &Key,
+        snapshot: &Snapshot,
+    ) -> Result<(Option<Value>, Option<SeqNum>), std::io::Error> {
+        let visible = snapshot.seq_num();
+        let (mut active, version) = Self::read_active(cf, &[key]);
+        let active = active.pop().unwrap();
+        let range_deleted_seq = version
+            .immutable_memtables
+            .iter()
+            .map(|m| m.range_tombstones())
+            .chain(version.sstables.iter().map(|s| s.range_tombstones()))
+            .filter_map(|tombstones| tombstones.max_covering_seq_at(key, visible))
+            .chain(active.range_deletion_seqs.iter().copied().find(|&seq| seq <= visible))
+            .max();
+        self.read_value(cf, key, active.entries, &version, visible, range_deleted_seq)
+    }
+
+    /// Get all values for a key (for duplicate key support).
+    /// Returns entries in seq_num descending order (newest first).
+    /// Versions deleted by a range tombstone are left out; expired versions are
+    /// returned as tombstones, as compaction treats them.
+    pub fn get_all(&self, key: &Key) -> Result<Vec<Entry>, std::io::Error> {
+        let cf = &self.default_cf;
+        let mut all_entries = Vec::new();
+
+        let memtable = cf.memtable.read().unwrap();
+        let version = cf.super_version();
+        let memtables = version.memtables(&memtable);
+        let range_deleted_seq = Self::max_range_tombstone_seq(&memtables, &version.sstables, key);
+        let now = unix_now();
+        let is_hidden = |entry: &Entry| range_deleted_seq.is_some_and(|t| t > entry.seq_num);
+
+        // Get from memtables
+        for memtable in memtables {
+            for entry in memtable.get_entries(key).into_iter().filter(|e| !is_hidden(e)) {
+                all_entries.push(Self::expire(entry, now));
+            }
+        }
+        drop(memtable);
+
+        // Get from SSTables
+        for sstable in version.sstables.iter() {
+            for entry in sstable.get(key)? {
+                if !is_hidden(&entry) {
+                    all_entries.push(self.resolve_value(Self::expire(entry, now))?);
+                }
+            }
+        }
+
+        // Sort by seq_num descending
+        all_entries.sort_by(|a, b| b.seq_num.cmp(&a.seq_num));
+
+        Ok(all_entries)
+    }
+
+    /// Scan all entries in sorted order.
+    /// Returns entries merged from memtable and all SSTables, leaving out entries
+    /// deleted by a range tombstone. Expired entries are returned as tombstones.
+    pub fn scan(&self) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
+        self.scan_cf(&self.default_cf)
+    }
+
+    /// Scan all entries of a column family in sorted order.
+    pub fn scan_cf(&self, cf: &ColumnFamily) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
+        let mut sources: Vec<Box<dyn Iterator<Item = Entry>>> = Vec::new();
+
+        let memtable = cf.memtable.read().unwrap();
+        let version = cf.super_version();
+        let memtables = version.memtables(&memtable);
+        let range_tombstones = Self::all_range_tombstones(&memtables, &version.sstables);
+
+        // Add memtable entries
+        for memtable in memtables {
+            let entries: Vec<Entry> = memtable.iter().collect();
+            sources.push(Box::new(entries.into_iter()));
+        }
+        drop(memtable);
+
+        // Add SSTable entries
+        for sstable in version.sstables.iter() {
+            let entries = sstable
+                .iter()
+                .map(|entry| self.resolve_value(entry?))
+                .collect::<Result<Vec<_>, _>>()?;
+            sources.push(Box::new(entries.into_iter()));
+        }
+
+        let now = unix_now();
+        Ok(MergeIterator::with_comparator(sources, cf.config.comparator.clone())
+            .filter(move |entry| !range_tombstones.covers(&entry.key, entry.seq_num))
+            .map(move |entry| Self::expire(entry, now)))
+    }
+
+    /// Live entries with a key in `range` as of `snapshot`: the newest version of
+    /// each key, with merge operands folded.
+    pub fn scan_range_at(&self, range: impl RangeBounds<Key>, snapshot: &Snapshot) -> Result<Vec<Entry>, std::io::Error> {
+        let cf = &self.default_cf;
+        let visible = snapshot.seq_num();
+        let in_view = |entry: &Entry| {
+            entry.seq_num <= visible && range_contains(cf.config.comparator.as_ref(), &range, &entry.key)
+        };
+        let mut sources: Vec<Box<dyn Iterator<Item = Entry>>> = Vec::new();
+
+        let memtable = cf.memtable.read().unwrap();
+        let version = cf.super_version();
+        let memtables = version.memtables(&memtable);
+        let range_tombstones = Self::all_range_tombstones(&memtables, &version.sstables);
+
+        for memtable in memtables {
+            let entries: Vec<Entry> = memtable.iter().filter(|e| in_view(e)).collect();
+            sources.push(Box::new(entries.into_iter()));
+        }
+        drop(memtable);
+
+        for sstable in version.sstables.iter() {
+            let mut entries = Vec::new();
+            for entry in sstable.iter() {
+                let entry = entry?;
+                if in_view(&entry) {
+                    entries.push(self.resolve_value(entry)?);
+                }
+            }
+            sources.push(Box::new(entries.into_iter()));
+        }
+
+        let now = unix_now();
+        let entries = MergeIterator::with_comparator(sources, cf.config.comparator.clone())
+            .filter(|entry| {
+                range_tombstones
+                    .max_covering_seq_at(&entry.key, visible)
+                    .is_none_or(|tombstone_seq| tombstone_seq < entry.seq_num)
+            })
+            .map(|entry| Self::expire(entry, now));
+        let latest = MergeResolveIterator::new(entries, cf.config.merge_operator.clone());
+        Ok(LiveEntriesIterator::new(latest).collect())
+    }
+
+    /// An entry as read at `now`: expired entries read as tombstones.
+    fn expire(entry: Entry, now: u64) -> Entry {
+        if entry.is_expired(now) {
+            Entry::delete(entry.key, entry.seq_num)
+        } else {
+            entry
+        }
+    }
+
+    /// Replace a blob reference with the value it points to.
+    /// Must be called while holding a super version with the table the entry was read
+    /// from, since blob GC keeps the blob files of live tables.
+    fn resolve_value(&self, entry: Entry) -> Result<Entry, std::io::Error> {
+        match entry.blob_ref()? {
+            Some(blob_ref) => {
+                let value = self.blob_store.read(&blob_ref)?;
+                Ok(Entry::put(entry.key, entry.seq_num, value).with_expiry(entry.expires_at))
+            }
+            None => Ok(entry),
+        }
+    }
+
+    /// Get the versions of a key written with a sequence number in `seqs`, newest
+    /// first, including tombstones. A range deletion covering the key appears as a
+    /// tombstone at its sequence number. Tables outside the range are not read.
+    pub fn get_history(&self, key: &Key, seqs: impl RangeBounds<SeqNum>) -> Result<Vec<Entry>, std::io::Error> {
+        let cf = &self.default_cf;
+        let mut history = Vec::new();
+
+        let version = {
+            let memtable = cf.memtable.read().unwrap();
+            let version = cf.super_version();
+            for memtable in version.memtables(&memtable) {
+                history.extend(memtable.get_entries(key).into_iter().filter(|e| seqs.contains(&e.seq_num)));
+                history.extend(Self::range_deletions_of(memtable.range_tombstones(), key, &seqs));
+            }
+            version
+        };
+
+        for sstable in version.sstables.iter().filter(|s| s.might_contain_seqs(&seqs)) {
+            for entry in sstable.get(key)? {
+                if seqs.contains(&entry.seq_num) {
+                    history.push(self.resolve_value(entry)?);
+                }
+            }
+            history.extend(Self::range_deletions_of(sstable.range_tombstones(), key, &seqs));
+        }
+
+        history.sort_by_key(|e| std::cmp::Reverse(e.seq_num));
+        Ok(history)
+    }
+
+    /// Tombstones for the range deletions covering `key` with a sequence number in `seqs`.
+    fn range_deletions_of<'a>(
+        tombstones: &'a FragmentedRangeTombstones,
+        key: &'a Key,
+        seqs: &'a impl RangeBounds<SeqNum>,
+    ) -> impl Iterator<Item = Entry> + 'a {
+        tombstones
+            .covering_seqs(key)
+            .iter()
+            .filter(|seq| seqs.contains(seq))
+            .map(|&seq| Entry::delete(key.clone(), seq))
+    }
+
+    /// Range deletions written after sequence number `seq_num`, oldest first. These
+    /// complete `scan_changes_since`, which only has point entries.
+    pub fn range_deletions_since(&self, seq_num: SeqNum) -> Vec<RangeTombstone> {
+        let cf = &self.default_cf;
+        let memtable = cf.memtable.read().unwrap();
+        let version = cf.super_version();
+        let mut deletions: Vec<RangeTombstone> = version
+            .memtables(&memtable)
+            .into_iter()
+            .map(|m| m.range_tombstones())
+            .chain(version.sstables.iter().map(|s| s.range_tombstones()))
+            .flat_map(|tombstones| tombstones.tombstones().iter().filter(|t| t.seq_num > seq_num).cloned())
+            .collect();
+        deletions.sort_by_key(|t| t.seq_num);
+        deletions
+    }
+
+    /// Scan every entry written after sequence number `seq_num`, in key order
+    /// (newest version first), including tombstones. Tables holding only older
+    /// entries are not read. Range deletions have no single key, so they are not in
+    /// the stream; `range_deletions_since` returns them.
+    pub fn scan_changes_since(&self, seq_num: SeqNum) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
+        let cf = &self.default_cf;
+        let mut sources: Vec<Box<dyn Iterator<Item = Entry>>> = Vec::new();
+        let changed = (Bound::Excluded(seq_num), Bound::Unbounded);
+
+        let version = {
+            let memtable = cf.memtable.read().unwrap();
+            let version = cf.super_version();
+            for memtable in version.memtables(&memtable) {
+                let entries: Vec<Entry> = memtable.iter().filter(|e| e.seq_num > seq_num).collect();
+                sources.push(Box::new(entries.into_iter()));
+            }
+            version
+        };
+
+        for sstable in version.sstables.iter().filter(|s| s.might_contain_seqs(&changed)) {
+            let mut entries = Vec::new();
+            for entry in sstable.iter() {
+                let entry = entry?;
+                if entry.seq_num > seq_num {
+                    entries.push(self.resolve_value(entry)?);
+                }
+            }
+            sources.push(Box::new(entries.into_iter()));
+        }
+
+        Ok(MergeIterator::with_comparator(sources, cf.config.comparator.clone()))
+    }
+
+    /// Scan with only latest versions (no duplicates), with merge operands applied.
+    pub fn scan_latest(&self) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
+        self.scan_latest_cf(&self.default_cf)
+    }
+
+    /// Scan a column family with only latest versions, with merge operands applied.
+    pub fn scan_latest_cf(&self, cf: &ColumnFamily) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
+        Ok(MergeResolveIterator::new(self.scan_cf(cf)?, cf.config.merge_operator.clone()))
+    }
+
+    /// Scan with only live entries (no tombstones).
+    pub fn scan_live(&self) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
+        self.scan_live_cf(&self.default_cf)
+    }
+
+    /// Scan a column family with only live entries.
+    pub fn scan_live_cf(&self, cf: &ColumnFamily) -> Result<impl Iterator<Item = Entry>, std::io::Error> {
+        Ok(LiveEntriesIterator::new(self.scan_latest_cf(cf)?))
+    }
+
+    fn maybe_flush(&self, cf: &ColumnFamily) -> Result<(), std::io::Error> {
+        let should_flush = {
+            let memtable = cf.memtable.read().unwrap();
+            memtable.size_bytes() >= cf.config.memtable_size_threshold
+        };
+
+        if should_flush {
+            self.flush_cf(cf)?;
+        }
+
+        Ok(())
+    }
+
+    fn maybe_compact(&self, cf: &ColumnFamily) -> Result<(), std::io::Error> {
+        let Some(trigger) = cf.config.l0_compaction_trigger else {
+            return Ok(());
+        };
+        let l0_tables = cf.super_version().sstables.iter().filter(|s| s.level == FLUSH_LEVEL).count();
+        if l0_tables >= trigger {
+            self.compact_cf(cf)?;
+        }
+        Ok(())
+    }
+
+    /// Start writing a new SSTable of a column family at `level`, with its options and collectors.
+    fn new_table_writer(
+        &self,
+        cf: &ColumnFamily,
+        sstable_id: u64,
+        level: usize,
+    ) -> Result<SSTableWriter, std::io::Error> {
+        let mut writer =
+            SSTableWriter::with_options(self.buffer_pool.clone(), sstable_id, cf.config.table_options(level))?;
+        for factory in &cf.config.table_properties_collectors {
+            writer.add_collector(factory.create());
+        }
+        Ok(writer)
+    }
+
+    /// Force flush the memtable to an SSTable, compacting afterwards if level 0
+    /// reached `l0_compaction_trigger`.
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        self.flush_cf(&self.default_cf)
+    }
+
+    /// Force flush the memtable of a column family, compacting afterwards if its
+    /// level 0 reached `l0_compaction_trigger`.
+    pub fn flush_cf(&self, cf: &ColumnFamily) -> Result<(), std::io::Error> {
+        self.flush_memtable(cf)?;
+        self.maybe_compact(cf)
+    }
+
+    fn flush_memtable(&self, cf: &ColumnFamily) -> Result<(), std::io::Error> {
+        let _maintenance = self.maintenance_lock.lock().unwrap();
+
+        // Seal the active memtable; reads find its writes in the super version
+        // while it is written out
+        {
+            let mut memtable = cf.memtable.write().unwrap();
+            if !memtable.is_empty() {
+                // Preserve sequence number across flushes
+                let fresh = MemTable::with_seq_counter(memtable.seq_counter(), cf.config.comparator.clone());
+                let sealed = Arc::new(std::mem::replace(&mut *memtable, fresh));
+                cf.edit_super_version(|version| SuperVersion {
+                    immutable_memtables: std::iter::once(sealed)
+                        .chain(version.immutable_memtables.iter().cloned())
+                        .collect(),
+                    sstables: version.sstables.clone(),
+                });
+                // Writes to the fresh memtable are logged to a new segment, so the
+                // sealed memtable's segment can go once it is written out
+                self.wal.write().unwrap().rotate()?;
+            }
+        }
+
+        // Write out the sealed memtables oldest first, including any a failed
+        // flush left behind, so the flushed sequence number only moves forward
+        let mut flushed = false;
+        while let Some(sealed) = cf.super_version().immutable_memtables.last().cloned() {
+            self.write_sealed_memtable(cf, &sealed)?;
+            flushed = true;
+        }
+        if !flushed {
+            return Ok(());
+        }
+
+        // Delete the WAL segments whose writes every family flushed
+        let flushed_seqs: HashMap<u32, SeqNum> = self
+            .column_families
+            .read()
+            .unwrap()
+            .values()
+            .map(|cf| (cf.id, cf.flushed_seq.load(Ordering::SeqCst)))
+            .collect();
+        self.wal.write().unwrap().purge(|cf_id| flushed_seqs.get(&cf_id).copied())
+    }
+
+    /// Write a sealed memtable to a new SSTable, and swap the table in for it.
+    fn write_sealed_memtable(&self, cf: &ColumnFamily, sealed: &Arc<MemTable>) -> Result<(), std::io::Error> {
+        let entries: Vec<Entry> = sealed.iter().collect();
+        let memtable_range_tombstones = sealed.range_tombstones().clone();
+        let range_tombstones = memtable_range_tombstones.tombstones().to_vec();
+        let seq_times = sealed.seq_times().clone();
+        let flushed_seq = entries
+            .iter()
+            .map(|e| e.seq_num)
+            .chain(range_tombstones.iter().map(|t| t.seq_num))
+            .max()
+            .unwrap_or(0);
+
+        // Create new SSTable
+        let sstable_id = self.next_sstable_id.fetch_add(1, Ordering::SeqCst);
+        
+        {
+            let mut writer = self.new_table_writer(cf, sstable_id, FLUSH_LEVEL)?;
+            writer.set_seq_times(seq_times.clone());
+            for tombstone in range_tombstones {
+                writer.add_range_tombstone(tombstone);
+            }
+            let snapshots = self.snapshots.seqs();
+            let now = unix_now();
+            let collapsed = MergeCollapseIterator::new(
+                entries.into_iter(),
+                cf.config.merge_operator.as_ref(),
+                &memtable_range_tombstones,
+                &snapshots,
+                false,
+                now,
+            );
+            let retained = RetentionIterator::new(collapsed, &cf.config.retention, seq_times, snapshots.clone(), now);
+            let mut blob_writer = None;
+            for entry in retained {
+                match (&entry.value, cf.config.blob_value_threshold) {
+                    (Some(value), Some(threshold)) if entry.value_kind == ValueKind::Inline && value.len() >= threshold => {
+                        if blob_writer.is_none() {
+                            blob_writer = Some(self.blob_store.new_file()?);
+                        }
+                        let blob_ref = blob_writer.as_mut().unwrap().append(value.as_bytes())?;
+                        let blob_index = Entry::blob_index(entry.key.clone(), entry.seq_num, &blob_ref);
+                        writer.write_entry(&blob_index.with_expiry(entry.expires_at))?;
+                    }
+                    _ => writer.write_entry(&entry)?,
+                }
+            }
+            writer.finish()?;
+            // Blob values must be durable before the table referencing them is
+            if let Some(blob_writer) = blob_writer {
+                blob_writer.finish()?;
+            }
+        }
+
+        // Flush buffer pool to ensure SSTable is persisted
+        self.buffer_pool.flush()?;
+
+        // Open the new SSTable for reading
+        let reader = SSTableReader::open_with_comparator(self.buffer_pool.clone(), sstable_id, cf.config.comparator.clone())?;
+
+        // Swap the table in for the sealed memtable, at the front (newest)
+        cf.edit_super_version(|version| SuperVersion {
+            immutable_memtables: version
+                .immutable_memtables
+                .iter()
+                .filter(|memtable| !Arc::ptr_eq(memtable, sealed))
+                .cloned()
+                .collect(),
+            sstables: std::iter::once(Arc::new(reader)).chain(version.sstables.iter().cloned()).collect(),
+        });
+        cf.flushed_seq.store(flushed_seq, Ordering::SeqCst);
+
+        // Update manifest
+        self.save_manifest()
+    }
+
+    /// Garbage collect blob files.
+    ///
+    /// Blob files no table references anymore are deleted. Blob files whose live bytes
+    /// fall below `blob_gc_live_ratio` of their size have their live values copied to a
+    /// new blob file; every table referencing them is rewritten to point at the new
+    /// locations before the old file is deleted.
+    pub fn collect_blob_garbage(&self) -> Result<BlobGcStats, std::io::Error> {
+        let _maintenance = self.maintenance_lock.lock().unwrap();
+        let mut stats = BlobGcStats::default();
+
+        // Live bytes per blob file, and the tables referencing each file. Retired
+        // tables still read through an old super version keep their blob files too.
+        let families: Vec<(Arc<ColumnFamily>, Arc<SuperVersion>)> = self
+            .column_families
+            .read()
+            .unwrap()
+            .values()
+            .map(|cf| (cf.clone(), cf.super_version()))
+            .collect();
+        let obsolete = self.live_obsolete_tables();
+        let mut live_bytes: HashMap<u64, u64> = HashMap::new();
+        let mut referencing_tables: HashMap<u64, BTreeSet<u64>> = HashMap::new();
+        let all_tables = families.iter().flat_map(|(_, version)| version.sstables.iter()).chain(obsolete.iter());
+        for sstable in all_tables {
+            for entry in sstable.iter() {
+                if let Some(blob_ref) = entry?.blob_ref()? {
+                    *live_bytes.entry(blob_ref.file_id).or_default() += blob_ref.record_size();
+                    referencing_tables.entry(blob_ref.file_id).or_default().insert(sstable.meta.id);
+                }
+            }
+        }
+        drop(obsolete);
+
+        let mut victims = HashSet::new();
+        for file_id in self.blob_store.file_ids()? {
+            let file_size = self.blob_store.file_size(file_id)?;
+            let live = live_bytes.get(&file_id).copied().unwrap_or(0);
+            if live == 0 {
+                self.blob_store.delete_file(file_id)?;
+                stats.files_deleted += 1;
+                stats.bytes_reclaimed += file_size;
+            } else if (live as f64) < file_size as f64 * self.config.blob_gc_live_ratio {
+                victims.insert(file_id);
+            }
+        }
+        if victims.is_empty() {
+            return Ok(stats);
+        }
+
+        // Copy the live values of the victims and rewrite the tables referencing them
+        let tables_to_rewrite: BTreeSet<u64> = victims
+            .iter()
+            .flat_map(|file_id| referencing_tables[file_id].iter().copied())
+            .collect();
+        let mut blob_writer = self.blob_store.new_file()?;
+        let mut replacements = Vec::new();
+        for (cf, version) in &families {
+            for sstable in version.sstables.iter().filter(|s| tables_to_rewrite.contains(&s.meta.id)) {
+                let new_id = self.next_sstable_id.fetch_add(1, Ordering::SeqCst);
+                let mut writer = self.new_table_writer(cf, new_id, sstable.level)?;
+                writer.set_seq_times(sstable.properties().seq_times.clone());
+                for tombstone in sstable.range_tombstones().tombstones() {
+                    writer.add_range_tombstone(tombstone.clone());
+                }
+                for entry in sstable.iter() {
+                    let entry = entry?;
+                    match entry.blob_ref()? {
+                        Some(blob_ref) if victims.contains(&blob_ref.file_id) => {
+                            let value = self.blob_store.read(&blob_ref)?;
+                            let new_ref = blob_writer.append(value.as_bytes())?;
+                            let blob_index = Entry::blob_index(entry.key, entry.seq_num, &new_ref);
+                            writer.write_entry(&blob_index.with_expiry(entry.expires_at))?;
+                        }
+                        _ => writer.write_entry(&entry)?,
+                    }
+                }
+                writer.finish()?;
+                replacements.push((cf.clone(), sstable.clone(), new_id));
+            }
+        }
+        blob_writer.finish()?;
+        self.buffer_pool.flush()?;
+
+        // Swap the rewritten tables in at the same positions, keeping the newest-first order
+        for (cf, old, new_id) in &replacements {
+            let mut reader =
+                SSTableReader::open_with_comparator(self.buffer_pool.clone(), *new_id, cf.config.comparator.clone())?;
+            reader.level = old.level;
+            let reader = Arc::new(reader);
+            cf.edit_super_version(|version| SuperVersion {
+                immutable_memtables: version.immutable_memtables.clone(),
+                sstables: version
+                    .sstables
+                    .iter()
+                    .map(|s| if Arc::ptr_eq(s, old) { reader.clone() } else { s.clone() })
+                    .collect(),
+            });
+        }
+        self.save_manifest()?;
+
+        for (_, old, _) in &replacements {
+            self.retire_table(old);
+        }
+        drop(replacements);
+        drop(families);
+
+        // A victim still referenced by a retired table in use is deleted by a later run
+        let in_use: HashSet<u64> = self.live_obsolete_tables().iter().map(|s| s.meta.id).collect();
+        for file_id in victims {
+            stats.files_rewritten += 1;
+            if referencing_tables[&file_id].iter().any(|id| in_use.contains(id)) {
+                continue;
+            }
+            stats.bytes_reclaimed += self.blob_store.file_size(file_id)?;
+            self.blob_store.delete_file(file_id)?;
+            stats.files_deleted += 1;
+        }
+
+        Ok(stats)
+    }
+
+    /// Merge all SSTables into a single table on the bottommost level, dropping
+    /// entries deleted by range or point tombstones that no snapshot needs.
+    pub fn compact(&self) -> Result<CompactionStats, std::io::Error> {
+        self.compact_cf(&self.default_cf)
+    }
+
+    /// Merge all SSTables of a column family into a single table on the bottommost level.
+    pub fn compact_cf(&self, cf: &ColumnFamily) -> Result<CompactionStats, std::io::Error> {
+        let _maintenance = self.maintenance_lock.lock().unwrap();
+        let mut stats = CompactionStats::default();
+
+        let output_id = self.next_sstable_id.fetch_add(1, Ordering::SeqCst);
+        let version = cf.super_version();
+        let input_ids: HashSet<u64>;
+        let output_entries;
+        {
+            let sstables = &version.sstables;
+            if sstables.is_empty() {
+                return Ok(stats);
+            }
+            input_ids = sstables.iter().map(|s| s.meta.id).collect();
+            stats.input_tables = sstables.len();
+
+            // The output holds everything older than the memtable, so range
+            // tombstones are applied here and only carried over for snapshots
+            let range_tombstones = FragmentedRangeTombstones::with_comparator(
+                sstables
+                    .iter()
+                    .flat_map(|s| s.range_tombstones().tombstones().iter().cloned())
+                    .collect(),
+                cf.config.comparator.clone(),
+            );
+            let snapshots = self.snapshots.seqs();
+            let mut seq_times = SeqTimeMapping::default();
+            for sstable in sstables.iter() {
+                seq_times.merge(&sstable.properties().seq_times);
+            }
+            let mut writer = self.new_table_writer(cf, output_id, BOTTOMMOST_LEVEL)?;
+            writer.set_seq_times(seq_times.clone());
+            for tombstone in retained_range_tombstones(&range_tombstones, &snapshots) {
+                writer.add_range_tombstone(tombstone);
+            }
+
+            let error = SourceError::default();
+            let sources = sstables
+                .iter()
+                .map(|s| TableSource::new(s.iter(), error.clone()))
+                .collect();
+            let now = unix_now();
+            let merged = CompactionIterator::new(
+                MergeIterator::with_comparator(sources, cf.config.comparator.clone()),
+                range_tombstones.clone(),
+                snapshots.clone(),
+                true,
+                now,
+            );
+            let collapsed = MergeCollapseIterator::new(
+                merged,
+                cf.config.merge_operator.as_ref(),
+                &range_tombstones,
+                &snapshots,
+                true,
+                now,
+            );
+            let mut entries = RetentionIterator::new(
+                collapsed,
+                &cf.config.retention,
+                seq_times,
+                snapshots.clone(),
+                now,
+            );
+            // Sequence number of the newer version of the current key
+            let mut newer: Option<(Key, SeqNum)> = None;
+            for entry in &mut entries {
+                let newer_seq = match &newer {
+                    Some((key, seq)) if *key == entry.key => *seq,
+                    _ => SeqNum::MAX,
+                };
+                newer = Some((entry.key.clone(), entry.seq_num));
+                let entry = match &cf.config.compaction_filter {
+                    Some(filter) if !snapshot_between(&snapshots, entry.seq_num, newer_seq) => {
+                        match self.apply_compaction_filter(filter.as_ref(), BOTTOMMOST_LEVEL, true, entry)? {
+                            (Some(entry), changed) => {
+                                stats.values_changed += changed as u64;
+                                entry
+                            }
+                            (None, _) => {
+                                stats.entries_filtered += 1;
+                                continue;
+                            }
+                        }
+                    }
+                    _ => entry,
+                };
+                writer.write_entry(&entry)?;
+                stats.entries_written += 1;
+            }
+            if let Some(e) = error.borrow_mut().take() {
+                return Err(e);
+            }
+            stats.entries_dropped = entries.inner().inner().entries_dropped();
+            stats.entries_reclaimed = entries.inner().inner().entries_reclaimed();
+            stats.entries_expired = entries.inner().inner().entries_expired();
+            stats.operands_merged = entries.inner().operands_merged();
+            stats.versions_pruned = entries.versions_pruned();
+            let meta = writer.finish()?;
+            output_entries = meta.entry_count + meta.properties.num_range_deletions;
+        }
+        self.buffer_pool.flush()?;
+
+        // Swap in the output; it is older than any table flushed meanwhile
+        let output = if output_entries > 0 {
+            let mut reader =
+                SSTableReader::open_with_comparator(self.buffer_pool.clone(), output_id, cf.config.comparator.clone())?;
+            reader.level = BOTTOMMOST_LEVEL;
+            Some(Arc::new(reader))
+        } else {
+            None
+        };
+        cf.edit_super_version(|current| SuperVersion {
+            immutable_memtables: current.immutable_memtables.clone(),
+            sstables: current
+                .sstables
+                .iter()
+                .filter(|s| !input_ids.contains(&s.meta.id))
+                .cloned()
+                .chain(output)
+                .collect(),
+        });
+        self.save_manifest()?;
+
+        for sstable in version.sstables.iter() {
+            self.retire_table(sstable);
+        }
+        if output_entries == 0 {
+            self.buffer_pool.remove_file(output_id)?;
+        }
+        self.entries_reclaimed.fetch_add(stats.entries_reclaimed, Ordering::Relaxed);
+
+        Ok(stats)
+    }
+
+    /// Take a table out of use. Its file is deleted once no super version references it.
+    fn retire_table(&self, table: &Arc<SSTableReader>) {
+        table.mark_obsolete();
+        let mut obsolete = self.obsolete_tables.lock().unwrap();
+        obsolete.retain(|table| table.strong_count() > 0);
+        obsolete.push(Arc::downgrade(table));
+    }
+
+    /// Retired tables still referenced by a super version.
+    fn live_obsolete_tables(&self) -> Vec<Arc<SSTableReader>> {
+        self.obsolete_tables.lock().unwrap().iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Run the compaction filter on an entry. Returns the entry to write, if any,
+    /// and whether its value was changed.
+    fn apply_compaction_filter(
+        &self,
+        filter: &dyn CompactionFilter,
+        level: usize,
+        bottommost: bool,
+        entry: Entry,
+    ) -> Result<(Option<Entry>, bool), std::io::Error> {
+        if entry.is_tombstone() {
+            return Ok((Some(entry), false));
+        }
+        let decision = match entry.value_kind {
+            ValueKind::BlobIndex => filter.filter(level, bottommost, &self.resolve_value(entry.clone())?),
+            _ => filter.filter(level, bottommost, &entry),
+        };
+        Ok(match decision {
+            CompactionDecision::Keep => (Some(entry), false),
+            CompactionDecision::Remove => (None, false),
+            CompactionDecision::ChangeValue(value) => {
+                let changed = match entry.value_kind {
+                    ValueKind::Merge => Entry::merge(entry.key, entry.seq_num, value),
+                    _ => Entry::put(entry.key, entry.seq_num, value),
+                };
+                (Some(changed.with_expiry(entry.expires_at)), true)
+            }
+        })
+    }
+
+    /// Properties of all live SSTables, by table id (newest first within each
+    /// column family).
+    pub fn table_properties(&self) -> Vec<(u64, TableProperties)> {
+        let families = self.column_families.read().unwrap();
+        let mut properties = Vec::new();
+        for cf in families.values() {
+            let version = cf.super_version();
+            properties.extend(version.sstables.iter().map(|sstable| (sstable.meta.id, sstable.properties().clone())));
+        }
+        properties
+    }
+
+    /// Get statistics about the default column family of the LSM tree.
+    pub fn stats(&self) -> LsmStats {
+        let cf = &self.default_cf;
+        let memtable = cf.memtable.read().unwrap();
+        let version = cf.super_version();
+        let sstables = &version.sstables;
+
+        let uncompressed_bytes = sstables.iter().map(|s| s.meta.properties.uncompressed_data_size).sum::<u64>();
+        let compressed_bytes = sstables.iter().map(|s| s.meta.properties.data_size).sum::<u64>();
+
+        LsmStats {
+            memtable_entries: memtable.len(),
+            memtable_size_bytes: memtable.size_bytes(),
+            sstable_count: sstables.len(),
+            total_entries: sstables.iter().map(|s| s.meta.entry_count).sum::<u64>() as usize
+                + memtable.len(),
+            uncompressed_bytes,
+            compressed_bytes,
+            compression_ratio: if compressed_bytes == 0 {
+                1.0
+            } else {
+                uncompressed_bytes as f64 / compressed_bytes as f64
+            },
+            entries_reclaimed: self.entries_reclaimed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Statistics about the LSM tree.
+#[derive(Debug, Clone)]
+pub struct LsmStats {
+    pub memtable_entries: usize,
+    pub memtable_size_bytes: usize,
+    pub sstable_count: usize,
+    pub total_entries: usize,
+    /// Size of SSTable blocks before compression.
+    pub uncompressed_bytes: u64,
+    /// Size of SSTable blocks as stored.
+    pub compressed_bytes: u64,
+    /// `uncompressed_bytes / compressed_bytes` (1.0 when there are no tables).
+    pub compression_ratio: f64,
+    /// Tombstones and shadowed versions dropped by compaction since open.
+    pub entries_reclaimed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::comparator::ReverseBytewiseComparator;
+    use super::super::external_sst::ExternalSstWriter;
+    use super::super::properties::TablePropertiesCollector;
+    use super::super::retention::RetentionPolicy;
+    use std::collections::BTreeMap;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn get_temp_dir() -> PathBuf {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        PathBuf::from(format!("/tmp/thordb_lsm_test_{}", since_epoch.as_nanos()))
+    }
+
+    #[test]
+    fn test_basic_put_get() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+
+        let lsm = LsmTree::open(config).unwrap();
+
+        lsm.put(Key::from("key1"), Value::from("value1")).unwrap();
+        lsm.put(Key::from("key2"), Value::from("value2")).unwrap();
+
+        assert_eq!(
+            lsm.get(&Key::from("key1")).unwrap().unwrap().as_bytes(),
+            b"value1"
+        );
+        assert_eq!(
+            lsm.get(&Key::from("key2")).unwrap().unwrap().as_bytes(),
+            b"value2"
+        );
+        assert!(lsm.get(&Key::from("key3")).unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+
+        let lsm = LsmTree::open(config).unwrap();
+
+        lsm.put(Key::from("key"), Value::from("v1")).unwrap();
+        lsm.put(Key::from("key"), Value::from("v2")).unwrap();
+
+        // Get returns latest
+        assert_eq!(
+            lsm.get(&Key::from("key")).unwrap().unwrap().as_bytes(),
+            b"v2"
+        );
+
+        // Get all returns both
+        let all = lsm.get_all(&Key::from("key")).unwrap();
+        assert_eq!(all.len(), 2);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_delete() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+
+        let lsm = LsmTree::open(config).unwrap();
+
+        lsm.put(Key::from("key"), Value::from("value")).unwrap();
+        lsm.delete(Key::from("key")).unwrap();
+
+        assert!(lsm.get(&Key::from("key")).unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_flush_and_recover() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            memtable_size_threshold: 100, // Small threshold for testing
+            ..Default::default()
+        };
+
+        // Write and flush
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            lsm.put(Key::from("key1"), Value::from("value1")).unwrap();
+            lsm.put(Key::from("key2"), Value::from("value2")).unwrap();
+            lsm.flush().unwrap();
+        }
+
+        // Reopen and verify
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            assert_eq!(
+                lsm.get(&Key::from("key1")).unwrap().unwrap().as_bytes(),
+                b"value1"
+            );
+            assert_eq!(
+                lsm.get(&Key::from("key2")).unwrap().unwrap().as_bytes(),
+                b"value2"
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+
+        let lsm = LsmTree::open(config).unwrap();
+
+        lsm.put(Key::from("c"), Value::from("3")).unwrap();
+        lsm.put(Key::from("a"), Value::from("1")).unwrap();
+        lsm.put(Key::from("b"), Value::from("2")).unwrap();
+
+        let entries: Vec<_> = lsm.scan_live().unwrap().collect();
+        let keys: Vec<_> = entries.iter().map(|e| e.key.as_bytes()).collect();
+
+        assert_eq!(keys, vec![b"a", b"b", b"c"]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_direct_io_flush_and_recover() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            direct_io: true,
+            ..Default::default()
+        };
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            for i in 0..100 {
+                lsm.put(Key::from(format!("key{:03}", i).as_str()), Value::from("value")).unwrap();
+            }
+            lsm.flush().unwrap();
+        }
+
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            assert_eq!(lsm.get(&Key::from("key042")).unwrap().unwrap().as_bytes(), b"value");
+            assert_eq!(lsm.scan_live().unwrap().count(), 100);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_large_values_survive_flush() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+        let large = Value::new((0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect());
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            lsm.put(Key::from("big"), large.clone()).unwrap();
+            lsm.put(Key::from("small"), Value::from("v")).unwrap();
+            lsm.flush().unwrap();
+        }
+
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            assert_eq!(lsm.get(&Key::from("big")).unwrap().unwrap(), large);
+            let entries: Vec<_> = lsm.scan_live().unwrap().collect();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].value.as_ref().unwrap(), &large);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_blob_separation() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            blob_value_threshold: Some(1024),
+            ..Default::default()
+        };
+        let large = |i: usize| Value::new(vec![i as u8; 8 * 1024]);
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            for i in 0..10 {
+                lsm.put(Key::from(format!("key{}", i).as_str()), large(i)).unwrap();
+            }
+            lsm.put(Key::from("small"), Value::from("inline")).unwrap();
+            lsm.flush().unwrap();
+            assert_eq!(lsm.blob_store.file_ids().unwrap().len(), 1);
+        }
+
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            assert_eq!(lsm.get(&Key::from("key3")).unwrap().unwrap(), large(3));
+            assert_eq!(lsm.get(&Key::from("small")).unwrap().unwrap().as_bytes(), b"inline");
+            assert_eq!(lsm.get_all(&Key::from("key7")).unwrap()[0].value, Some(large(7)));
+            let entries: Vec<_> = lsm.scan_live().unwrap().collect();
+            assert_eq!(entries.len(), 11);
+            assert_eq!(entries[9].value, Some(large(9)));
+            assert!(entries.iter().all(|e| e.value_kind == ValueKind::Inline));
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_blob_garbage_collection() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            blob_value_threshold: Some(1024),
+            // Every file is below this ratio, so GC rewrites all of them
+            blob_gc_live_ratio: 2.0,
+            ..Default::default()
+        };
+        let large = |i: usize| Value::new(vec![i as u8; 4 * 1024]);
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            for round in 0..2 {
+                for i in 0..5 {
+                    lsm.put(Key::from(format!("key{}", i).as_str()), large(round * 10 + i)).unwrap();
+                }
+                lsm.flush().unwrap();
+            }
+            // A blob file left behind without any table referencing it
+            let mut orphan = lsm.blob_store.new_file().unwrap();
+            orphan.append(&[0u8; 2048]).unwrap();
+            orphan.finish().unwrap();
+
+            let old_files = lsm.blob_store.file_ids().unwrap();
+            let stats = lsm.collect_blob_garbage().unwrap();
+            assert_eq!(stats.files_rewritten, 2);
+            assert_eq!(stats.files_deleted, 3);
+
+            let new_files = lsm.blob_store.file_ids().unwrap();
+            assert_eq!(new_files.len(), 1);
+            assert!(!old_files.contains(&new_files[0]));
+
+            assert_eq!(lsm.get(&Key::from("key2")).unwrap().unwrap(), large(12));
+            assert_eq!(lsm.get_all(&Key::from("key2")).unwrap()[1].value, Some(large(2)));
+        }
+
+        // The rewritten tables keep their place in the manifest
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            assert_eq!(lsm.get(&Key::from("key4")).unwrap().unwrap(), large(14));
+            assert_eq!(lsm.get_all(&Key::from("key4")).unwrap().len(), 2);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_block_compression() {
+        for compression in [CompressionType::Lz4, CompressionType::Zstd] {
+            let dir = get_temp_dir();
+            let config = LsmConfig {
+                data_dir: dir.clone(),
+                compression_per_level: vec![compression],
+                ..Default::default()
+            };
+            let value = |i: usize| Value::from(format!("value-{}-{}", i, "x".repeat(100)).as_str());
+
+            {
+                let lsm = LsmTree::open(config.clone()).unwrap();
+                for i in 0..1000 {
+                    lsm.put(Key::from(format!("key{:05}", i).as_str()), value(i)).unwrap();
+                }
+                lsm.flush().unwrap();
+
+                let stats = lsm.stats();
+                assert!(stats.compressed_bytes < stats.uncompressed_bytes);
+                assert!(stats.compression_ratio > 2.0);
+            }
+
+            {
+                let lsm = LsmTree::open(config).unwrap();
+                assert_eq!(lsm.get(&Key::from("key00500")).unwrap().unwrap(), value(500));
+                assert_eq!(lsm.scan_live().unwrap().count(), 1000);
+                assert!(lsm.stats().compression_ratio > 2.0);
+            }
+
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    /// Counts keys per tenant, where the tenant is the key part before the first '/'.
+    struct TenantCounter(BTreeMap<String, u64>);
+
+    impl TablePropertiesCollector for TenantCounter {
+        fn name(&self) -> &str {
+            "tenant_counter"
+        }
+
+        fn add(&mut self, entry: &Entry) {
+            let key = String::from_utf8_lossy(entry.key.as_bytes());
+            let tenant = key.split('/').next().unwrap_or_default();
+            *self.0.entry(format!("tenant.{}", tenant)).or_default() += 1;
+        }
+
+        fn finish(&mut self) -> BTreeMap<String, Vec<u8>> {
+            std::mem::take(&mut self.0)
+                .into_iter()
+                .map(|(name, count)| (name, count.to_le_bytes().to_vec()))
+                .collect()
+        }
+    }
+
+    struct TenantCounterFactory;
+
+    impl TablePropertiesCollectorFactory for TenantCounterFactory {
+        fn create(&self) -> Box<dyn TablePropertiesCollector> {
+            Box::new(TenantCounter(BTreeMap::new()))
+        }
+    }
+
+    #[test]
+    fn test_table_properties_collector() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            table_properties_collectors: vec![Arc::new(TenantCounterFactory)],
+            ..Default::default()
+        };
+        let count = |properties: &TableProperties, tenant: &str| {
+            properties.user_collected.get(tenant).map(|v| u64::from_le_bytes(v[..8].try_into().unwrap()))
+        };
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            for i in 0..30 {
+                lsm.put(Key::from(format!("acme/{}", i).as_str()), Value::from("v")).unwrap();
+            }
+            lsm.put(Key::from("globex/1"), Value::from("v")).unwrap();
+            lsm.flush().unwrap();
+            lsm.put(Key::from("globex/2"), Value::from("v")).unwrap();
+            lsm.flush().unwrap();
+        }
+
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            let tables = lsm.table_properties();
+            assert_eq!(tables.len(), 2);
+            // Newest first
+            assert_eq!(count(&tables[0].1, "tenant.globex"), Some(1));
+            assert_eq!(count(&tables[0].1, "tenant.acme"), None);
+            assert_eq!(count(&tables[1].1, "tenant.acme"), Some(30));
+            assert_eq!(count(&tables[1].1, "tenant.globex"), Some(1));
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+        let key = |i: usize| Key::from(format!("key{:02}", i).as_str());
+        let live_keys = |lsm: &LsmTree| lsm.scan_live().unwrap().map(|e| e.key).collect::<Vec<_>>();
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            for i in 0..10 {
+                lsm.put(key(i), Value::from("old")).unwrap();
+            }
+            lsm.flush().unwrap();
+            lsm.delete_range(key(2), key(5)).unwrap();
+            // Written after the tombstone, so not deleted by it
+            lsm.put(key(3), Value::from("new")).unwrap();
+            assert!(lsm.delete_range(key(5), key(5)).is_err());
+
+            assert_eq!(lsm.get(&key(2)).unwrap(), None);
+            assert_eq!(lsm.get(&key(3)).unwrap(), Some(Value::from("new")));
+            assert_eq!(lsm.get(&key(5)).unwrap(), Some(Value::from("old")));
+            assert_eq!(lsm.get_all(&key(3)).unwrap().len(), 1);
+            assert_eq!(live_keys(&lsm).len(), 8);
+        }
+
+        // Replayed from the WAL, then persisted in an SSTable
+        for flush in [false, true] {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            if flush {
+                lsm.flush().unwrap();
+            }
+            assert_eq!(lsm.get(&key(4)).unwrap(), None);
+            assert_eq!(lsm.get(&key(3)).unwrap(), Some(Value::from("new")));
+            assert_eq!(live_keys(&lsm), [0, 1, 3, 5, 6, 7, 8, 9].map(key));
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_compaction_drops_range_deleted_entries() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            l0_compaction_trigger: Some(3),
+            ..Default::default()
+        };
+        let key = |i: usize| Key::from(format!("key{:02}", i).as_str());
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            for i in 0..20 {
+                lsm.put(key(i), Value::from("v1")).unwrap();
+            }
+            lsm.flush().unwrap();
+            lsm.delete_range(key(0), key(10)).unwrap();
+            lsm.flush().unwrap();
+            lsm.put(key(15), Value::from("v2")).unwrap();
+            // The third level-0 table triggers compaction
+            lsm.flush().unwrap();
+
+            let sstables = lsm.default_cf.super_version().sstables.clone();
+            assert_eq!(sstables.len(), 1);
+            assert_eq!(sstables[0].level, BOTTOMMOST_LEVEL);
+            assert!(sstables[0].range_tombstones().is_empty());
+            assert_eq!(sstables[0].meta.entry_count, 11);
+        }
+
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            assert_eq!(lsm.default_cf.super_version().sstables[0].level, BOTTOMMOST_LEVEL);
+            assert_eq!(lsm.get(&key(5)).unwrap(), None);
+            assert_eq!(lsm.get(&key(15)).unwrap(), Some(Value::from("v2")));
+            assert_eq!(lsm.get_all(&key(15)).unwrap().len(), 2);
+            assert_eq!(lsm.scan_live().unwrap().count(), 10);
+
+            // Only newer data is left, so a second run drops nothing
+            let stats = lsm.compact().unwrap();
+            assert_eq!(stats.input_tables, 1);
+            assert_eq!(stats.entries_dropped, 0);
+            assert_eq!(stats.entries_written, 11);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn test_basic_put_get() {
+    fn test_tombstone_gc() {
         let dir = get_temp_dir();
         let config = LsmConfig {
             data_dir: dir.clone(),
             ..Default::default()
         };
+        let lsm = LsmTree::open(config).unwrap();
+
+        lsm.put(Key::from("a"), Value::from("a1")).unwrap();
+        lsm.put(Key::from("b"), Value::from("b1")).unwrap();
+        lsm.put(Key::from("b"), Value::from("b2")).unwrap();
+        lsm.flush().unwrap();
+        let snapshot = lsm.snapshot();
+        lsm.delete(Key::from("a")).unwrap();
+        lsm.delete(Key::from("b")).unwrap();
+        lsm.flush().unwrap();
+
+        // The snapshot still sees the old versions, so nothing is reclaimed
+        let stats = lsm.compact().unwrap();
+        assert_eq!(stats.entries_reclaimed, 0);
+        assert_eq!(lsm.get(&Key::from("b")).unwrap(), None);
+        assert_eq!(lsm.get_at(&Key::from("b"), &snapshot).unwrap(), Some(Value::from("b2")));
+
+        drop(snapshot);
+        let stats = lsm.compact().unwrap();
+        assert_eq!(stats.entries_reclaimed, 5);
+        assert_eq!(stats.entries_written, 0);
+        assert_eq!(lsm.stats().entries_reclaimed, 5);
+        assert_eq!(lsm.stats().sstable_count, 0);
+        assert!(lsm.get_all(&Key::from("a")).unwrap().is_empty());
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(dir);
+    }
 
+    #[test]
+    fn test_retention_policy_by_prefix() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            retention: RetentionPolicies::new(RetentionPolicy::KeepLast(2))
+                .with_prefix("audit/", RetentionPolicy::KeepAll),
+            ..Default::default()
+        };
         let lsm = LsmTree::open(config).unwrap();
 
-        lsm.put(Key::from("key1"), Value::from("value1")).unwrap();
-        lsm.put(Key::from("key2"), Value::from("value2")).unwrap();
+        for round in 0..3 {
+            for i in 0..4 {
+                lsm.put(Key::from("audit/x"), Value::from(format!("{}.{}", round, i).as_str())).unwrap();
+                lsm.put(Key::from("user/x"), Value::from(format!("{}.{}", round, i).as_str())).unwrap();
+            }
+            // Flush trims each memtable's history of user/x to two versions
+            lsm.flush().unwrap();
+            assert_eq!(lsm.get_all(&Key::from("user/x")).unwrap().len(), 2 * (round + 1));
+        }
 
-        assert_eq!(
-            lsm.get(&Key::from("key1")).unwrap().unwrap().as_bytes(),
-            b"value1"
-        );
-        assert_eq!(
-            lsm.get(&Key::from("key2")).unwrap().unwrap().as_bytes(),
-            b"value2"
-        );
-        assert!(lsm.get(&Key::from("key3")).unwrap().is_none());
+        let stats = lsm.compact().unwrap();
+        assert_eq!(stats.versions_pruned, 4);
+        let versions = lsm.get_all(&Key::from("user/x")).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].value, Some(Value::from("2.3")));
+        assert_eq!(lsm.get_all(&Key::from("audit/x")).unwrap().len(), 12);
+        assert!(!lsm.table_properties()[0].1.seq_times.is_empty());
 
+        drop(lsm);
         let _ = std::fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn test_overwrite() {
+    fn test_history_queries() {
         let dir = get_temp_dir();
         let config = LsmConfig {
             data_dir: dir.clone(),
             ..Default::default()
         };
-
         let lsm = LsmTree::open(config).unwrap();
 
-        lsm.put(Key::from("key"), Value::from("v1")).unwrap();
-        lsm.put(Key::from("key"), Value::from("v2")).unwrap();
-
-        // Get returns latest
-        assert_eq!(
-            lsm.get(&Key::from("key")).unwrap().unwrap().as_bytes(),
-            b"v2"
-        );
+        let first = lsm.put(Key::from("acct"), Value::from("100")).unwrap();
+        lsm.put(Key::from("other"), Value::from("x")).unwrap();
+        lsm.flush().unwrap();
+        let checkpoint = lsm.put(Key::from("acct"), Value::from("80")).unwrap();
+        lsm.delete(Key::from("other")).unwrap();
+        lsm.flush().unwrap();
+        let last = lsm.put(Key::from("acct"), Value::from("95")).unwrap();
 
-        // Get all returns both
-        let all = lsm.get_all(&Key::from("key")).unwrap();
-        assert_eq!(all.len(), 2);
+        let history = lsm.get_history(&Key::from("acct"), first..=last).unwrap();
+        let values: Vec<_> = history.iter().map(|e| e.value.clone().unwrap()).collect();
+        assert_eq!(values, [Value::from("95"), Value::from("80"), Value::from("100")]);
+        assert_eq!(lsm.get_history(&Key::from("acct"), ..checkpoint).unwrap().len(), 1);
 
+        // Only the newest table and the memtable hold changes after the checkpoint
+        {
+            let sstables = lsm.default_cf.super_version().sstables.clone();
+            assert!(sstables[0].might_contain_seqs(&(checkpoint..)));
+            assert!(!sstables[1].might_contain_seqs(&(checkpoint..)));
+        }
+        let changes: Vec<_> = lsm.scan_changes_since(checkpoint).unwrap().collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].seq_num, last);
+        assert!(changes[1].is_tombstone());
+
+        // Range deletions show up as tombstones in the history, and beside the changes
+        let range_seq = lsm.delete_range(Key::from("a"), Key::from("b")).unwrap();
+        lsm.flush().unwrap();
+        let history = lsm.get_history(&Key::from("acct"), checkpoint..).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].seq_num, range_seq);
+        assert!(history[0].is_tombstone());
+        assert_eq!(lsm.get_history(&Key::from("other"), range_seq..).unwrap().len(), 0);
+        let deletions = lsm.range_deletions_since(checkpoint);
+        assert_eq!(deletions, [RangeTombstone::new(Key::from("a"), Key::from("b"), range_seq)]);
+        assert!(lsm.range_deletions_since(range_seq).is_empty());
+
+        drop(lsm);
         let _ = std::fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn test_delete() {
+    fn test_put_with_ttl() {
         let dir = get_temp_dir();
         let config = LsmConfig {
             data_dir: dir.clone(),
             ..Default::default()
         };
+        let hour = Duration::from_secs(3600);
 
-        let lsm = LsmTree::open(config).unwrap();
-
-        lsm.put(Key::from("key"), Value::from("value")).unwrap();
-        lsm.delete(Key::from("key")).unwrap();
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            lsm.put(Key::from("session/a"), Value::from("old")).unwrap();
+            // A zero TTL expires immediately, hiding the older version too
+            lsm.put_with_ttl(Key::from("session/a"), Value::from("new"), Duration::ZERO).unwrap();
+            lsm.put_with_ttl(Key::from("session/b"), Value::from("live"), hour).unwrap();
+
+            assert_eq!(lsm.get(&Key::from("session/a")).unwrap(), None);
+            assert_eq!(lsm.get(&Key::from("session/b")).unwrap(), Some(Value::from("live")));
+            // The expired version reads as a tombstone over the old one
+            let versions = lsm.get_all(&Key::from("session/a")).unwrap();
+            assert_eq!(versions.len(), 2);
+            assert!(versions[0].is_tombstone());
+            assert_eq!(versions[1].value, Some(Value::from("old")));
+        }
 
-        assert!(lsm.get(&Key::from("key")).unwrap().is_none());
+        {
+            // Expiry survives WAL replay and flush
+            let lsm = LsmTree::open(config).unwrap();
+            lsm.flush().unwrap();
+            assert_eq!(lsm.get(&Key::from("session/a")).unwrap(), None);
+            let live: Vec<_> = lsm.scan_live().unwrap().map(|e| e.key).collect();
+            assert_eq!(live, [Key::from("session/b")]);
+            assert!(lsm.get_all(&Key::from("session/b")).unwrap()[0].expires_at.is_some());
+            assert!(lsm.get_all(&Key::from("session/a")).unwrap()[0].is_tombstone());
+
+            let stats = lsm.compact().unwrap();
+            assert_eq!(stats.entries_expired, 1);
+            assert_eq!(stats.entries_written, 1);
+            assert!(lsm.get_history(&Key::from("session/a"), ..).unwrap().is_empty());
+            assert!(lsm.get_all(&Key::from("session/a")).unwrap().is_empty());
+        }
 
         let _ = std::fs::remove_dir_all(dir);
     }
 
+    /// Adds decimal counters.
+    struct Counter;
+
+    impl MergeOperator for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn full_merge(&self, _key: &Key, existing: Option<&Value>, operands: &[Value]) -> Value {
+            let parse = |v: &Value| String::from_utf8_lossy(v.as_bytes()).parse::<i64>().unwrap();
+            let total: i64 = existing.map_or(0, parse) + operands.iter().map(parse).sum::<i64>();
+            Value::from(total.to_string().as_str())
+        }
+    }
+
     #[test]
-    fn test_flush_and_recover() {
+    fn test_merge_operator() {
         let dir = get_temp_dir();
         let config = LsmConfig {
             data_dir: dir.clone(),
-            memtable_size_threshold: 100, // Small threshold for testing
+            merge_operator: Some(Arc::new(Counter)),
             ..Default::default()
         };
+        let hits = Key::from("hits");
 
-        // Write and flush
         {
             let lsm = LsmTree::open(config.clone()).unwrap();
-            lsm.put(Key::from("key1"), Value::from("value1")).unwrap();
-            lsm.put(Key::from("key2"), Value::from("value2")).unwrap();
+            lsm.put(hits.clone(), Value::from("10")).unwrap();
+            lsm.merge(hits.clone(), Value::from("1")).unwrap();
             lsm.flush().unwrap();
+            lsm.merge(hits.clone(), Value::from("2")).unwrap();
+            lsm.merge(Key::from("new"), Value::from("5")).unwrap();
+            assert_eq!(lsm.get(&hits).unwrap(), Some(Value::from("13")));
         }
 
-        // Reopen and verify
         {
-            let lsm = LsmTree::open(config).unwrap();
-            assert_eq!(
-                lsm.get(&Key::from("key1")).unwrap().unwrap().as_bytes(),
-                b"value1"
-            );
+            // Operands survive WAL replay and fold across tables
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            assert_eq!(lsm.get(&hits).unwrap(), Some(Value::from("13")));
+            let live: Vec<_> = lsm.scan_live().unwrap().map(|e| (e.key, e.value)).collect();
             assert_eq!(
-                lsm.get(&Key::from("key2")).unwrap().unwrap().as_bytes(),
-                b"value2"
+                live,
+                [(hits.clone(), Some(Value::from("13"))), (Key::from("new"), Some(Value::from("5")))]
             );
+
+            lsm.flush().unwrap();
+            let stats = lsm.compact().unwrap();
+            assert!(stats.operands_merged > 0);
+            assert_eq!(lsm.get(&hits).unwrap(), Some(Value::from("13")));
+            assert_eq!(lsm.get_all(&hits).unwrap()[0].value_kind, ValueKind::Inline);
+        }
+
+        let without_operator = LsmTree::open(LsmConfig {
+            merge_operator: None,
+            ..config
+        })
+        .unwrap();
+        assert!(without_operator.merge(hits, Value::from("1")).is_err());
+
+        drop(without_operator);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Drops `inactive/` keys and upper-cases other values.
+    struct Deactivate;
+
+    impl CompactionFilter for Deactivate {
+        fn name(&self) -> &str {
+            "deactivate"
+        }
+
+        fn filter(&self, level: usize, bottommost: bool, entry: &Entry) -> CompactionDecision {
+            assert_eq!((level, bottommost), (BOTTOMMOST_LEVEL, true));
+            if entry.key.as_bytes().starts_with(b"inactive/") {
+                return CompactionDecision::Remove;
+            }
+            let value = entry.value.as_ref().unwrap().as_bytes().to_ascii_uppercase();
+            CompactionDecision::ChangeValue(Value::from(value))
         }
+    }
+
+    #[test]
+    fn test_compaction_filter() {
+        let dir = get_temp_dir();
+        let lsm = LsmTree::open(LsmConfig {
+            data_dir: dir.clone(),
+            blob_value_threshold: Some(16),
+            compaction_filter: Some(Arc::new(Deactivate)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        lsm.put(Key::from("inactive/d"), Value::from("dave")).unwrap();
+        // A snapshot keeps the versions it sees out of the filter
+        let snapshot = lsm.snapshot();
+        lsm.put(Key::from("active/a"), Value::from("alice")).unwrap();
+        lsm.put(Key::from("active/b"), Value::from("a value stored in a blob file")).unwrap();
+        lsm.put(Key::from("inactive/c"), Value::from("carol")).unwrap();
+        lsm.put(Key::from("inactive/d"), Value::from("dave2")).unwrap();
+        lsm.flush().unwrap();
+
+        let stats = lsm.compact().unwrap();
+        assert_eq!(stats.entries_filtered, 2);
+        assert_eq!(stats.values_changed, 2);
+        assert_eq!(lsm.get(&Key::from("active/a")).unwrap(), Some(Value::from("ALICE")));
+        assert_eq!(
+            lsm.get(&Key::from("active/b")).unwrap(),
+            Some(Value::from("A VALUE STORED IN A BLOB FILE"))
+        );
+        assert_eq!(lsm.get(&Key::from("inactive/c")).unwrap(), None);
+        assert_eq!(lsm.get(&Key::from("inactive/d")).unwrap(), Some(Value::from("dave")));
+        assert_eq!(lsm.get_at(&Key::from("inactive/d"), &snapshot).unwrap(), Some(Value::from("dave")));
 
+        drop(snapshot);
+        drop(lsm);
         let _ = std::fs::remove_dir_all(dir);
     }
 
     #[test]
-    fn test_scan() {
+    fn test_write_batch() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            lsm.put(Key::from("b"), Value::from("old")).unwrap();
+            let mut batch = WriteBatch::new();
+            batch.put(Key::from("a"), Value::from("1"));
+            batch.delete(Key::from("b"));
+            batch.put(Key::from("c"), Value::from("3"));
+            let last_seq = lsm.write(batch).unwrap();
+            assert_eq!(lsm.get_all(&Key::from("c")).unwrap()[0].seq_num, last_seq);
+
+            let mut invalid = WriteBatch::new();
+            invalid.put(Key::from("d"), Value::from("4"));
+            invalid.merge(Key::from("d"), Value::from("5"));
+            assert!(lsm.write(invalid).is_err());
+            assert_eq!(lsm.get(&Key::from("d")).unwrap(), None);
+        }
+
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            let live: Vec<_> = lsm.scan_live().unwrap().map(|e| e.key).collect();
+            assert_eq!(live, [Key::from("a"), Key::from("c")]);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_column_families() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+        let small = LsmConfig {
+            memtable_size_threshold: 64,
+            ..config.clone()
+        };
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            let users = lsm.create_column_family("users", small.clone()).unwrap();
+            assert!(lsm.create_column_family("users", small.clone()).is_err());
+
+            // Families are independent key spaces with their own thresholds
+            lsm.put(Key::from("k"), Value::from("default")).unwrap();
+            lsm.put_cf(&users, Key::from("k"), Value::from("user")).unwrap();
+            for i in 0..10 {
+                lsm.put_cf(&users, Key::from(format!("u{}", i).as_str()), Value::from("x")).unwrap();
+            }
+            assert!(!users.super_version().sstables.is_empty());
+            assert_eq!(lsm.stats().sstable_count, 0);
+            assert_eq!(lsm.get(&Key::from("k")).unwrap(), Some(Value::from("default")));
+            assert_eq!(lsm.get_cf(&users, &Key::from("k")).unwrap(), Some(Value::from("user")));
+
+            // A batch spans families atomically
+            let snapshot = lsm.snapshot();
+            let mut batch = WriteBatch::new();
+            batch.put(Key::from("a"), Value::from("1"));
+            batch.delete_cf(&users, Key::from("k"));
+            lsm.write(batch).unwrap();
+            assert_eq!(lsm.get_at_cf(&users, &Key::from("k"), &snapshot).unwrap(), Some(Value::from("user")));
+            assert_eq!(lsm.get_cf(&users, &Key::from("k")).unwrap(), None);
+            drop(snapshot);
+            lsm.put_cf(&users, Key::from("late"), Value::from("y")).unwrap();
+
+            // The WAL outlives a flush while another family still has writes in memory
+            lsm.flush().unwrap();
+        }
+
+        {
+            let lsm = LsmTree::open_with_column_families(config, vec![("users".to_string(), small)]).unwrap();
+            assert_eq!(lsm.column_family_names(), ["default", "users"]);
+            let users = lsm.column_family("users").unwrap();
+            assert_eq!(users.config.memtable_size_threshold, 64);
+            assert_eq!(lsm.get(&Key::from("a")).unwrap(), Some(Value::from("1")));
+            assert_eq!(lsm.get_all(&Key::from("k")).unwrap().len(), 1);
+            assert_eq!(lsm.get_cf(&users, &Key::from("k")).unwrap(), None);
+            assert_eq!(lsm.get_cf(&users, &Key::from("late")).unwrap(), Some(Value::from("y")));
+            assert_eq!(lsm.scan_live_cf(&users).unwrap().count(), 11);
+
+            // Sequence numbers continue after the recovered writes
+            let seq = lsm.put(Key::from("b"), Value::from("2")).unwrap();
+            assert!(lsm.get_all(&Key::from("a")).unwrap()[0].seq_num < seq);
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_wal_segments_with_column_families() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+        let wal_files = || {
+            let mut names: Vec<String> = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with("wal"))
+                .collect();
+            names.sort();
+            names
+        };
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            let users = lsm.create_column_family("users", config.clone()).unwrap();
+            for round in 0..5 {
+                let key = Key::from(format!("k{}", round).as_str());
+                lsm.put(key.clone(), Value::from("default")).unwrap();
+                lsm.put_cf(&users, key, Value::from("user")).unwrap();
+                // A segment stays until every family flushed its writes
+                lsm.flush().unwrap();
+                assert_eq!(wal_files().len(), 2);
+                lsm.flush_cf(&users).unwrap();
+                assert_eq!(wal_files().len(), 1);
+            }
+            lsm.put_cf(&users, Key::from("pending"), Value::from("x")).unwrap();
+        }
+
+        // An older tree's single WAL is replayed as the first segment
+        let segments = wal_files();
+        assert_eq!(segments.len(), 1);
+        std::fs::rename(dir.join(&segments[0]), dir.join("wal.log")).unwrap();
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            let users = lsm.column_family("users").unwrap();
+            assert_eq!(lsm.get_cf(&users, &Key::from("pending")).unwrap(), Some(Value::from("x")));
+            assert_eq!(lsm.get_cf(&users, &Key::from("k4")).unwrap(), Some(Value::from("user")));
+            assert_eq!(wal_files(), ["wal-000000.log", "wal-000001.log"]);
+        }
+        assert!(!dir.join("manifest.tmp").exists());
+
+        // The WAL no longer holds a flushed table's writes, so a corrupt table fails the open
+        for entry in std::fs::read_dir(dir.join("pages")).unwrap() {
+            let path = entry.unwrap().path();
+            let mut bytes = std::fs::read(&path).unwrap();
+            bytes.iter_mut().for_each(|byte| *byte ^= 0xff);
+            std::fs::write(&path, bytes).unwrap();
+        }
+        assert!(LsmTree::open(config).is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_reverse_comparator() {
         let dir = get_temp_dir();
         let config = LsmConfig {
             data_dir: dir.clone(),
+            comparator: Arc::new(ReverseBytewiseComparator),
             ..Default::default()
         };
+        let keys = |lsm: &LsmTree| -> Vec<Key> { lsm.scan_live().unwrap().map(|e| e.key).collect() };
+
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            for key in ["a", "c", "e"] {
+                lsm.put(Key::from(key), Value::from(key)).unwrap();
+            }
+            lsm.flush().unwrap();
+            for key in ["b", "d", "f"] {
+                lsm.put(Key::from(key), Value::from(key)).unwrap();
+            }
+            assert_eq!(keys(&lsm), ["f", "e", "d", "c", "b", "a"].map(Key::from));
+            assert_eq!(lsm.get(&Key::from("c")).unwrap(), Some(Value::from("c")));
+
+            // Ranges run from the greater key down
+            assert!(lsm.delete_range(Key::from("b"), Key::from("e")).is_err());
+            lsm.delete_range(Key::from("e"), Key::from("b")).unwrap();
+            assert_eq!(keys(&lsm), ["f", "b", "a"].map(Key::from));
+            lsm.flush().unwrap();
+            lsm.compact().unwrap();
+            assert_eq!(keys(&lsm), ["f", "b", "a"].map(Key::from));
+            assert_eq!(lsm.get(&Key::from("a")).unwrap(), Some(Value::from("a")));
+        }
+
+        // The comparator is checked on open
+        let bytewise = LsmConfig {
+            comparator: Arc::new(BytewiseComparator),
+            ..config.clone()
+        };
+        assert!(LsmTree::open(bytewise).is_err());
+        let lsm = LsmTree::open(config).unwrap();
+        assert_eq!(keys(&lsm), ["f", "b", "a"].map(Key::from));
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(dir);
+    }
 
+    #[test]
+    fn test_multi_get() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
         let lsm = LsmTree::open(config).unwrap();
 
-        lsm.put(Key::from("c"), Value::from("3")).unwrap();
+        for i in 0..100 {
+            lsm.put(Key::from(format!("key{:03}", i).as_str()), Value::from("old")).unwrap();
+        }
+        lsm.flush().unwrap();
+        for i in (0..100).step_by(3) {
+            lsm.put(Key::from(format!("key{:03}", i).as_str()), Value::from("new")).unwrap();
+        }
+        lsm.flush().unwrap();
+        lsm.delete(Key::from("key010")).unwrap();
+        lsm.put(Key::from("key011"), Value::from("mem")).unwrap();
+
+        let keys: Vec<Key> = ["key099", "key010", "missing", "key011", "key003", "key004", "key003"]
+            .into_iter()
+            .map(Key::from)
+            .collect();
+        let values: Vec<Option<Value>> = lsm.multi_get(&keys).into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            values,
+            vec![
+                Some(Value::from("new")),
+                None,
+                None,
+                Some(Value::from("mem")),
+                Some(Value::from("new")),
+                Some(Value::from("old")),
+                Some(Value::from("new")),
+            ]
+        );
+        for (key, value) in keys.iter().zip(&values) {
+            assert_eq!(&lsm.get(key).unwrap(), value);
+        }
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_super_version_keeps_obsolete_tables() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+        let lsm = LsmTree::open(config).unwrap();
+        let table_file_exists = |id: u64| {
+            std::fs::read_dir(dir.join("pages")).unwrap().any(|file| {
+                let path = file.unwrap().path();
+                path.extension().is_some_and(|ext| ext == "pagefile")
+                    && path.file_stem().and_then(|stem| stem.to_str()?.parse::<u64>().ok()) == Some(id)
+            })
+        };
+
         lsm.put(Key::from("a"), Value::from("1")).unwrap();
+        lsm.flush().unwrap();
         lsm.put(Key::from("b"), Value::from("2")).unwrap();
+        lsm.flush().unwrap();
+
+        // A reader's super version outlives the compaction replacing its tables
+        let version = lsm.default_cf.super_version();
+        let old_ids: Vec<u64> = version.sstables.iter().map(|s| s.meta.id).collect();
+        lsm.compact().unwrap();
+        assert_eq!(lsm.default_cf.super_version().sstables.len(), 1);
+        assert!(old_ids.iter().all(|&id| table_file_exists(id)));
+        assert_eq!(version.sstables[1].get(&Key::from("a")).unwrap()[0].value, Some(Value::from("1")));
+
+        // The last reference deletes the obsolete files
+        drop(version);
+        assert!(old_ids.iter().all(|&id| !table_file_exists(id)));
+        assert_eq!(lsm.get(&Key::from("a")).unwrap(), Some(Value::from("1")));
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(dir);
+    }
 
-        let entries: Vec<_> = lsm.scan_live().unwrap().collect();
-        let keys: Vec<_> = entries.iter().map(|e| e.key.as_bytes()).collect();
+    #[test]
+    fn test_wal_rotates_under_concurrent_writes() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+        let lsm = LsmTree::open(config).unwrap();
+        let wal_files = || {
+            std::fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with("wal-"))
+                .count()
+        };
 
-        assert_eq!(keys, vec![b"a", b"b", b"c"]);
+        let stop = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut i = 0;
+                while !stop.load(Ordering::SeqCst) {
+                    lsm.put(Key::from(format!("k{}", i % 100).as_str()), Value::from("v")).unwrap();
+                    i += 1;
+                }
+            });
+            // Writes landing during a flush go to the next segment, not the sealed one's
+            for _ in 0..10 {
+                lsm.put(Key::from("main"), Value::from("v")).unwrap();
+                lsm.flush().unwrap();
+                assert_eq!(wal_files(), 1);
+            }
+            stop.store(true, Ordering::SeqCst);
+        });
+
+        drop(lsm);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = get_temp_dir();
+        let checkpoint_dir = dir.join("checkpoint");
+        let config = LsmConfig {
+            data_dir: dir.join("db"),
+            blob_value_threshold: Some(1024),
+            ..Default::default()
+        };
+        let large = Value::new(vec![7u8; 4 * 1024]);
+
+        let lsm = LsmTree::open(config.clone()).unwrap();
+        let users = lsm.create_column_family("users", config.clone()).unwrap();
+        for i in 0..10 {
+            lsm.put(Key::from(format!("key{}", i).as_str()), Value::from("flushed")).unwrap();
+        }
+        lsm.put(Key::from("blob"), large.clone()).unwrap();
+        lsm.flush().unwrap();
+        // Unflushed writes reach the checkpoint through its copy of the WAL
+        lsm.put(Key::from("key0"), Value::from("logged")).unwrap();
+        lsm.put_cf(&users, Key::from("alice"), Value::from("1")).unwrap();
+
+        // A directory in the way of the copy is left alone
+        std::fs::create_dir_all(dir.join("checkpoint.tmp/pages")).unwrap();
+        assert_eq!(lsm.checkpoint(&checkpoint_dir).unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(dir.join("checkpoint.tmp/pages").exists());
+        assert!(!checkpoint_dir.exists());
+        std::fs::remove_dir_all(dir.join("checkpoint.tmp")).unwrap();
+
+        lsm.checkpoint(&checkpoint_dir).unwrap();
+        assert!(!dir.join("checkpoint.tmp").exists());
+        assert_eq!(lsm.checkpoint(&checkpoint_dir).unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+
+        // Later writes and compactions of the tree leave the checkpoint alone
+        lsm.put(Key::from("key1"), Value::from("later")).unwrap();
+        lsm.flush().unwrap();
+        lsm.compact().unwrap();
+        drop(lsm);
+
+        let copy = LsmTree::open(LsmConfig {
+            data_dir: checkpoint_dir,
+            ..config
+        })
+        .unwrap();
+        let users = copy.column_family("users").unwrap();
+        assert_eq!(copy.get(&Key::from("key0")).unwrap(), Some(Value::from("logged")));
+        assert_eq!(copy.get(&Key::from("key1")).unwrap(), Some(Value::from("flushed")));
+        assert_eq!(copy.get(&Key::from("blob")).unwrap(), Some(large));
+        assert_eq!(copy.get_cf(&users, &Key::from("alice")).unwrap(), Some(Value::from("1")));
+        assert_eq!(copy.scan_live().unwrap().count(), 11);
+
+        drop(copy);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_ingest_external_files() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.join("db"),
+            ..Default::default()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = |i: usize| Key::from(format!("key{:03}", i).as_str());
+        let write_external = |name: &str, keys: std::ops::Range<usize>, value: &str| {
+            let path = dir.join(name);
+            let mut writer = ExternalSstWriter::create(&path, SSTableOptions::default()).unwrap();
+            for i in keys {
+                writer.put(key(i), Value::from(value)).unwrap();
+            }
+            writer.finish().unwrap();
+            path
+        };
+        // Writers of paths differing only in the extension don't share build files
+        let (first, second) = (dir.join("data.1"), dir.join("data.2"));
+        {
+            let mut writers =
+                [&first, &second].map(|path| ExternalSstWriter::create(path, SSTableOptions::default()).unwrap());
+            for i in 0..50 {
+                writers[0].put(key(i), Value::from("ingested")).unwrap();
+                writers[1].put(key(50 + i), Value::from("ingested")).unwrap();
+            }
+            for writer in writers {
+                writer.finish().unwrap();
+            }
+        }
+        let overlapping = write_external("overlapping.sst", 40..60, "overlapping");
+
+        let mut writer = ExternalSstWriter::create(dir.join("unsorted.sst"), SSTableOptions::default()).unwrap();
+        writer.put(key(2), Value::from("x")).unwrap();
+        assert!(writer.put(key(1), Value::from("x")).is_err());
+        assert!(ExternalSstWriter::create(&first, SSTableOptions::default()).is_err());
+        // A directory in the way of the build files is left alone
+        std::fs::create_dir_all(dir.join("taken.sst.build").join("keep")).unwrap();
+        let taken = ExternalSstWriter::create(dir.join("taken.sst"), SSTableOptions::default());
+        assert_eq!(taken.err().map(|e| e.kind()), Some(std::io::ErrorKind::AlreadyExists));
+        assert!(dir.join("taken.sst.build").join("keep").exists());
+
+        let global_seq;
+        {
+            let lsm = LsmTree::open(config.clone()).unwrap();
+            lsm.put(key(60), Value::from("memtable")).unwrap();
+            lsm.put(key(500), Value::from("memtable")).unwrap();
+            let snapshot = lsm.snapshot();
+
+            // Failed ingestions leave no file behind
+            assert!(lsm.ingest_external_files(&[&first, &overlapping]).is_err());
+            assert!(lsm.ingest_external_files(&[first.clone(), dir.join("missing.sst")]).is_err());
+            assert_eq!(lsm.default_cf.super_version().sstables.len(), 0);
+            assert_eq!(std::fs::read_dir(dir.join("db").join("pages")).unwrap().count(), 0);
+
+            // The memtable write to a key of the second file is flushed first
+            global_seq = lsm.ingest_external_files(&[&second, &first]).unwrap();
+            assert_eq!(lsm.default_cf.super_version().sstables.len(), 3);
+            assert_eq!(lsm.get(&key(60)).unwrap(), Some(Value::from("ingested")));
+            assert_eq!(lsm.get(&key(10)).unwrap(), Some(Value::from("ingested")));
+            assert_eq!(lsm.get_at(&key(10), &snapshot).unwrap(), None);
+            assert_eq!(lsm.get_at(&key(60), &snapshot).unwrap(), Some(Value::from("memtable")));
+            assert_eq!(lsm.get_all(&key(60)).unwrap()[0].seq_num, global_seq);
+            assert!(lsm.put(key(1), Value::from("newer")).unwrap() > global_seq);
+            assert_eq!(lsm.get(&key(1)).unwrap(), Some(Value::from("newer")));
+        }
+
+        // The tree holds copies, so changing the ingested files doesn't reach it
+        std::fs::write(&first, b"").unwrap();
+        std::fs::write(&second, b"").unwrap();
+
+        // The global sequence number is kept in the manifest
+        {
+            let lsm = LsmTree::open(config).unwrap();
+            assert_eq!(lsm.get(&key(99)).unwrap(), Some(Value::from("ingested")));
+            assert_eq!(lsm.get(&key(1)).unwrap(), Some(Value::from("newer")));
+            assert_eq!(lsm.get_all(&key(99)).unwrap()[0].seq_num, global_seq);
+            assert_eq!(lsm.scan_live().unwrap().count(), 101);
+            lsm.compact().unwrap();
+            assert_eq!(lsm.get(&key(60)).unwrap(), Some(Value::from("ingested")));
+        }
 
         let _ = std::fs::remove_dir_all(dir);
     }
@@ -1,12 +1,17 @@
 //! In-memory sorted table for fast writes.
 //!
 //! The MemTable stores entries sorted by (key, seq_num desc) using a BTreeMap.
-//! This allows efficient point lookups and range scans.
+//! This allows efficient point lookups and range scans. Range deletions are kept
+//! separately as a list of range tombstones, fragmented on first read after a write.
 
 use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 
-use super::types::{Entry, Key, SeqNum, Value};
+use super::comparator::{bytewise, Comparator, OrderedKey};
+use super::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use super::retention::{unix_now, SeqTimeMapping};
+use super::types::{Entry, Key, SeqNum, Value, ValueKind};
 
 /// In-memory sorted table.
 /// 
@@ -14,37 +19,75 @@ use super::types::{Entry, Key, SeqNum, Value};
 /// - Keys are in ascending order
 /// - For the same key, newer entries (higher seq_num) come first
 pub struct MemTable {
-    /// Entries stored as (key, seq_num) -> Option<Value>
+    /// Entries stored as (key, seq_num) -> value
     /// We use reverse seq_num ordering within the same key.
-    entries: BTreeMap<(Key, std::cmp::Reverse<SeqNum>), Option<Value>>,
+    entries: BTreeMap<(OrderedKey, std::cmp::Reverse<SeqNum>), MemValue>,
+    /// Range deletions, in the order written.
+    range_tombstones: Vec<RangeTombstone>,
+    /// Fragmented view of `range_tombstones`, built on first read and reset by writes.
+    fragmented_range_tombstones: OnceLock<FragmentedRangeTombstones>,
+    /// When the entries were written, for time-based retention.
+    seq_times: SeqTimeMapping,
     /// Current size in bytes (approximate).
     size_bytes: usize,
-    /// Sequence number generator.
-    next_seq_num: AtomicU64,
+    /// Sequence number generator, shared by the memtables of all column families.
+    next_seq_num: Arc<AtomicU64>,
+    /// Order of the keys.
+    comparator: Arc<dyn Comparator>,
+}
+
+/// A memtable entry's value: None for a tombstone, with an optional expiry.
+struct MemValue {
+    value: Option<Value>,
+    kind: ValueKind,
+    expires_at: Option<u64>,
+}
+
+impl MemValue {
+    /// The value as read at `now`; expired values read as deleted.
+    fn read(&self, now: u64) -> Option<&Value> {
+        match self.expires_at {
+            Some(expires_at) if now >= expires_at => None,
+            _ => self.value.as_ref(),
+        }
+    }
 }
 
 impl MemTable {
     /// Create a new empty memtable.
     pub fn new() -> Self {
-        Self {
-            entries: BTreeMap::new(),
-            size_bytes: 0,
-            next_seq_num: AtomicU64::new(1),
-        }
+        Self::with_seq_num(1)
     }
 
     /// Create a memtable starting from a specific sequence number.
     pub fn with_seq_num(start_seq_num: SeqNum) -> Self {
+        Self::with_seq_counter(Arc::new(AtomicU64::new(start_seq_num)), bytewise())
+    }
+
+    /// Create a memtable ordering keys by `comparator` and allocating sequence
+    /// numbers from `next_seq_num`, which other memtables may share.
+    pub(crate) fn with_seq_counter(next_seq_num: Arc<AtomicU64>, comparator: Arc<dyn Comparator>) -> Self {
         Self {
             entries: BTreeMap::new(),
+            range_tombstones: Vec::new(),
+            fragmented_range_tombstones: OnceLock::new(),
+            seq_times: SeqTimeMapping::default(),
             size_bytes: 0,
-            next_seq_num: AtomicU64::new(start_seq_num),
+            next_seq_num,
+            comparator,
         }
     }
 
-    /// Get the next sequence number and increment.
-    fn alloc_seq_num(&self) -> SeqNum {
-        self.next_seq_num.fetch_add(1, Ordering::SeqCst)
+    /// The sequence number generator of this memtable.
+    pub(crate) fn seq_counter(&self) -> Arc<AtomicU64> {
+        self.next_seq_num.clone()
+    }
+
+    /// Get the next sequence number and increment, recording the write time.
+    fn alloc_seq_num(&mut self) -> SeqNum {
+        let seq_num = self.next_seq_num.fetch_add(1, Ordering::SeqCst);
+        self.seq_times.record(seq_num, unix_now());
+        seq_num
     }
 
     /// Current sequence number (for recovery).
@@ -61,11 +104,48 @@ impl MemTable {
 
     /// Put with an explicit sequence number (used during WAL replay).
     pub fn put_with_seq(&mut self, key: Key, value: Value, seq_num: SeqNum) {
+        self.put_with_seq_and_expiry(key, value, seq_num, None);
+    }
+
+    /// Put a key-value pair that reads as deleted from `expires_at` (seconds since
+    /// the Unix epoch). Returns the sequence number assigned.
+    pub fn put_with_expiry(&mut self, key: Key, value: Value, expires_at: u64) -> SeqNum {
+        let seq_num = self.alloc_seq_num();
+        self.put_with_seq_and_expiry(key, value, seq_num, Some(expires_at));
+        seq_num
+    }
+
+    /// Put with an explicit sequence number and optional expiry (used during WAL replay).
+    pub fn put_with_seq_and_expiry(&mut self, key: Key, value: Value, seq_num: SeqNum, expires_at: Option<u64>) {
         let entry_size = key.len() + value.len() + 8 + 16; // approximate overhead
         self.size_bytes += entry_size;
         self.entries.insert(
-            (key, std::cmp::Reverse(seq_num)),
-            Some(value),
+            (self.ordered(key), std::cmp::Reverse(seq_num)),
+            MemValue {
+                value: Some(value),
+                kind: ValueKind::Inline,
+                expires_at,
+            },
+        );
+    }
+
+    /// Add a merge operand for a key. Returns the sequence number assigned.
+    pub fn merge(&mut self, key: Key, operand: Value) -> SeqNum {
+        let seq_num = self.alloc_seq_num();
+        self.merge_with_seq(key, operand, seq_num);
+        seq_num
+    }
+
+    /// Merge with an explicit sequence number (used during WAL replay).
+    pub fn merge_with_seq(&mut self, key: Key, operand: Value, seq_num: SeqNum) {
+        self.size_bytes += key.len() + operand.len() + 8 + 16; // approximate overhead
+        self.entries.insert(
+            (self.ordered(key), std::cmp::Reverse(seq_num)),
+            MemValue {
+                value: Some(operand),
+                kind: ValueKind::Merge,
+                expires_at: None,
+            },
         );
     }
 
@@ -81,56 +161,110 @@ impl MemTable {
         let entry_size = key.len() + 8 + 16; // approximate overhead
         self.size_bytes += entry_size;
         self.entries.insert(
-            (key, std::cmp::Reverse(seq_num)),
-            None, // tombstone
+            (self.ordered(key), std::cmp::Reverse(seq_num)),
+            MemValue {
+                value: None, // tombstone
+                kind: ValueKind::Inline,
+                expires_at: None,
+            },
         );
     }
 
-    /// Get the latest value for a key.
-    /// Returns Some(Some(value)) if found, Some(None) if deleted (tombstone),
-    /// or None if key never existed.
-    pub fn get(&self, key: &Key) -> Option<Option<&Value>> {
-        // Find the first entry with this key (which has the highest seq_num due to ordering)
-        let start = (key.clone(), std::cmp::Reverse(SeqNum::MAX));
-        let end = (key.clone(), std::cmp::Reverse(0));
-        
+    /// Delete all keys in `[start, end)`. Returns the sequence number assigned.
+    pub fn delete_range(&mut self, start: Key, end: Key) -> SeqNum {
+        let seq_num = self.alloc_seq_num();
+        self.delete_range_with_seq(start, end, seq_num);
+        seq_num
+    }
+
+    /// Delete a range with an explicit sequence number (used during WAL replay).
+    pub fn delete_range_with_seq(&mut self, start: Key, end: Key, seq_num: SeqNum) {
+        self.size_bytes += start.len() + end.len() + 8 + 16; // approximate overhead
+        self.range_tombstones.push(RangeTombstone::new(start, end, seq_num));
+        self.fragmented_range_tombstones = OnceLock::new();
+    }
+
+    fn ordered(&self, key: Key) -> OrderedKey {
+        OrderedKey::new(key, self.comparator.clone())
+    }
+
+    /// Range deletions in this memtable.
+    pub fn range_tombstones(&self) -> &FragmentedRangeTombstones {
+        self.fragmented_range_tombstones.get_or_init(|| {
+            FragmentedRangeTombstones::with_comparator(self.range_tombstones.clone(), self.comparator.clone())
+        })
+    }
+
+    /// Write times of the entries written through this memtable.
+    pub fn seq_times(&self) -> &SeqTimeMapping {
+        &self.seq_times
+    }
+
+    /// Get the newest entry for a key as (seq_num, value), ignoring range deletions.
+    /// An expired entry reads as deleted; a merge operand is returned as stored.
+    pub fn get_latest(&self, key: &Key) -> Option<(SeqNum, Option<&Value>)> {
+        let start = (self.ordered(key.clone()), std::cmp::Reverse(SeqNum::MAX));
+        let end = (self.ordered(key.clone()), std::cmp::Reverse(0));
+        let now = unix_now();
+
         self.entries
             .range(start..=end)
             .next()
-            .map(|(_, v)| v.as_ref())
+            .map(|((_, std::cmp::Reverse(seq)), v)| (*seq, v.read(now)))
+    }
+
+    /// Get the latest value for a key.
+    /// Returns Some(Some(value)) if found, Some(None) if deleted (tombstone) or
+    /// expired, or None if key never existed.
+    pub fn get(&self, key: &Key) -> Option<Option<&Value>> {
+        self.get_latest(key).map(|(_, value)| value)
     }
 
-    /// Get all values for a key (for duplicate key support).
-    /// Returns entries in seq_num descending order (newest first).
+    /// Get all values for a key (for duplicate key support); expired values read as
+    /// tombstones. Returns entries in seq_num descending order (newest first).
     pub fn get_all(&self, key: &Key) -> Vec<(SeqNum, Option<&Value>)> {
-        let start = (key.clone(), std::cmp::Reverse(SeqNum::MAX));
-        let end = (key.clone(), std::cmp::Reverse(0));
-        
+        let now = unix_now();
+        self.entries_for(key).map(|(seq, v)| (seq, v.read(now))).collect()
+    }
+
+    /// All versions of a key as stored, newest first, including expired ones.
+    pub fn get_entries(&self, key: &Key) -> Vec<Entry> {
+        self.entries_for(key).map(|(seq, v)| Self::to_entry(key, seq, v)).collect()
+    }
+
+    fn entries_for(&self, key: &Key) -> impl Iterator<Item = (SeqNum, &MemValue)> {
+        let start = (self.ordered(key.clone()), std::cmp::Reverse(SeqNum::MAX));
+        let end = (self.ordered(key.clone()), std::cmp::Reverse(0));
         self.entries
             .range(start..=end)
-            .map(|((_, std::cmp::Reverse(seq)), v)| (*seq, v.as_ref()))
-            .collect()
+            .map(|((_, std::cmp::Reverse(seq)), v)| (*seq, v))
+    }
+
+    fn to_entry(key: &Key, seq_num: SeqNum, value: &MemValue) -> Entry {
+        Entry {
+            key: key.clone(),
+            seq_num,
+            value: value.value.clone(),
+            value_kind: value.kind,
+            expires_at: value.expires_at,
+        }
     }
 
     /// Iterate over all entries in sorted order.
     pub fn iter(&self) -> impl Iterator<Item = Entry> + '_ {
-        self.entries.iter().map(|((key, std::cmp::Reverse(seq)), value)| {
-            Entry {
-                key: key.clone(),
-                seq_num: *seq,
-                value: value.clone(),
-            }
-        })
+        self.entries
+            .iter()
+            .map(|((key, std::cmp::Reverse(seq)), value)| Self::to_entry(&key.key, *seq, value))
     }
 
-    /// Number of entries.
+    /// Number of entries (excluding range deletions).
     pub fn len(&self) -> usize {
         self.entries.len()
     }
 
-    /// Check if empty.
+    /// Check if empty (no entries and no range deletions).
     pub fn is_empty(&self) -> bool {
-        self.entries.is_empty()
+        self.entries.is_empty() && self.range_tombstones.is_empty()
     }
 
     /// Approximate size in bytes.
@@ -141,6 +275,9 @@ impl MemTable {
     /// Clear the memtable.
     pub fn clear(&mut self) {
         self.entries.clear();
+        self.range_tombstones.clear();
+        self.fragmented_range_tombstones = OnceLock::new();
+        self.seq_times = SeqTimeMapping::default();
         self.size_bytes = 0;
     }
 }
@@ -226,4 +363,24 @@ mod tests {
         assert_eq!(entries[0].seq_num, seq2);
         assert_eq!(entries[1].seq_num, seq1);
     }
+
+    #[test]
+    fn test_delete_range() {
+        let mut mem = MemTable::new();
+        let put_seq = mem.put(Key::from("b"), Value::from("1"));
+        let range_seq = mem.delete_range(Key::from("a"), Key::from("c"));
+        mem.put(Key::from("b"), Value::from("2"));
+
+        assert!(!mem.is_empty());
+        let tombstones = mem.range_tombstones();
+        assert!(tombstones.covers(&Key::from("b"), put_seq));
+        assert!(!tombstones.covers(&Key::from("c"), put_seq));
+        assert_eq!(tombstones.max_covering_seq(&Key::from("a")), Some(range_seq));
+        assert_eq!(mem.get_latest(&Key::from("b")).unwrap().0, range_seq + 1);
+
+        // A range delete after a read is seen by the next read
+        let later_seq = mem.delete_range(Key::from("b"), Key::from("d"));
+        assert_eq!(mem.range_tombstones().max_covering_seq(&Key::from("c")), Some(later_seq));
+        assert_eq!(mem.range_tombstones().covering_seqs(&Key::from("b")), [later_seq, range_seq]);
+    }
 }
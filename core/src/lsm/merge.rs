@@ -0,0 +1,363 @@
+//! Merge operator - read-modify-write without reads.
+//!
+//! `LsmTree::merge` stores an operand entry instead of a value. Reads fold the
+//! operands of a key, oldest first, onto the newest value below them using the
+//! configured `MergeOperator`. Flush and compaction collapse operand chains:
+//! a chain that reaches a value (or the bottom of the tree) is fully merged into
+//! a value, otherwise consecutive operands are combined with a partial merge if
+//! the operator supports it. Operands are never combined across a snapshot.
+
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use super::compaction::snapshot_between;
+use super::range_tombstone::FragmentedRangeTombstones;
+use super::types::{Entry, Key, SeqNum, Value, ValueKind};
+
+/// Combines merge operands with the value they apply to.
+pub trait MergeOperator: Send + Sync {
+    /// Name of the operator, used in error messages.
+    fn name(&self) -> &str;
+
+    /// Apply `operands` (oldest first) to `existing`, the value below them if any.
+    fn full_merge(&self, key: &Key, existing: Option<&Value>, operands: &[Value]) -> Value;
+
+    /// Combine consecutive `operands` (oldest first) into a single operand, when
+    /// that can be done without the value below them. Returns None if not supported.
+    fn partial_merge(&self, _key: &Key, _operands: &[Value]) -> Option<Value> {
+        None
+    }
+}
+
+/// Apply operands collected newest first.
+fn full_merge(operator: &dyn MergeOperator, key: &Key, existing: Option<&Value>, newest_first: &[Value]) -> Value {
+    let operands: Vec<Value> = newest_first.iter().rev().cloned().collect();
+    operator.full_merge(key, existing, &operands)
+}
+
+/// Folds the versions of a key, newest first, into its current value: operands
+/// are collected until a value or tombstone is reached.
+pub(crate) struct MergeFold<'a> {
+    operator: Option<&'a Arc<dyn MergeOperator>>,
+    operands: Vec<Value>,
+    base: Option<Value>,
+    done: bool,
+}
+
+impl<'a> MergeFold<'a> {
+    pub(crate) fn new(operator: Option<&'a Arc<dyn MergeOperator>>) -> Self {
+        Self {
+            operator,
+            operands: Vec::new(),
+            base: None,
+            done: false,
+        }
+    }
+
+    /// Add the next older version. Returns true once the value is determined.
+    pub(crate) fn add(&mut self, entry: Entry) -> bool {
+        match entry.value_kind {
+            ValueKind::Merge => self.operands.extend(entry.value),
+            _ => {
+                self.base = entry.value;
+                self.done = true;
+            }
+        }
+        self.done
+    }
+
+    /// Add a version that is deleted (a tombstone, expired or range deleted).
+    pub(crate) fn add_deleted(&mut self) {
+        self.done = true;
+    }
+
+    /// The folded value, or None if the key is deleted or absent.
+    pub(crate) fn finish(self, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        if self.operands.is_empty() {
+            return Ok(self.base);
+        }
+        let operator = self.operator.ok_or_else(missing_operator)?;
+        Ok(Some(full_merge(operator.as_ref(), key, self.base.as_ref(), &self.operands)))
+    }
+}
+
+pub(crate) fn missing_operator() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, "Merge requires a merge operator in LsmConfig")
+}
+
+/// Yields the newest version of each key from entries sorted by key, newest
+/// first, with merge operands folded into a value. Without a merge operator
+/// operands are returned as stored.
+pub(crate) struct MergeResolveIterator<I: Iterator<Item = Entry>> {
+    inner: Peekable<I>,
+    operator: Option<Arc<dyn MergeOperator>>,
+}
+
+impl<I> MergeResolveIterator<I>
+where
+    I: Iterator<Item = Entry>,
+{
+    pub(crate) fn new(inner: I, operator: Option<Arc<dyn MergeOperator>>) -> Self {
+        Self {
+            inner: inner.peekable(),
+            operator,
+        }
+    }
+}
+
+impl<I> Iterator for MergeResolveIterator<I>
+where
+    I: Iterator<Item = Entry>,
+{
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        let newest = self.inner.next()?;
+        let result = match (&self.operator, newest.value_kind) {
+            (Some(operator), ValueKind::Merge) => {
+                let mut operands: Vec<Value> = newest.value.iter().cloned().collect();
+                let mut base = None;
+                while let Some(entry) = self.inner.next_if(|e| e.key == newest.key) {
+                    if entry.value_kind != ValueKind::Merge {
+                        base = entry.value;
+                        break;
+                    }
+                    operands.extend(entry.value);
+                }
+                let value = full_merge(operator.as_ref(), &newest.key, base.as_ref(), &operands);
+                Entry::put(newest.key, newest.seq_num, value)
+            }
+            _ => newest,
+        };
+        // Skip the older versions of the key
+        while self.inner.next_if(|e| e.key == result.key).is_some() {}
+        Some(result)
+    }
+}
+
+/// Collapses merge operand chains in entries sorted by key, newest first, as
+/// they are written by flush or compaction.
+pub(crate) struct MergeCollapseIterator<'a, I> {
+    inner: I,
+    /// Next input entry, read ahead to find the end of a key's versions.
+    peeked: Option<Entry>,
+    operator: Option<&'a Arc<dyn MergeOperator>>,
+    range_tombstones: &'a FragmentedRangeTombstones,
+    /// Live snapshot sequence numbers, ascending.
+    snapshots: &'a [SeqNum],
+    /// Whether nothing older than the input exists, so a chain without a value
+    /// below it applies to no value.
+    bottommost: bool,
+    now: u64,
+    /// Collapsed versions of the current key, newest first.
+    pending: std::vec::IntoIter<Entry>,
+    operands_merged: u64,
+}
+
+impl<'a, I> MergeCollapseIterator<'a, I>
+where
+    I: Iterator<Item = Entry>,
+{
+    pub(crate) fn new(
+        inner: I,
+        operator: Option<&'a Arc<dyn MergeOperator>>,
+        range_tombstones: &'a FragmentedRangeTombstones,
+        snapshots: &'a [SeqNum],
+        bottommost: bool,
+        now: u64,
+    ) -> Self {
+        Self {
+            inner,
+            peeked: None,
+            operator,
+            range_tombstones,
+            snapshots,
+            bottommost,
+            now,
+            pending: Vec::new().into_iter(),
+            operands_merged: 0,
+        }
+    }
+
+    pub(crate) fn operands_merged(&self) -> u64 {
+        self.operands_merged
+    }
+
+    pub(crate) fn inner(&self) -> &I {
+        &self.inner
+    }
+
+    /// Whether a range tombstone written between the two versions deletes the older one.
+    fn deleted_between(&self, key: &Key, older: SeqNum, newer: SeqNum) -> bool {
+        self.range_tombstones
+            .covering_seqs(key)
+            .iter()
+            .any(|&t| t > older && t < newer)
+    }
+
+    fn collapse(&mut self, operator: &Arc<dyn MergeOperator>, versions: Vec<Entry>) -> Vec<Entry> {
+        let mut out = Vec::with_capacity(versions.len());
+        let mut i = 0;
+        while i < versions.len() {
+            let newest = &versions[i];
+            if newest.value_kind != ValueKind::Merge {
+                out.push(newest.clone());
+                i += 1;
+                continue;
+            }
+
+            // Extend the chain while nothing separates consecutive operands
+            let mut last = i;
+            while let Some(next) = versions.get(last + 1) {
+                let (older, newer) = (next.seq_num, versions[last].seq_num);
+                if next.value_kind != ValueKind::Merge
+                    || snapshot_between(self.snapshots, older, newer)
+                    || self.deleted_between(&newest.key, older, newer)
+                {
+                    break;
+                }
+                last += 1;
+            }
+            let operands: Vec<Value> = versions[i..=last].iter().filter_map(|e| e.value.clone()).collect();
+            let oldest_seq = versions[last].seq_num;
+
+            // The value below the chain, if it can be applied now
+            let base = match versions.get(last + 1) {
+                Some(next) if snapshot_between(self.snapshots, next.seq_num, oldest_seq) => None,
+                Some(next) if self.deleted_between(&newest.key, next.seq_num, oldest_seq) => Some(None),
+                Some(next) if next.is_tombstone() || next.is_expired(self.now) => Some(None),
+                Some(next) if next.value_kind == ValueKind::Inline => Some(next.value.as_ref()),
+                Some(_) => None,
+                None if self.bottommost => Some(None),
+                None => None,
+            };
+
+            match base {
+                Some(existing) => {
+                    let value = full_merge(operator.as_ref(), &newest.key, existing, &operands);
+                    out.push(Entry::put(newest.key.clone(), newest.seq_num, value));
+                    self.operands_merged += operands.len() as u64;
+                }
+                None => match operands.len() {
+                    1 => out.push(newest.clone()),
+                    _ => {
+                        let oldest_first: Vec<Value> = operands.iter().rev().cloned().collect();
+                        match operator.partial_merge(&newest.key, &oldest_first) {
+                            Some(operand) => {
+                                out.push(Entry::merge(newest.key.clone(), newest.seq_num, operand));
+                                self.operands_merged += operands.len() as u64 - 1;
+                            }
+                            None => out.extend_from_slice(&versions[i..=last]),
+                        }
+                    }
+                },
+            }
+            i = last + 1;
+        }
+        out
+    }
+}
+
+impl<I> Iterator for MergeCollapseIterator<'_, I>
+where
+    I: Iterator<Item = Entry>,
+{
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        if let Some(entry) = self.pending.next() {
+            return Some(entry);
+        }
+        let first = self.peeked.take().or_else(|| self.inner.next())?;
+        let operator = match self.operator {
+            Some(operator) if first.value_kind == ValueKind::Merge => operator,
+            _ => return Some(first),
+        };
+
+        let mut versions = vec![first];
+        for entry in self.inner.by_ref() {
+            if entry.key != versions[0].key {
+                self.peeked = Some(entry);
+                break;
+            }
+            versions.push(entry);
+        }
+        self.pending = self.collapse(operator, versions).into_iter();
+        self.pending.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds little-endian u64 operands.
+    struct Counter;
+
+    impl MergeOperator for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn full_merge(&self, _key: &Key, existing: Option<&Value>, operands: &[Value]) -> Value {
+            let base = existing.map_or(0, decode);
+            Value::new(operands.iter().map(decode).fold(base, |a, b| a + b).to_le_bytes().to_vec())
+        }
+
+        fn partial_merge(&self, key: &Key, operands: &[Value]) -> Option<Value> {
+            Some(self.full_merge(key, None, operands))
+        }
+    }
+
+    fn decode(value: &Value) -> u64 {
+        u64::from_le_bytes(value.as_bytes().try_into().unwrap())
+    }
+
+    fn operand(n: u64) -> Value {
+        Value::new(n.to_le_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_merge_collapse() {
+        let operator: Arc<dyn MergeOperator> = Arc::new(Counter);
+        let key = Key::from("hits");
+        let entries = vec![
+            Entry::merge(key.clone(), 5, operand(1)),
+            Entry::merge(key.clone(), 4, operand(2)),
+            Entry::merge(key.clone(), 3, operand(3)),
+            Entry::put(key.clone(), 2, operand(10)),
+        ];
+        let no_tombstones = FragmentedRangeTombstones::default();
+        let collapse = |snapshots: &[SeqNum], bottommost: bool, entries: Vec<Entry>| {
+            let mut iter = MergeCollapseIterator::new(
+                entries.into_iter(),
+                Some(&operator),
+                &no_tombstones,
+                snapshots,
+                bottommost,
+                0,
+            );
+            let out: Vec<_> = iter.by_ref().map(|e| (e.seq_num, e.value_kind, decode(e.value.as_ref().unwrap()))).collect();
+            (out, iter.operands_merged())
+        };
+
+        // The chain reaches a value and is fully merged; the value stays as history
+        let (out, merged) = collapse(&[], false, entries.clone());
+        assert_eq!(out, [(5, ValueKind::Inline, 16), (2, ValueKind::Inline, 10)]);
+        assert_eq!(merged, 3);
+
+        // A snapshot at 3 reads 13, so operands 4 and 5 are only partially merged
+        let (out, _) = collapse(&[3], false, entries.clone());
+        assert_eq!(out, [(5, ValueKind::Merge, 3), (3, ValueKind::Inline, 13), (2, ValueKind::Inline, 10)]);
+
+        // Without a value below, only the bottommost level can fully merge
+        let (out, _) = collapse(&[], false, entries[..3].to_vec());
+        assert_eq!(out, [(5, ValueKind::Merge, 6)]);
+        let (out, _) = collapse(&[], true, entries[..3].to_vec());
+        assert_eq!(out, [(5, ValueKind::Inline, 6)]);
+
+        let resolved: Vec<_> = MergeResolveIterator::new(entries.into_iter(), Some(operator.clone())).collect();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(decode(resolved[0].value.as_ref().unwrap()), 16);
+    }
+}
@@ -2,19 +2,53 @@
 //!
 //! Architecture:
 //! - MemTable: In-memory sorted structure for fast writes
-//! - SSTable: Immutable on-disk sorted files
+//! - SSTable: Immutable on-disk sorted files, made of optionally compressed blocks
 //! - WAL: Write-ahead log for durability
+//! - Blob files: Large values separated from keys (key-value separation)
 //! - Compaction: Background merging of SSTables
 
 mod types;
+mod blob;
+mod backup;
+mod batch;
+mod column_family;
+mod comparator;
+mod block;
 mod memtable;
 mod sstable;
+mod external_sst;
+mod properties;
+mod range_tombstone;
+mod compaction;
+mod snapshot;
+mod version;
+mod retention;
+mod merge;
+mod transaction;
+mod ssi;
+mod lock_manager;
+mod transaction_db;
 mod wal;
 mod iterator;
 mod lsm;
 
-pub use types::{Key, Value, Entry, SeqNum};
+pub use types::{Key, Value, Entry, SeqNum, ValueKind};
+pub use blob::{BlobRef, BlobGcStats};
+pub use backup::{BackupEngine, BackupInfo};
+pub use batch::WriteBatch;
+pub use column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY};
+pub use comparator::{Comparator, BytewiseComparator, ReverseBytewiseComparator};
+pub use block::CompressionType;
 pub use memtable::MemTable;
-pub use sstable::{SSTableWriter, SSTableReader};
+pub use sstable::{SSTableWriter, SSTableReader, SSTableOptions, SSTableMeta};
+pub use external_sst::ExternalSstWriter;
+pub use range_tombstone::RangeTombstone;
+pub use compaction::{CompactionStats, CompactionFilter, CompactionDecision};
+pub use snapshot::Snapshot;
+pub use merge::MergeOperator;
+pub use transaction::{OptimisticTransaction, TransactionError};
+pub use transaction_db::{TransactionDB, TransactionDbOptions, Transaction};
+pub use retention::{RetentionPolicy, RetentionPolicies, SeqTimeMapping};
+pub use properties::{TableProperties, TablePropertiesCollector, TablePropertiesCollectorFactory};
 pub use lsm::{LsmTree, LsmConfig, LsmStats};
 pub use iterator::MergeIterator;
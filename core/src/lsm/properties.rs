@@ -0,0 +1,241 @@
+//! Table properties - statistics recorded in each SSTable's meta block.
+//!
+//! Properties are persisted as a map from name to bytes, so new properties can be
+//! added without changing the table format. Built-in properties use the
+//! `thordb.` prefix; any other name is a user-collected property, produced by a
+//! `TablePropertiesCollector` while the table is written.
+//!
+//! Map format:
+//! - count: varint
+//! - entries, sorted by name: name_len: varint, name: [u8], value_len: varint, value: [u8]
+
+use std::collections::BTreeMap;
+
+use crate::tuple::varint::{decode_varint, encode_varint};
+
+use super::block::CompressionType;
+use super::retention::SeqTimeMapping;
+use super::types::Entry;
+
+/// Prefix reserved for built-in property names.
+pub const BUILTIN_PROPERTY_PREFIX: &str = "thordb.";
+
+/// Name-to-bytes map as stored on disk.
+pub(crate) type PropertyMap = BTreeMap<String, Vec<u8>>;
+
+/// Statistics about an SSTable, recorded when it is written.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Number of tombstones.
+    pub num_deletions: u64,
+    /// Number of range tombstones.
+    pub num_range_deletions: u64,
+    /// Number of data blocks.
+    pub num_data_blocks: u64,
+    /// Total size of all keys, uncompressed.
+    pub raw_key_size: u64,
+    /// Total size of all values, uncompressed.
+    pub raw_value_size: u64,
+    /// Size of the data blocks before compression.
+    pub uncompressed_data_size: u64,
+    /// Size of the data blocks as stored.
+    pub data_size: u64,
+    /// Seconds since the Unix epoch when the table was written.
+    pub creation_time: u64,
+    /// Codec the table was written with. Individual blocks may be stored
+    /// uncompressed when compression does not save space.
+    pub compression: CompressionType,
+    /// Write times of the table's entries, for time-based retention.
+    pub seq_times: SeqTimeMapping,
+    /// Properties added by the user, by name.
+    pub user_collected: BTreeMap<String, Vec<u8>>,
+}
+
+impl TableProperties {
+    /// Add the properties to `map`.
+    pub(crate) fn encode_into(&self, map: &mut PropertyMap) {
+        put_u64(map, "num_deletions", self.num_deletions);
+        put_u64(map, "num_range_deletions", self.num_range_deletions);
+        put_u64(map, "num_data_blocks", self.num_data_blocks);
+        put_u64(map, "raw_key_size", self.raw_key_size);
+        put_u64(map, "raw_value_size", self.raw_value_size);
+        put_u64(map, "uncompressed_data_size", self.uncompressed_data_size);
+        put_u64(map, "data_size", self.data_size);
+        put_u64(map, "creation_time", self.creation_time);
+        put_u64(map, "compression", self.compression.to_byte() as u64);
+        if !self.seq_times.is_empty() {
+            put_bytes(map, "seq_times", &self.seq_times.encode());
+        }
+        for (name, value) in &self.user_collected {
+            map.insert(name.clone(), value.clone());
+        }
+    }
+
+    /// Read the properties from `map`. Unknown built-in properties are ignored, so
+    /// tables written by newer versions remain readable.
+    pub(crate) fn decode_from(map: &PropertyMap) -> Result<Self, std::io::Error> {
+        let compression = get_u64(map, "compression")?.unwrap_or(0);
+        Ok(Self {
+            num_deletions: get_u64(map, "num_deletions")?.unwrap_or(0),
+            num_range_deletions: get_u64(map, "num_range_deletions")?.unwrap_or(0),
+            num_data_blocks: get_u64(map, "num_data_blocks")?.unwrap_or(0),
+            raw_key_size: get_u64(map, "raw_key_size")?.unwrap_or(0),
+            raw_value_size: get_u64(map, "raw_value_size")?.unwrap_or(0),
+            uncompressed_data_size: get_u64(map, "uncompressed_data_size")?.unwrap_or(0),
+            data_size: get_u64(map, "data_size")?.unwrap_or(0),
+            creation_time: get_u64(map, "creation_time")?.unwrap_or(0),
+            compression: CompressionType::from_byte(compression as u8)?,
+            seq_times: match get_bytes(map, "seq_times") {
+                Some(bytes) => SeqTimeMapping::decode(bytes)?,
+                None => SeqTimeMapping::default(),
+            },
+            user_collected: map
+                .iter()
+                .filter(|(name, _)| !name.starts_with(BUILTIN_PROPERTY_PREFIX))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        })
+    }
+
+    /// Compression ratio of the data blocks (1.0 for an empty table).
+    pub fn compression_ratio(&self) -> f64 {
+        if self.data_size == 0 {
+            1.0
+        } else {
+            self.uncompressed_data_size as f64 / self.data_size as f64
+        }
+    }
+}
+
+/// Collects user-defined properties while an SSTable is written.
+///
+/// `add` is called for every entry in the order it is written (for separated
+/// values the entry holds the blob reference). The properties returned by
+/// `finish` are stored in the table and available through
+/// `TableProperties::user_collected`.
+pub trait TablePropertiesCollector: Send {
+    /// Name of the collector, used in error messages.
+    fn name(&self) -> &str;
+
+    /// Observe an entry added to the table.
+    fn add(&mut self, entry: &Entry);
+
+    /// Produce the properties to persist. Names must not start with `thordb.`.
+    fn finish(&mut self) -> BTreeMap<String, Vec<u8>>;
+}
+
+/// Creates a fresh collector for each SSTable written by an `LsmTree`.
+pub trait TablePropertiesCollectorFactory: Send + Sync {
+    fn create(&self) -> Box<dyn TablePropertiesCollector>;
+}
+
+fn builtin_name(name: &str) -> String {
+    format!("{}{}", BUILTIN_PROPERTY_PREFIX, name)
+}
+
+/// Store a built-in property as a varint.
+pub(crate) fn put_u64(map: &mut PropertyMap, name: &str, value: u64) {
+    let mut bytes = Vec::new();
+    encode_varint(value, &mut bytes).expect("Vec write cannot fail");
+    map.insert(builtin_name(name), bytes);
+}
+
+/// Store a built-in property as raw bytes.
+pub(crate) fn put_bytes(map: &mut PropertyMap, name: &str, value: &[u8]) {
+    map.insert(builtin_name(name), value.to_vec());
+}
+
+/// Read a built-in varint property, if present.
+pub(crate) fn get_u64(map: &PropertyMap, name: &str) -> Result<Option<u64>, std::io::Error> {
+    match map.get(&builtin_name(name)) {
+        Some(bytes) => match decode_varint(bytes)? {
+            (value, len) if len == bytes.len() => Ok(Some(value)),
+            _ => Err(invalid_properties("trailing bytes in integer property")),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Read a built-in bytes property, if present.
+pub(crate) fn get_bytes<'m>(map: &'m PropertyMap, name: &str) -> Option<&'m [u8]> {
+    map.get(&builtin_name(name)).map(|bytes| bytes.as_slice())
+}
+
+/// Serialize a property map.
+pub(crate) fn encode_property_map(map: &PropertyMap, out: &mut Vec<u8>) -> Result<(), std::io::Error> {
+    encode_varint(map.len() as u64, out)?;
+    for (name, value) in map {
+        encode_varint(name.len() as u64, out)?;
+        out.extend_from_slice(name.as_bytes());
+        encode_varint(value.len() as u64, out)?;
+        out.extend_from_slice(value);
+    }
+    Ok(())
+}
+
+/// Deserialize a property map written by `encode_property_map`.
+pub(crate) fn decode_property_map(data: &[u8]) -> Result<PropertyMap, std::io::Error> {
+    let mut offset = 0;
+    let read_bytes = |offset: &mut usize| -> Result<&[u8], std::io::Error> {
+        let (len, n) = decode_varint(&data[*offset..])?;
+        let start = *offset + n;
+        let bytes = start
+            .checked_add(len as usize)
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| invalid_properties("truncated property"))?;
+        *offset = start + bytes.len();
+        Ok(bytes)
+    };
+
+    let (count, n) = decode_varint(data)?;
+    offset += n;
+    let mut map = PropertyMap::new();
+    for _ in 0..count {
+        let name = String::from_utf8(read_bytes(&mut offset)?.to_vec())
+            .map_err(|_| invalid_properties("property name is not UTF-8"))?;
+        let value = read_bytes(&mut offset)?.to_vec();
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+fn invalid_properties(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid table properties: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_properties_roundtrip() {
+        let mut properties = TableProperties {
+            num_deletions: 3,
+            num_range_deletions: 1,
+            num_data_blocks: 2,
+            raw_key_size: 100,
+            raw_value_size: 5000,
+            uncompressed_data_size: 5200,
+            data_size: 1300,
+            creation_time: 1_700_000_000,
+            compression: CompressionType::Zstd,
+            seq_times: SeqTimeMapping::default(),
+            user_collected: BTreeMap::new(),
+        };
+        properties.seq_times.record(7, 1_700_000_000);
+        properties.user_collected.insert("app.max_ts".to_string(), vec![1, 2, 3]);
+
+        let mut map = PropertyMap::new();
+        properties.encode_into(&mut map);
+        put_u64(&mut map, "some_future_property", 42);
+        let mut bytes = Vec::new();
+        encode_property_map(&map, &mut bytes).unwrap();
+
+        let decoded_map = decode_property_map(&bytes).unwrap();
+        assert_eq!(decoded_map, map);
+        assert_eq!(TableProperties::decode_from(&decoded_map).unwrap(), properties);
+        assert_eq!(properties.compression_ratio(), 4.0);
+
+        assert!(decode_property_map(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
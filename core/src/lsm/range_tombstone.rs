@@ -0,0 +1,260 @@
+//! Range tombstones - deletions of every key in `[start, end)`.
+//!
+//! A range tombstone deletes all entries in its range with a lower sequence
+//! number. Tombstones may overlap, so lookups use a fragmented view: the key
+//! space is cut at every tombstone boundary into non-overlapping fragments,
+//! each carrying the sequence numbers of all tombstones covering it.
+//!
+//! Range tombstone block format (one per SSTable, optional):
+//! - count: varint
+//! - tombstones: start_len: varint, start: [u8], end_len: varint, end: [u8], seq_num: varint
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::tuple::varint::{decode_varint, encode_varint};
+
+use super::comparator::{bytewise, compare_keys, Comparator};
+use super::types::{Key, SeqNum};
+
+/// Deletes every key `k` with `start <= k < end` written before `seq_num`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Key,
+    pub end: Key,
+    pub seq_num: SeqNum,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Key, end: Key, seq_num: SeqNum) -> Self {
+        Self { start, end, seq_num }
+    }
+
+    /// Whether `key` lies in the deleted range, with keys ordered by `comparator`.
+    pub fn contains(&self, comparator: &dyn Comparator, key: &Key) -> bool {
+        compare_keys(comparator, &self.start, key).is_le() && compare_keys(comparator, key, &self.end).is_lt()
+    }
+}
+
+/// A non-overlapping piece of the key space covered by one or more tombstones.
+#[derive(Clone, Debug)]
+struct Fragment {
+    start: Key,
+    end: Key,
+    /// Sequence numbers of the covering tombstones, highest first.
+    seqs: Vec<SeqNum>,
+}
+
+/// A set of range tombstones, fragmented for lookups.
+#[derive(Clone)]
+pub struct FragmentedRangeTombstones {
+    tombstones: Vec<RangeTombstone>,
+    /// Sorted by start key, non-overlapping.
+    fragments: Vec<Fragment>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Default for FragmentedRangeTombstones {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl std::fmt::Debug for FragmentedRangeTombstones {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FragmentedRangeTombstones")
+            .field("tombstones", &self.tombstones)
+            .field("fragments", &self.fragments)
+            .field("comparator", &self.comparator.name())
+            .finish()
+    }
+}
+
+impl FragmentedRangeTombstones {
+    /// Fragment tombstones over byte-wise ordered keys.
+    pub fn new(tombstones: Vec<RangeTombstone>) -> Self {
+        Self::with_comparator(tombstones, bytewise())
+    }
+
+    /// Fragment tombstones over keys ordered by `comparator`.
+    pub fn with_comparator(tombstones: Vec<RangeTombstone>, comparator: Arc<dyn Comparator>) -> Self {
+        let fragments = fragment(&tombstones, comparator.as_ref());
+        Self {
+            tombstones,
+            fragments,
+            comparator,
+        }
+    }
+
+    /// The comparator the fragments are ordered by.
+    pub(crate) fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tombstones.is_empty()
+    }
+
+    /// The tombstones as added (unfragmented).
+    pub fn tombstones(&self) -> &[RangeTombstone] {
+        &self.tombstones
+    }
+
+    /// Sequence numbers of all tombstones covering `key`, highest first.
+    pub fn covering_seqs(&self, key: &Key) -> &[SeqNum] {
+        // Last fragment starting at or before the key
+        let comparator = self.comparator.as_ref();
+        let idx = self.fragments.partition_point(|f| compare_keys(comparator, &f.start, key).is_le());
+        match idx.checked_sub(1).map(|i| &self.fragments[i]) {
+            Some(fragment) if compare_keys(comparator, key, &fragment.end).is_lt() => &fragment.seqs,
+            _ => &[],
+        }
+    }
+
+    /// Highest sequence number of a tombstone covering `key`.
+    pub fn max_covering_seq(&self, key: &Key) -> Option<SeqNum> {
+        self.covering_seqs(key).first().copied()
+    }
+
+    /// Highest sequence number of a tombstone covering `key`, among tombstones
+    /// written at or before `seq_num`.
+    pub fn max_covering_seq_at(&self, key: &Key, seq_num: SeqNum) -> Option<SeqNum> {
+        self.covering_seqs(key).iter().copied().find(|&seq| seq <= seq_num)
+    }
+
+    /// Whether the entry for `key` at `seq_num` is deleted by a newer tombstone.
+    pub fn covers(&self, key: &Key, seq_num: SeqNum) -> bool {
+        self.max_covering_seq(key).is_some_and(|tombstone_seq| tombstone_seq > seq_num)
+    }
+
+    /// Serialize the tombstones as a range tombstone block.
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        let mut out = Vec::new();
+        encode_varint(self.tombstones.len() as u64, &mut out)?;
+        for tombstone in &self.tombstones {
+            for key in [&tombstone.start, &tombstone.end] {
+                encode_varint(key.len() as u64, &mut out)?;
+                out.extend_from_slice(key.as_bytes());
+            }
+            encode_varint(tombstone.seq_num, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    /// Deserialize a range tombstone block written by `encode`.
+    pub fn decode(data: &[u8]) -> Result<Self, std::io::Error> {
+        let truncated = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Truncated range tombstone block");
+        let mut offset = 0;
+        let read_key = |offset: &mut usize| -> Result<Key, std::io::Error> {
+            let (len, n) = decode_varint(data.get(*offset..).ok_or_else(truncated)?)?;
+            let start = *offset + n;
+            let key = start
+                .checked_add(len as usize)
+                .and_then(|end| data.get(start..end))
+                .ok_or_else(truncated)?;
+            *offset = start + key.len();
+            Ok(Key::from_slice(key))
+        };
+
+        let (count, n) = decode_varint(data)?;
+        offset += n;
+        let mut tombstones = Vec::new();
+        for _ in 0..count {
+            let start = read_key(&mut offset)?;
+            let end = read_key(&mut offset)?;
+            let (seq_num, n) = decode_varint(data.get(offset..).ok_or_else(truncated)?)?;
+            offset += n;
+            tombstones.push(RangeTombstone::new(start, end, seq_num));
+        }
+        Ok(Self::new(tombstones))
+    }
+}
+
+/// Cut the key space at every tombstone boundary and record which tombstones
+/// cover each piece.
+fn fragment(tombstones: &[RangeTombstone], comparator: &dyn Comparator) -> Vec<Fragment> {
+    // Boundary keys in order, with the seqs starting and ending there
+    let mut boundaries: Vec<(&Key, Vec<SeqNum>, Vec<SeqNum>)> = Vec::new();
+    for tombstone in tombstones.iter().filter(|t| compare_keys(comparator, &t.start, &t.end).is_lt()) {
+        boundaries.push((&tombstone.start, vec![tombstone.seq_num], Vec::new()));
+        boundaries.push((&tombstone.end, Vec::new(), vec![tombstone.seq_num]));
+    }
+    boundaries.sort_by(|a, b| compare_keys(comparator, a.0, b.0));
+    boundaries.dedup_by(|next, prev| {
+        if next.0 != prev.0 {
+            return false;
+        }
+        prev.1.append(&mut next.1);
+        prev.2.append(&mut next.2);
+        true
+    });
+
+    let mut fragments = Vec::new();
+    // Multiset of the sequence numbers of tombstones covering the current position
+    let mut active: BTreeMap<SeqNum, usize> = BTreeMap::new();
+    let mut iter = boundaries.into_iter().peekable();
+    while let Some((key, starts, ends)) = iter.next() {
+        for seq in ends {
+            if let Some(count) = active.get_mut(&seq) {
+                *count -= 1;
+                if *count == 0 {
+                    active.remove(&seq);
+                }
+            }
+        }
+        for seq in starts {
+            *active.entry(seq).or_default() += 1;
+        }
+        if let Some((next_key, ..)) = iter.peek().filter(|_| !active.is_empty()) {
+            fragments.push(Fragment {
+                start: key.clone(),
+                end: (*next_key).clone(),
+                seqs: active.keys().rev().copied().collect(),
+            });
+        }
+    }
+    fragments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::comparator::{BytewiseComparator, ReverseBytewiseComparator};
+
+    #[test]
+    fn test_overlapping_tombstones() {
+        let tombstones = FragmentedRangeTombstones::new(vec![
+            RangeTombstone::new(Key::from("b"), Key::from("f"), 10),
+            RangeTombstone::new(Key::from("d"), Key::from("h"), 20),
+            RangeTombstone::new(Key::from("x"), Key::from("x"), 30),
+        ]);
+
+        assert_eq!(tombstones.max_covering_seq(&Key::from("a")), None);
+        assert_eq!(tombstones.max_covering_seq(&Key::from("b")), Some(10));
+        assert_eq!(tombstones.max_covering_seq(&Key::from("c")), Some(10));
+        assert_eq!(tombstones.max_covering_seq(&Key::from("e")), Some(20));
+        assert_eq!(tombstones.max_covering_seq(&Key::from("g")), Some(20));
+        // End keys are exclusive; empty ranges delete nothing
+        assert_eq!(tombstones.max_covering_seq(&Key::from("h")), None);
+        assert_eq!(tombstones.max_covering_seq(&Key::from("x")), None);
+
+        assert_eq!(tombstones.covering_seqs(&Key::from("e")), [20, 10]);
+        assert_eq!(tombstones.max_covering_seq_at(&Key::from("e"), 15), Some(10));
+        assert_eq!(tombstones.max_covering_seq_at(&Key::from("e"), 5), None);
+
+        assert!(tombstones.covers(&Key::from("c"), 9));
+        assert!(!tombstones.covers(&Key::from("c"), 11));
+
+        let tombstone = RangeTombstone::new(Key::from("b"), Key::from("f"), 10);
+        assert!(tombstone.contains(&BytewiseComparator, &Key::from("b")));
+        assert!(!tombstone.contains(&BytewiseComparator, &Key::from("f")));
+        // In reverse order, nothing lies between b and f
+        let reversed = RangeTombstone::new(Key::from("f"), Key::from("b"), 10);
+        assert!(reversed.contains(&ReverseBytewiseComparator, &Key::from("c")));
+        assert!(!tombstone.contains(&ReverseBytewiseComparator, &Key::from("c")));
+
+        let decoded = FragmentedRangeTombstones::decode(&tombstones.encode().unwrap()).unwrap();
+        assert_eq!(decoded.tombstones(), tombstones.tombstones());
+        assert_eq!(decoded.max_covering_seq(&Key::from("e")), Some(20));
+    }
+}
@@ -0,0 +1,322 @@
+//! Version retention - how many historic versions of a key to keep.
+//!
+//! Duplicate key support keeps every version by default. A `RetentionPolicy`
+//! limits history by count or by age, chosen per key prefix, and is applied
+//! when the memtable is flushed and during compaction. The newest version of a
+//! key, and any version a live snapshot can see, is always kept.
+//!
+//! Entries carry no timestamp, so ages come from a `SeqTimeMapping`: samples
+//! recorded as writes happen and persisted in table properties, bounding the
+//! time each sequence number was written.
+//!
+//! Mapping format:
+//! - count: varint
+//! - samples, ascending: seq_num: varint, unix_secs: varint
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::tuple::varint::{decode_varint, encode_varint};
+
+use super::compaction::snapshot_between;
+use super::types::{Entry, Key, SeqNum, ValueKind};
+
+/// Samples kept per mapping; older samples are thinned out beyond this.
+const MAX_SEQ_TIME_SAMPLES: usize = 1024;
+
+/// Which versions of a key to keep.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// Keep every version.
+    #[default]
+    KeepAll,
+    /// Keep the given number of newest versions (at least one).
+    KeepLast(usize),
+    /// Keep versions written within the given duration.
+    KeepFor(Duration),
+}
+
+/// Retention policies by key prefix.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicies {
+    default: RetentionPolicy,
+    prefixes: Vec<(Vec<u8>, RetentionPolicy)>,
+}
+
+impl RetentionPolicies {
+    /// Apply `default` to keys matching no prefix.
+    pub fn new(default: RetentionPolicy) -> Self {
+        Self {
+            default,
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Apply `policy` to keys starting with `prefix`. The longest matching prefix wins.
+    pub fn with_prefix(mut self, prefix: impl Into<Vec<u8>>, policy: RetentionPolicy) -> Self {
+        self.prefixes.push((prefix.into(), policy));
+        self
+    }
+
+    /// Policy for `key`.
+    pub fn policy_for(&self, key: &Key) -> &RetentionPolicy {
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| key.as_bytes().starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(&self.default, |(_, policy)| policy)
+    }
+}
+
+/// Samples `(seq_num, time)`, each stating that every entry up to `seq_num`
+/// was written at or before `time` (seconds since the Unix epoch).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeqTimeMapping {
+    /// Ascending by sequence number and time.
+    samples: Vec<(SeqNum, u64)>,
+}
+
+impl SeqTimeMapping {
+    /// Record that `seq_num` was written at `time`.
+    pub fn record(&mut self, seq_num: SeqNum, time: u64) {
+        match self.samples.last_mut() {
+            Some(last) if last.1 >= time => last.0 = last.0.max(seq_num),
+            _ => self.samples.push((seq_num, time)),
+        }
+        self.thin();
+    }
+
+    /// Add the samples of `other`.
+    pub fn merge(&mut self, other: &SeqTimeMapping) {
+        self.samples.extend_from_slice(&other.samples);
+        self.samples.sort_unstable();
+        // Keep only samples that are not implied by a later one
+        let mut merged: Vec<(SeqNum, u64)> = Vec::with_capacity(self.samples.len());
+        for &(seq_num, time) in &self.samples {
+            while merged.last().is_some_and(|&(_, t)| t >= time) {
+                merged.pop();
+            }
+            merged.push((seq_num, time));
+        }
+        self.samples = merged;
+        self.thin();
+    }
+
+    /// Latest time `seq_num` can have been written at, if known.
+    pub fn write_time(&self, seq_num: SeqNum) -> Option<u64> {
+        let idx = self.samples.partition_point(|&(s, _)| s < seq_num);
+        self.samples.get(idx).map(|&(_, time)| time)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Dropping a sample only makes the bounds of earlier sequence numbers later,
+    /// so thinning never makes a version look older than it is.
+    fn thin(&mut self) {
+        if self.samples.len() > MAX_SEQ_TIME_SAMPLES {
+            let last = self.samples.len() - 1;
+            let mut idx = 0;
+            self.samples.retain(|_| {
+                idx += 1;
+                idx % 2 == 0 || idx - 1 == last
+            });
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_varint(self.samples.len() as u64, &mut out).expect("Vec write cannot fail");
+        for &(seq_num, time) in &self.samples {
+            encode_varint(seq_num, &mut out).expect("Vec write cannot fail");
+            encode_varint(time, &mut out).expect("Vec write cannot fail");
+        }
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, std::io::Error> {
+        let truncated = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Truncated seq time mapping");
+        let read = |offset: &mut usize| -> Result<u64, std::io::Error> {
+            let (value, n) = decode_varint(data.get(*offset..).ok_or_else(truncated)?)?;
+            *offset += n;
+            Ok(value)
+        };
+        let mut offset = 0;
+        let count = read(&mut offset)?;
+        let mut samples = Vec::new();
+        for _ in 0..count {
+            samples.push((read(&mut offset)?, read(&mut offset)?));
+        }
+        Ok(Self { samples })
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Drops versions the retention policies no longer keep from an iterator of
+/// entries sorted by key, newest version first.
+pub(crate) struct RetentionIterator<'a, I> {
+    inner: I,
+    policies: &'a RetentionPolicies,
+    seq_times: SeqTimeMapping,
+    /// Live snapshot sequence numbers, ascending.
+    snapshots: Vec<SeqNum>,
+    now: u64,
+    current_key: Option<Key>,
+    /// Versions of the current key seen so far.
+    versions_seen: usize,
+    /// Sequence number of the previous (newer) version of the current key.
+    newer_seq: SeqNum,
+    /// Whether all newer versions of the current key are merge operands, which
+    /// need this version to apply to.
+    below_operands: bool,
+    versions_pruned: u64,
+}
+
+impl<'a, I> RetentionIterator<'a, I>
+where
+    I: Iterator<Item = Entry>,
+{
+    pub(crate) fn new(
+        inner: I,
+        policies: &'a RetentionPolicies,
+        seq_times: SeqTimeMapping,
+        snapshots: Vec<SeqNum>,
+        now: u64,
+    ) -> Self {
+        Self {
+            inner,
+            policies,
+            seq_times,
+            snapshots,
+            now,
+            current_key: None,
+            versions_seen: 0,
+            newer_seq: SeqNum::MAX,
+            below_operands: true,
+            versions_pruned: 0,
+        }
+    }
+
+    pub(crate) fn versions_pruned(&self) -> u64 {
+        self.versions_pruned
+    }
+
+    pub(crate) fn inner(&self) -> &I {
+        &self.inner
+    }
+
+    fn keep(&self, entry: &Entry) -> bool {
+        // The newest value, and versions a snapshot reads, always stay
+        if self.below_operands || snapshot_between(&self.snapshots, entry.seq_num, self.newer_seq) {
+            return true;
+        }
+        match self.policies.policy_for(&entry.key) {
+            RetentionPolicy::KeepAll => true,
+            RetentionPolicy::KeepLast(count) => self.versions_seen < *count,
+            RetentionPolicy::KeepFor(duration) => match self.seq_times.write_time(entry.seq_num) {
+                Some(time) => self.now.saturating_sub(time) < duration.as_secs(),
+                None => true,
+            },
+        }
+    }
+}
+
+impl<I> Iterator for RetentionIterator<'_, I>
+where
+    I: Iterator<Item = Entry>,
+{
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            let entry = self.inner.next()?;
+            if self.current_key.as_ref() != Some(&entry.key) {
+                self.current_key = Some(entry.key.clone());
+                self.versions_seen = 0;
+                self.newer_seq = SeqNum::MAX;
+                self.below_operands = true;
+            }
+
+            let keep = self.keep(&entry);
+            self.versions_seen += 1;
+            self.newer_seq = entry.seq_num;
+            self.below_operands &= entry.value_kind == ValueKind::Merge;
+            if keep {
+                return Some(entry);
+            }
+            self.versions_pruned += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::types::Value;
+
+    fn versions(key: &str, seqs: &[SeqNum]) -> Vec<Entry> {
+        seqs.iter().map(|&seq| Entry::put(Key::from(key), seq, Value::from("v"))).collect()
+    }
+
+    #[test]
+    fn test_retention_policies() {
+        let policies = RetentionPolicies::new(RetentionPolicy::KeepLast(2))
+            .with_prefix("audit/", RetentionPolicy::KeepAll)
+            .with_prefix("cache/", RetentionPolicy::KeepFor(Duration::from_secs(60)));
+        let mut seq_times = SeqTimeMapping::default();
+        seq_times.record(10, 1_000);
+        seq_times.record(20, 1_100);
+        seq_times.record(30, 1_180);
+
+        let mut entries = versions("audit/x", &[5, 4, 3]);
+        entries.extend(versions("cache/x", &[30, 25, 15, 5]));
+        entries.extend(versions("user/x", &[9, 8, 7, 6]));
+        let kept = |snapshots: Vec<SeqNum>| {
+            RetentionIterator::new(entries.clone().into_iter(), &policies, seq_times.clone(), snapshots, 1_200)
+                .map(|e| (e.key.as_bytes().to_vec(), e.seq_num))
+                .collect::<Vec<_>>()
+        };
+
+        let without_snapshots = kept(vec![]);
+        assert_eq!(
+            without_snapshots,
+            [
+                (b"audit/x".to_vec(), 5),
+                (b"audit/x".to_vec(), 4),
+                (b"audit/x".to_vec(), 3),
+                (b"cache/x".to_vec(), 30),
+                (b"cache/x".to_vec(), 25),
+                (b"user/x".to_vec(), 9),
+                (b"user/x".to_vec(), 8),
+            ]
+        );
+        // A snapshot at 6 reads version 6 of user/x
+        assert!(kept(vec![6]).contains(&(b"user/x".to_vec(), 6)));
+    }
+
+    #[test]
+    fn test_seq_time_mapping() {
+        let mut mapping = SeqTimeMapping::default();
+        for seq in 1..=5000 {
+            mapping.record(seq, 1_000 + seq / 2);
+        }
+        assert!(mapping.samples.len() <= MAX_SEQ_TIME_SAMPLES);
+        // Bounds stay conservative after thinning
+        assert!(mapping.write_time(100).unwrap() >= 1_050);
+        assert_eq!(mapping.write_time(5000), Some(3_500));
+        assert_eq!(mapping.write_time(5001), None);
+
+        let mut other = SeqTimeMapping::default();
+        other.record(6000, 4_000);
+        mapping.merge(&other);
+        assert_eq!(mapping.write_time(5500), Some(4_000));
+        assert_eq!(SeqTimeMapping::decode(&mapping.encode()).unwrap(), mapping);
+    }
+}
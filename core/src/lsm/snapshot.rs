@@ -0,0 +1,62 @@
+//! Snapshots - consistent read views pinned at a sequence number.
+//!
+//! A snapshot sees every entry with a sequence number up to its own. While a
+//! snapshot is alive, compaction keeps the versions it can see, even when they
+//! are deleted by a newer tombstone.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use super::types::SeqNum;
+
+/// Sequence numbers of the live snapshots of an `LsmTree`.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotList {
+    /// Sequence number -> number of snapshots taken at it.
+    seqs: Mutex<BTreeMap<SeqNum, usize>>,
+}
+
+impl SnapshotList {
+    pub(crate) fn acquire(self: &Arc<Self>, seq_num: SeqNum) -> Snapshot {
+        *self.seqs.lock().unwrap().entry(seq_num).or_default() += 1;
+        Snapshot {
+            seq_num,
+            list: self.clone(),
+        }
+    }
+
+    fn release(&self, seq_num: SeqNum) {
+        let mut seqs = self.seqs.lock().unwrap();
+        if let Some(count) = seqs.get_mut(&seq_num) {
+            *count -= 1;
+            if *count == 0 {
+                seqs.remove(&seq_num);
+            }
+        }
+    }
+
+    /// Sequence numbers of all live snapshots, ascending.
+    pub(crate) fn seqs(&self) -> Vec<SeqNum> {
+        self.seqs.lock().unwrap().keys().copied().collect()
+    }
+}
+
+/// A read view of an `LsmTree`, released when dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    seq_num: SeqNum,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// Newest sequence number visible to the snapshot.
+    pub fn seq_num(&self) -> SeqNum {
+        self.seq_num
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.seq_num);
+    }
+}
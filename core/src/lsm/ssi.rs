@@ -0,0 +1,165 @@
+//! Serializable snapshot isolation - detecting rw-antidependency cycles.
+//!
+//! Snapshot isolation lets two transactions each read what the other writes
+//! (write skew). Every serializable transaction registers the keys and ranges it
+//! reads; at commit its writes are registered with its commit sequence number.
+//! Transactions are concurrent if neither committed before the other's snapshot.
+//!
+//! A concurrent transaction reading a version another one overwrites is an
+//! rw-antidependency from the reader to the writer. Every cycle in the
+//! serialization graph contains a pivot, a transaction with both an incoming and
+//! an outgoing rw-antidependency. A commit fails if it would make itself, or an
+//! already committed transaction, such a pivot. This may abort transactions that
+//! would have been serializable, but never lets a cycle commit.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+use super::comparator::{range_contains, Comparator};
+use super::transaction::TransactionError;
+use super::types::{Key, SeqNum};
+
+pub(crate) type SsiTxnId = u64;
+
+#[derive(Debug, Default)]
+struct SsiTxn {
+    snapshot_seq: SeqNum,
+    /// Sequence number of the last write, once committed.
+    commit_seq: Option<SeqNum>,
+    reads: HashSet<Key>,
+    read_ranges: Vec<(Bound<Key>, Bound<Key>)>,
+    writes: HashSet<Key>,
+    /// A concurrent transaction read a version this one overwrote.
+    in_conflict: bool,
+    /// This transaction read a version a concurrent one overwrote.
+    out_conflict: bool,
+}
+
+impl SsiTxn {
+    fn has_read(&self, key: &Key, comparator: &dyn Comparator) -> bool {
+        self.reads.contains(key) || self.read_ranges.iter().any(|range| range_contains(comparator, range, key))
+    }
+
+    /// Whether this transaction overlapped with one whose snapshot is at `snapshot_seq`.
+    fn concurrent_with(&self, snapshot_seq: SeqNum) -> bool {
+        self.commit_seq.is_none_or(|commit_seq| commit_seq > snapshot_seq)
+    }
+}
+
+/// The serializable transactions of an `LsmTree`, and those committed while
+/// any of them was running.
+pub(crate) struct SsiTracker {
+    next_id: Mutex<SsiTxnId>,
+    txns: Mutex<HashMap<SsiTxnId, SsiTxn>>,
+    /// Order of keys, for read ranges.
+    comparator: Arc<dyn Comparator>,
+}
+
+impl SsiTracker {
+    pub(crate) fn new(comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            next_id: Mutex::new(0),
+            txns: Mutex::new(HashMap::new()),
+            comparator,
+        }
+    }
+
+    /// Register a transaction reading through a snapshot at `snapshot_seq`.
+    pub(crate) fn begin(&self, snapshot_seq: SeqNum) -> SsiTxnId {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        let txn = SsiTxn {
+            snapshot_seq,
+            ..Default::default()
+        };
+        self.txns.lock().unwrap().insert(id, txn);
+        id
+    }
+
+    pub(crate) fn record_read(&self, id: SsiTxnId, key: &Key) {
+        if let Some(txn) = self.txns.lock().unwrap().get_mut(&id) {
+            txn.reads.insert(key.clone());
+        }
+    }
+
+    pub(crate) fn record_range(&self, id: SsiTxnId, range: (Bound<Key>, Bound<Key>)) {
+        if let Some(txn) = self.txns.lock().unwrap().get_mut(&id) {
+            txn.read_ranges.push(range);
+        }
+    }
+
+    /// Forget a transaction that did not commit.
+    pub(crate) fn end(&self, id: SsiTxnId) {
+        let mut txns = self.txns.lock().unwrap();
+        if txns.get(&id).is_some_and(|txn| txn.commit_seq.is_none()) {
+            txns.remove(&id);
+        }
+    }
+
+    /// Check that committing `writes` at `commit_seq` closes no cycle, and
+    /// register them if so. Must be called with writes blocked. Committed
+    /// transactions older than `oldest_snapshot` are forgotten, as no running
+    /// transaction can be concurrent with them.
+    pub(crate) fn commit(
+        &self,
+        id: SsiTxnId,
+        writes: HashSet<Key>,
+        commit_seq: SeqNum,
+        oldest_snapshot: Option<SeqNum>,
+    ) -> Result<(), std::io::Error> {
+        let mut txns = self.txns.lock().unwrap();
+        let txn = txns.get(&id).expect("transaction registered at begin");
+        let unserializable = |key: &Key| TransactionError::Unserializable { key: key.clone() };
+        let comparator = self.comparator.as_ref();
+
+        let mut out_edges = Vec::new();
+        let mut in_edges = Vec::new();
+        // An incoming edge can only be found now, once the writes are known
+        let mut in_key = None;
+        for (&other_id, other) in txns.iter() {
+            if other_id == id || !other.concurrent_with(txn.snapshot_seq) {
+                continue;
+            }
+            // This transaction read a version the other, committed, one overwrote
+            if let Some(key) = other.writes.iter().find(|key| txn.has_read(key, comparator)) {
+                if other.out_conflict {
+                    return Err(unserializable(key).into());
+                }
+                out_edges.push(other_id);
+            }
+            // The other transaction read a version this one overwrites
+            if let Some(key) = writes.iter().find(|key| other.has_read(key, comparator)) {
+                if other.commit_seq.is_some() && other.in_conflict {
+                    return Err(unserializable(key).into());
+                }
+                in_edges.push(other_id);
+                in_key.get_or_insert_with(|| key.clone());
+            }
+        }
+        let out_conflict = txn.out_conflict || !out_edges.is_empty();
+        if let Some(key) = &in_key
+            && out_conflict
+        {
+            return Err(unserializable(key).into());
+        }
+
+        for other_id in out_edges {
+            txns.get_mut(&other_id).unwrap().in_conflict = true;
+        }
+        for other_id in in_edges {
+            txns.get_mut(&other_id).unwrap().out_conflict = true;
+        }
+        let txn = txns.get_mut(&id).unwrap();
+        txn.writes = writes;
+        txn.commit_seq = Some(commit_seq);
+        txn.in_conflict = in_key.is_some();
+        txn.out_conflict = out_conflict;
+
+        txns.retain(|_, txn| txn.commit_seq.is_none() || oldest_snapshot.is_some_and(|s| txn.concurrent_with(s)));
+        Ok(())
+    }
+}
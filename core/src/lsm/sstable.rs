@@ -1,18 +1,42 @@
 //! SSTable (Sorted String Table) - immutable on-disk sorted files.
 //!
 //! Uses SerialPages for storage. Each SSTable consists of:
-//! - Data pages: Sorted entries
-//! - Metadata: Entry count, min/max keys, page range
+//! - Data pages: Sorted entries grouped into blocks, one record cell per block
+//! - Overflow pages: Continuation of blocks larger than a page, interleaved with data pages
+//! - Range tombstone block: Optional record on the page after the data pages
+//! - Meta block: Record in page 0 (continuing after the data pages if large)
+//!
+//! Meta block format:
+//! - magic: [u8; 8] (`SSTABLE_MAGIC`)
+//! - format_version: u32
+//! - properties: property map (see `properties`) holding the table layout and
+//!   `TableProperties`
 
+use std::ops::{Bound, RangeBounds};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::bufferpool::{BufferPool, PageAddr};
-use crate::page::{Page, PageMut, PageRead};
-use crate::tuple::tuple::{Tuple, TupleOnDisk};
-use crate::tuple::types::TupleValue;
-
+use crate::error::Corruption;
+use crate::page::{Page, PageMut, PageRead, PageType};
+use crate::serialpages::{SerialWriter, read_record, record_prefix, write_record};
+
+use super::block::{Block, BlockBuilder, BlockHeader, CompressionType, DEFAULT_BLOCK_SIZE, DEFAULT_RESTART_INTERVAL, decode_block};
+use super::comparator::{bytewise, compare_keys, Comparator};
+use super::properties::{
+    BUILTIN_PROPERTY_PREFIX, PropertyMap, TableProperties, TablePropertiesCollector, decode_property_map, encode_property_map, get_bytes, get_u64, put_bytes, put_u64,
+};
+use super::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use super::retention::SeqTimeMapping;
 use super::types::{Entry, Key, SeqNum};
 
+/// Identifies page 0 of a file as an SSTable meta block.
+pub const SSTABLE_MAGIC: [u8; 8] = *b"THORSST\0";
+
+/// Version of the SSTable format written by this build.
+pub const SSTABLE_FORMAT_VERSION: u32 = 1;
+
 /// Metadata for an SSTable stored in the first page.
 #[derive(Clone, Debug)]
 pub struct SSTableMeta {
@@ -32,63 +56,118 @@ pub struct SSTableMeta {
     pub min_seq: SeqNum,
     /// Maximum sequence number.
     pub max_seq: SeqNum,
+    /// Page holding the range tombstone block, if the table has range tombstones.
+    pub range_tombstone_page: Option<u64>,
+    /// Statistics recorded when the table was written.
+    pub properties: TableProperties,
+}
+
+/// Options for writing an SSTable.
+#[derive(Clone, Copy, Debug)]
+pub struct SSTableOptions {
+    /// Target size of a block before compression.
+    pub block_size: usize,
+    /// Number of entries between restart points within a block.
+    pub restart_interval: usize,
+    /// Codec used to compress blocks.
+    pub compression: CompressionType,
+}
+
+impl Default for SSTableOptions {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            restart_interval: DEFAULT_RESTART_INTERVAL,
+            compression: CompressionType::None,
+        }
+    }
 }
 
 /// Writer for creating an SSTable.
-pub struct SSTableWriter<'a> {
-    buffer_pool: &'a BufferPool,
+pub struct SSTableWriter {
+    buffer_pool: Arc<BufferPool>,
     file_id: u64,
-    current_page: u64,
-    current_page_mut: PageMut<'a>,
+    options: SSTableOptions,
+    /// Data page being filled; a `SerialWriter` resumes there for each block.
+    page_address: PageAddr,
+    /// First page not yet used by data or overflow pages.
+    next_free_page: PageAddr,
+    block: BlockBuilder,
     entry_count: u64,
     min_key: Option<Key>,
     max_key: Option<Key>,
     min_seq: SeqNum,
     max_seq: SeqNum,
+    properties: TableProperties,
+    collectors: Vec<Box<dyn TablePropertiesCollector>>,
+    range_tombstones: Vec<RangeTombstone>,
 }
 
-impl<'a> SSTableWriter<'a> {
-    /// Create a new SSTable writer.
+impl SSTableWriter {
+    /// Create a new SSTable writer with default options.
     /// `file_id` is used for PageAddr file_id.
     /// Page 0 is reserved for metadata, data starts at page 1.
-    pub fn new(buffer_pool: &'a BufferPool, file_id: u64) -> Result<Self, std::io::Error> {
+    pub fn new(buffer_pool: Arc<BufferPool>, file_id: u64) -> Result<Self, std::io::Error> {
+        Self::with_options(buffer_pool, file_id, SSTableOptions::default())
+    }
+
+    /// Create a new SSTable writer.
+    pub fn with_options(
+        buffer_pool: Arc<BufferPool>,
+        file_id: u64,
+        options: SSTableOptions,
+    ) -> Result<Self, std::io::Error> {
         // Start writing at page 1 (page 0 is for metadata)
         let page_addr = PageAddr::new(file_id, 1);
-        let current_page_mut = PageMut::open(buffer_pool, page_addr)?;
+        let next_free_page = SerialWriter::new(&buffer_pool, page_addr)?.next_free_page();
 
         Ok(Self {
             buffer_pool,
             file_id,
-            current_page: 1,
-            current_page_mut,
+            options,
+            page_address: page_addr,
+            next_free_page,
+            block: BlockBuilder::new(options.restart_interval),
             entry_count: 0,
             min_key: None,
             max_key: None,
             min_seq: SeqNum::MAX,
             max_seq: 0,
+            properties: TableProperties {
+                compression: options.compression,
+                ..Default::default()
+            },
+            collectors: Vec::new(),
+            range_tombstones: Vec::new(),
         })
     }
 
-    /// Write an entry to the SSTable.
-    pub fn write_entry(&mut self, entry: &Entry) -> Result<(), std::io::Error> {
-        // Serialize entry
-        let mut entry_bytes = Vec::new();
-        entry.write_to(&mut entry_bytes)?;
+    /// Add a range tombstone. Range tombstones may be added in any order.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.properties.num_range_deletions += 1;
+        self.min_seq = self.min_seq.min(tombstone.seq_num);
+        self.max_seq = self.max_seq.max(tombstone.seq_num);
+        self.range_tombstones.push(tombstone);
+    }
 
-        // Create a tuple with the serialized entry as VarBytes
-        let tuple = Tuple::new(vec![TupleValue::VarBytes(&entry_bytes)]);
+    /// Record when the table's entries were written, for time-based retention.
+    pub fn set_seq_times(&mut self, seq_times: SeqTimeMapping) {
+        self.properties.seq_times = seq_times;
+    }
 
-        // Check if we need a new page
-        if !self.current_page_mut.has_space_for_cell(tuple.len())? {
-            self.current_page += 1;
-            let page_addr = PageAddr::new(self.file_id, self.current_page);
-            self.current_page_mut = PageMut::open(self.buffer_pool, page_addr)?;
-        }
+    /// Add a collector that sees every entry written and contributes properties on finish.
+    pub fn add_collector(&mut self, collector: Box<dyn TablePropertiesCollector>) {
+        self.collectors.push(collector);
+    }
 
-        // Write the tuple
-        let cell_buffer = self.current_page_mut.allocate_cell(tuple.len())?;
-        let mut cursor = std::io::Cursor::new(cell_buffer);
-        tuple.write_to_stream(&mut cursor)?;
+    /// Write an entry to the SSTable.
+    /// Entries are buffered into blocks; a block larger than a page continues in overflow pages.
+    pub fn write_entry(&mut self, entry: &Entry) -> Result<(), std::io::Error> {
+        // Cut the block before it grows past the target size
+        if !self.block.is_empty() && self.block.size() + entry.serialized_size() > self.options.block_size {
+            self.flush_block()?;
+        }
+        self.block.add(entry)?;
 
         // Update metadata
         self.entry_count += 1;
@@ -98,53 +177,126 @@ impl<'a> SSTableWriter<'a> {
         self.max_key = Some(entry.key.clone());
         self.min_seq = self.min_seq.min(entry.seq_num);
         self.max_seq = self.max_seq.max(entry.seq_num);
+        self.properties.raw_key_size += entry.key.len() as u64;
+        match &entry.value {
+            Some(value) => self.properties.raw_value_size += value.len() as u64,
+            None => self.properties.num_deletions += 1,
+        }
+        for collector in &mut self.collectors {
+            collector.add(entry);
+        }
 
         Ok(())
     }
 
+    fn flush_block(&mut self) -> Result<(), std::io::Error> {
+        let block = std::mem::replace(&mut self.block, BlockBuilder::new(self.options.restart_interval));
+        let block_max_seq = block.max_seq();
+        self.properties.num_data_blocks += 1;
+        self.properties.uncompressed_data_size += block.size() as u64;
+        let (record, stored_len) = block.finish(self.options.compression)?;
+        self.properties.data_size += stored_len as u64;
+
+        let mut writer = SerialWriter::resume(&self.buffer_pool, self.page_address, self.next_free_page)?;
+        writer.append_record(&record)?;
+
+        // The page LSN tracks the newest sequence number stored in the page
+        let page = writer.page_mut();
+        let lsn = page.lsn()?.max(block_max_seq);
+        page.set_lsn(lsn)?;
+        self.page_address = writer.page_address();
+        self.next_free_page = writer.next_free_page();
+        Ok(())
+    }
+
     /// Finish writing and return metadata.
-    pub fn finish(self) -> Result<SSTableMeta, std::io::Error> {
+    pub fn finish(mut self) -> Result<SSTableMeta, std::io::Error> {
+        if !self.block.is_empty() {
+            self.flush_block()?;
+        }
+
         let min_key = self.min_key.clone().unwrap_or_else(|| Key::new(vec![]));
         let max_key = self.max_key.clone().unwrap_or_else(|| Key::new(vec![]));
+        let mut properties = std::mem::take(&mut self.properties);
+        for collector in &mut self.collectors {
+            for (name, value) in collector.finish() {
+                if name.starts_with(BUILTIN_PROPERTY_PREFIX) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Collector {} uses reserved property name {}", collector.name(), name),
+                    ));
+                }
+                properties.user_collected.insert(name, value);
+            }
+        }
+        properties.creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
         
+        let end_page = self.next_free_page.page_id() - 1;
+        let range_tombstone_page = if self.range_tombstones.is_empty() {
+            None
+        } else {
+            Some(end_page + 1)
+        };
+
         let meta = SSTableMeta {
             id: self.file_id,
             entry_count: self.entry_count,
             start_page: 1,
-            end_page: self.current_page,
+            end_page,
             min_key,
             max_key,
             min_seq: if self.min_seq == SeqNum::MAX { 0 } else { self.min_seq },
             max_seq: self.max_seq,
+            range_tombstone_page,
+            properties,
         };
 
+        // Range tombstones go on their own page after the data, followed by
+        // the overflow pages of the meta block if it needs any
+        let mut next_free_page = end_page + 1;
+        if let Some(page_id) = range_tombstone_page {
+            let block = FragmentedRangeTombstones::new(std::mem::take(&mut self.range_tombstones)).encode()?;
+            let page_addr = PageAddr::new(self.file_id, page_id);
+            let mut page = PageMut::open_with_type(&self.buffer_pool, page_addr, PageType::Meta)?;
+            page.set_lsn(meta.max_seq)?;
+            let overflow_start = PageAddr::new(self.file_id, page_id + 1);
+            next_free_page = page_id + 1 + write_record(&self.buffer_pool, &mut page, &block, overflow_start)?;
+        }
+
         // Write metadata to page 0
-        self.write_metadata(&meta)?;
+        self.write_metadata(&meta, next_free_page)?;
 
         Ok(meta)
     }
 
-    fn write_metadata(&self, meta: &SSTableMeta) -> Result<(), std::io::Error> {
-        let page_addr = PageAddr::new(self.file_id, 0);
-        let mut meta_page = PageMut::open(self.buffer_pool, page_addr)?;
+    fn write_metadata(&self, meta: &SSTableMeta, next_free_page: u64) -> Result<(), std::io::Error> {
+        let mut map = PropertyMap::new();
+        put_u64(&mut map, "num_entries", meta.entry_count);
+        put_u64(&mut map, "start_page", meta.start_page);
+        put_u64(&mut map, "end_page", meta.end_page);
+        put_u64(&mut map, "min_seq", meta.min_seq);
+        put_u64(&mut map, "max_seq", meta.max_seq);
+        put_bytes(&mut map, "min_key", meta.min_key.as_bytes());
+        put_bytes(&mut map, "max_key", meta.max_key.as_bytes());
+        if let Some(page_id) = meta.range_tombstone_page {
+            put_u64(&mut map, "range_tombstone_page", page_id);
+        }
+        meta.properties.encode_into(&mut map);
 
-        // Serialize metadata as a tuple
         let mut meta_bytes = Vec::new();
-        meta_bytes.extend_from_slice(&meta.id.to_le_bytes());
-        meta_bytes.extend_from_slice(&meta.entry_count.to_le_bytes());
-        meta_bytes.extend_from_slice(&meta.start_page.to_le_bytes());
-        meta_bytes.extend_from_slice(&meta.end_page.to_le_bytes());
-        meta_bytes.extend_from_slice(&meta.min_seq.to_le_bytes());
-        meta_bytes.extend_from_slice(&meta.max_seq.to_le_bytes());
-        meta_bytes.extend_from_slice(&(meta.min_key.len() as u32).to_le_bytes());
-        meta_bytes.extend_from_slice(meta.min_key.as_bytes());
-        meta_bytes.extend_from_slice(&(meta.max_key.len() as u32).to_le_bytes());
-        meta_bytes.extend_from_slice(meta.max_key.as_bytes());
-
-        let tuple = Tuple::new(vec![TupleValue::VarBytes(&meta_bytes)]);
-        let cell_buffer = meta_page.allocate_cell(tuple.len())?;
-        let mut cursor = std::io::Cursor::new(cell_buffer);
-        tuple.write_to_stream(&mut cursor)?;
+        meta_bytes.extend_from_slice(&SSTABLE_MAGIC);
+        meta_bytes.extend_from_slice(&SSTABLE_FORMAT_VERSION.to_le_bytes());
+        encode_property_map(&map, &mut meta_bytes)?;
+
+        let page_addr = PageAddr::new(self.file_id, 0);
+        let mut meta_page = PageMut::open_with_type(&self.buffer_pool, page_addr, PageType::Meta)?;
+        meta_page.set_lsn(meta.max_seq)?;
+        // A meta block larger than a page continues after the data pages
+        let overflow_start = PageAddr::new(self.file_id, next_free_page);
+        write_record(&self.buffer_pool, &mut meta_page, &meta_bytes, overflow_start)?;
 
         Ok(())
     }
@@ -154,84 +306,176 @@ impl<'a> SSTableWriter<'a> {
 pub struct SSTableReader {
     buffer_pool: Arc<BufferPool>,
     pub meta: SSTableMeta,
+    /// Level of the table in the LSM tree (0 for flushed tables).
+    pub level: usize,
+    range_tombstones: FragmentedRangeTombstones,
+    /// Order the table's keys were written in.
+    comparator: Arc<dyn Comparator>,
+    /// The table left the tree; its file is deleted when the reader is dropped.
+    obsolete: AtomicBool,
+    /// Sequence number of every entry of an ingested table, which was written without one.
+    global_seq: Option<SeqNum>,
 }
 
 impl SSTableReader {
-    /// Open an existing SSTable.
+    /// Open an existing SSTable with byte-wise ordered keys.
     pub fn open(buffer_pool: Arc<BufferPool>, file_id: u64) -> Result<Self, std::io::Error> {
+        Self::open_with_comparator(buffer_pool, file_id, bytewise())
+    }
+
+    /// Open an existing SSTable whose keys were written in `comparator` order.
+    pub fn open_with_comparator(
+        buffer_pool: Arc<BufferPool>,
+        file_id: u64,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self, std::io::Error> {
         let meta = Self::read_metadata(&buffer_pool, file_id)?;
-        Ok(Self { buffer_pool, meta })
+        let tombstones = match meta.range_tombstone_page {
+            Some(page_id) => {
+                let page = Page::open(&buffer_pool, PageAddr::new(file_id, page_id))?;
+                let cell = page.read_cell(0)?;
+                FragmentedRangeTombstones::decode(&read_record(&buffer_pool, &page, cell)?)?
+                    .tombstones()
+                    .to_vec()
+            }
+            None => Vec::new(),
+        };
+        Ok(Self {
+            buffer_pool,
+            meta,
+            level: 0,
+            range_tombstones: FragmentedRangeTombstones::with_comparator(tombstones, comparator.clone()),
+            comparator,
+            obsolete: AtomicBool::new(false),
+            global_seq: None,
+        })
+    }
+
+    /// Sequence number given to all entries of an ingested table.
+    pub fn global_seq(&self) -> Option<SeqNum> {
+        self.global_seq
+    }
+
+    /// Read every entry of the table with sequence number `seq`.
+    pub(crate) fn set_global_seq(&mut self, seq: SeqNum) {
+        self.global_seq = Some(seq);
+        self.meta.min_seq = seq;
+        self.meta.max_seq = seq;
+    }
+
+    fn apply_global_seq(&self, mut entries: Vec<Entry>) -> Vec<Entry> {
+        if let Some(seq) = self.global_seq {
+            for entry in &mut entries {
+                entry.seq_num = seq;
+            }
+        }
+        entries
+    }
+
+    /// Delete the table's file once this reader is dropped.
+    pub(crate) fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::SeqCst);
+    }
+
+    /// Range tombstones stored in this table.
+    pub fn range_tombstones(&self) -> &FragmentedRangeTombstones {
+        &self.range_tombstones
     }
 
     fn read_metadata(buffer_pool: &BufferPool, file_id: u64) -> Result<SSTableMeta, std::io::Error> {
         let page_addr = PageAddr::new(file_id, 0);
         let page = Page::open(buffer_pool, page_addr)?;
-        
+        if page.page_type()? != PageType::Meta || page.num_cells()? == 0 {
+            return Err(Corruption::BadTableMagic { file_id }.into());
+        }
+
         let cell = page.read_cell(0)?;
-        let tuple = TupleOnDisk::new(cell);
-        
-        // The first field is VarBytes containing our metadata
-        // We need to parse the null bitmap first, then the varint length
-        let null_bitmap_len = 1; // 1 field = 1 byte
-        let mut offset = null_bitmap_len;
-        
-        // Read varint length
-        let (data_len, varint_size) = crate::tuple::varint::decode_varint(&tuple.data[offset..])?;
-        offset += varint_size;
-        
-        let meta_bytes = &tuple.data[offset..offset + data_len as usize];
-        let mut pos = 0;
-
-        let id = u64::from_le_bytes(meta_bytes[pos..pos + 8].try_into().unwrap());
-        pos += 8;
-        let entry_count = u64::from_le_bytes(meta_bytes[pos..pos + 8].try_into().unwrap());
-        pos += 8;
-        let start_page = u64::from_le_bytes(meta_bytes[pos..pos + 8].try_into().unwrap());
-        pos += 8;
-        let end_page = u64::from_le_bytes(meta_bytes[pos..pos + 8].try_into().unwrap());
-        pos += 8;
-        let min_seq = u64::from_le_bytes(meta_bytes[pos..pos + 8].try_into().unwrap());
-        pos += 8;
-        let max_seq = u64::from_le_bytes(meta_bytes[pos..pos + 8].try_into().unwrap());
-        pos += 8;
-
-        let min_key_len = u32::from_le_bytes(meta_bytes[pos..pos + 4].try_into().unwrap()) as usize;
-        pos += 4;
-        let min_key = Key::from_slice(&meta_bytes[pos..pos + min_key_len]);
-        pos += min_key_len;
-
-        let max_key_len = u32::from_le_bytes(meta_bytes[pos..pos + 4].try_into().unwrap()) as usize;
-        pos += 4;
-        let max_key = Key::from_slice(&meta_bytes[pos..pos + max_key_len]);
+        let meta_bytes = read_record(buffer_pool, &page, cell)?;
+        if meta_bytes.get(..SSTABLE_MAGIC.len()) != Some(&SSTABLE_MAGIC[..]) {
+            return Err(Corruption::BadTableMagic { file_id }.into());
+        }
+        let version_bytes = &meta_bytes[SSTABLE_MAGIC.len()..];
+        let version = match version_bytes.get(..4) {
+            Some(bytes) => u32::from_le_bytes(bytes.try_into().unwrap()),
+            None => return Err(Corruption::InvalidTableMeta { file_id }.into()),
+        };
+        if version != SSTABLE_FORMAT_VERSION {
+            return Err(Corruption::UnsupportedTableVersion { file_id, version }.into());
+        }
+
+        let invalid = |_| std::io::Error::from(Corruption::InvalidTableMeta { file_id });
+        let map = decode_property_map(&version_bytes[4..]).map_err(invalid)?;
+        let required = |name: &str| -> Result<u64, std::io::Error> {
+            get_u64(&map, name)
+                .map_err(invalid)?
+                .ok_or_else(|| Corruption::InvalidTableMeta { file_id }.into())
+        };
+        let required_key = |name: &str| -> Result<Key, std::io::Error> {
+            get_bytes(&map, name)
+                .map(Key::from_slice)
+                .ok_or_else(|| Corruption::InvalidTableMeta { file_id }.into())
+        };
 
         Ok(SSTableMeta {
-            id,
-            entry_count,
-            start_page,
-            end_page,
-            min_key,
-            max_key,
-            min_seq,
-            max_seq,
+            id: file_id,
+            entry_count: required("num_entries")?,
+            start_page: required("start_page")?,
+            end_page: required("end_page")?,
+            min_key: required_key("min_key")?,
+            max_key: required_key("max_key")?,
+            min_seq: required("min_seq")?,
+            max_seq: required("max_seq")?,
+            range_tombstone_page: get_u64(&map, "range_tombstone_page").map_err(invalid)?,
+            properties: TableProperties::decode_from(&map).map_err(invalid)?,
         })
     }
 
+    /// Statistics recorded when the table was written, including user-collected properties.
+    pub fn properties(&self) -> &TableProperties {
+        &self.meta.properties
+    }
+
     /// Check if a key might be in this SSTable (based on key range).
     pub fn might_contain(&self, key: &Key) -> bool {
-        key >= &self.meta.min_key && key <= &self.meta.max_key
+        compare_keys(self.comparator.as_ref(), key, &self.meta.min_key).is_ge()
+            && compare_keys(self.comparator.as_ref(), key, &self.meta.max_key).is_le()
     }
 
-    /// Get all entries for a key using binary search.
+    /// Check if the table might hold entries with sequence numbers in `seqs`
+    /// (based on its sequence number range).
+    pub fn might_contain_seqs(&self, seqs: &impl RangeBounds<SeqNum>) -> bool {
+        let after_start = match seqs.start_bound() {
+            Bound::Included(&start) => self.meta.max_seq >= start,
+            Bound::Excluded(&start) => self.meta.max_seq > start,
+            Bound::Unbounded => true,
+        };
+        let before_end = match seqs.end_bound() {
+            Bound::Included(&end) => self.meta.min_seq <= end,
+            Bound::Excluded(&end) => self.meta.min_seq < end,
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    /// Get all entries for a key using binary search over block headers.
     pub fn get(&self, key: &Key) -> Result<Vec<Entry>, std::io::Error> {
+        Ok(self.apply_global_seq(self.find(key)?))
+    }
+
+    fn find(&self, key: &Key) -> Result<Vec<Entry>, std::io::Error> {
         if !self.might_contain(key) {
             return Ok(vec![]);
         }
 
+        let comparator = self.comparator.as_ref();
         let mut results = Vec::new();
         
         for page_id in self.meta.start_page..=self.meta.end_page {
             let page_addr = PageAddr::new(self.meta.id, page_id);
             let page = Page::open(&self.buffer_pool, page_addr)?;
+            if page.page_type()? == PageType::Overflow {
+                continue;
+            }
             let num_cells = page.num_cells()?;
             
             if num_cells == 0 {
@@ -239,40 +483,30 @@ impl SSTableReader {
             }
 
             // Check if key is in range for this page
-            let first_entry = self.read_entry_from_page(&page, 0)?;
-            let last_entry = self.read_entry_from_page(&page, num_cells - 1)?;
+            let first_key = self.read_block_header(&page, 0)?.first_key;
+            let last_key = self.read_block_header(&page, num_cells - 1)?.last_key;
             
-            if key < &first_entry.key {
+            if compare_keys(comparator, key, &first_key).is_lt() {
                 // Key is before this page, and since pages are sorted, 
                 // it won't be in any subsequent page either
                 break;
             }
-            if key > &last_entry.key {
+            if compare_keys(comparator, key, &last_key).is_gt() {
                 // Key is after this page, check next page
                 continue;
             }
 
-            // Binary search to find first occurrence of key in this page
-            let first_idx = self.binary_search_first(&page, key, num_cells)?;
-            
-            if let Some(idx) = first_idx {
-                // Collect all entries with this key (they're consecutive)
-                for cell_idx in idx..num_cells {
-                    let entry = self.read_entry_from_page(&page, cell_idx)?;
-                    if &entry.key == key {
-                        results.push(entry);
-                    } else {
-                        // Keys are sorted, so we're done with this key
-                        break;
-                    }
+            // Entries for a key may span several blocks, starting at the first
+            // block whose last key is not below it
+            let first_block = self.binary_search_first_block(&page, key, num_cells)?;
+            for cell_idx in first_block..num_cells {
+                let header = self.read_block_header(&page, cell_idx)?;
+                if compare_keys(comparator, key, &header.first_key).is_lt() {
+                    return Ok(results);
                 }
-            }
-            
-            // If we found entries and the last one's key equals our key,
-            // there might be more in the next page
-            if let Some(last) = results.last() {
-                if &last.key != key {
-                    break; // No more entries for this key
+                results.extend(self.read_block(&page, cell_idx)?.get(key, comparator)?);
+                if compare_keys(comparator, key, &header.last_key).is_lt() {
+                    return Ok(results);
                 }
             }
         }
@@ -280,54 +514,112 @@ impl SSTableReader {
         Ok(results)
     }
 
-    /// Binary search to find the first occurrence of a key in a page.
-    /// Returns the index of the first entry with the given key, or None if not found.
-    fn binary_search_first(&self, page: &Page, key: &Key, num_cells: usize) -> Result<Option<usize>, std::io::Error> {
-        if num_cells == 0 {
-            return Ok(None);
+    /// Get all entries for each of `keys`, which must be sorted in the table's key order.
+    /// The table is walked once: every page is opened and every block decoded at most
+    /// once, however many keys it holds.
+    pub fn multi_get(&self, keys: &[&Key]) -> Result<Vec<Vec<Entry>>, std::io::Error> {
+        let comparator = self.comparator.as_ref();
+        let mut results: Vec<Vec<Entry>> = keys.iter().map(|_| Vec::new()).collect();
+        let mut next = keys.partition_point(|key| compare_keys(comparator, key, &self.meta.min_key).is_lt());
+        let end = keys.partition_point(|key| compare_keys(comparator, key, &self.meta.max_key).is_le());
+
+        for page_id in self.meta.start_page..=self.meta.end_page {
+            if next >= end {
+                break;
+            }
+            let page = Page::open(&self.buffer_pool, PageAddr::new(self.meta.id, page_id))?;
+            if page.page_type()? == PageType::Overflow {
+                continue;
+            }
+            let num_cells = page.num_cells()?;
+            if num_cells == 0 {
+                continue;
+            }
+            let page_last_key = self.read_block_header(&page, num_cells - 1)?.last_key;
+            if compare_keys(comparator, keys[next], &page_last_key).is_gt() {
+                continue;
+            }
+
+            let mut cell_idx = self.binary_search_first_block(&page, keys[next], num_cells)?;
+            while cell_idx < num_cells && next < end {
+                let header = self.read_block_header(&page, cell_idx)?;
+                while next < end && compare_keys(comparator, keys[next], &header.first_key).is_lt() {
+                    next += 1;
+                }
+                if next >= end {
+                    break;
+                }
+                if compare_keys(comparator, keys[next], &header.last_key).is_gt() {
+                    cell_idx = self.binary_search_first_block(&page, keys[next], num_cells)?.max(cell_idx + 1);
+                    continue;
+                }
+
+                let block = self.read_block(&page, cell_idx)?;
+                let mut after = next;
+                while after < end && compare_keys(comparator, keys[after], &header.last_key).is_le() {
+                    results[after].extend(block.get(keys[after], comparator)?);
+                    after += 1;
+                }
+                // Entries for the block's last key may continue in the next block
+                next = (next..after)
+                    .find(|&i| compare_keys(comparator, keys[i], &header.last_key).is_eq())
+                    .unwrap_or(after);
+                cell_idx += 1;
+            }
         }
 
+        Ok(results.into_iter().map(|entries| self.apply_global_seq(entries)).collect())
+    }
+
+    /// Binary search for the first block in a page whose last key is >= `key`.
+    fn binary_search_first_block(&self, page: &Page, key: &Key, num_cells: usize) -> Result<usize, std::io::Error> {
         let mut left = 0;
         let mut right = num_cells;
-        let mut result = None;
 
         while left < right {
             let mid = left + (right - left) / 2;
-            let entry = self.read_entry_from_page(page, mid)?;
-
-            match entry.key.cmp(key) {
-                std::cmp::Ordering::Less => {
-                    left = mid + 1;
-                }
-                std::cmp::Ordering::Equal => {
-                    result = Some(mid);
-                    right = mid; // Continue searching left for first occurrence
-                }
-                std::cmp::Ordering::Greater => {
-                    right = mid;
-                }
+            if compare_keys(self.comparator.as_ref(), &self.read_block_header(page, mid)?.last_key, key).is_lt() {
+                left = mid + 1;
+            } else {
+                right = mid;
             }
         }
 
-        Ok(result)
+        Ok(left)
     }
 
     /// Iterate over all entries.
     pub fn iter(&self) -> SSTableIterator {
-        SSTableIterator::new(self.buffer_pool.clone(), self.meta.clone())
+        SSTableIterator::new(self.buffer_pool.clone(), self.meta.clone(), self.global_seq)
     }
 
-    fn read_entry_from_page(&self, page: &Page, cell_idx: usize) -> Result<Entry, std::io::Error> {
+    fn read_block(&self, page: &Page, cell_idx: usize) -> Result<Block, std::io::Error> {
         let cell = page.read_cell(cell_idx)?;
-        let tuple = TupleOnDisk::new(cell);
-        
-        // Parse: null_bitmap (1 byte) + varint length + data
-        let null_bitmap_len = 1;
-        let (data_len, varint_size) = crate::tuple::varint::decode_varint(&tuple.data[null_bitmap_len..])?;
-        let entry_bytes = &tuple.data[null_bitmap_len + varint_size..null_bitmap_len + varint_size + data_len as usize];
-        
-        let (entry, _) = Entry::read_from(entry_bytes)?;
-        Ok(entry)
+        let record = read_record(&self.buffer_pool, page, cell)?;
+        Block::decode(&record)
+    }
+
+    /// Read only the header of a block, avoiding the overflow chain when the header
+    /// fits in the inline part of the record.
+    fn read_block_header(&self, page: &Page, cell_idx: usize) -> Result<BlockHeader, std::io::Error> {
+        let cell = page.read_cell(cell_idx)?;
+        if let Some((header, _)) = BlockHeader::peek(record_prefix(page, cell)?) {
+            return Ok(header);
+        }
+        let record = read_record(&self.buffer_pool, page, cell)?;
+        BlockHeader::peek(&record)
+            .map(|(header, _)| header)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid block: truncated header"))
+    }
+}
+
+impl Drop for SSTableReader {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::SeqCst)
+            && let Err(e) = self.buffer_pool.remove_file(self.meta.id)
+        {
+            eprintln!("Warning: Failed to delete obsolete SSTable {}: {}", self.meta.id, e);
+        }
     }
 }
 
@@ -338,34 +630,62 @@ pub struct SSTableIterator {
     current_page: u64,
     current_cell: usize,
     cells_in_page: usize,
+    /// Remaining entries of the block being read.
+    block: std::vec::IntoIter<Entry>,
+    global_seq: Option<SeqNum>,
     initialized: bool,
     finished: bool,
 }
 
 impl SSTableIterator {
-    fn new(buffer_pool: Arc<BufferPool>, meta: SSTableMeta) -> Self {
+    fn new(buffer_pool: Arc<BufferPool>, meta: SSTableMeta, global_seq: Option<SeqNum>) -> Self {
         Self {
             buffer_pool,
             current_page: meta.start_page,
             meta,
             current_cell: 0,
             cells_in_page: 0,
+            block: Vec::new().into_iter(),
+            global_seq,
             initialized: false,
             finished: false,
         }
     }
 
     fn load_current_page(&mut self) -> Result<bool, std::io::Error> {
-        if self.current_page > self.meta.end_page {
-            self.finished = true;
-            return Ok(false);
+        loop {
+            if self.current_page > self.meta.end_page {
+                self.finished = true;
+                return Ok(false);
+            }
+
+            let page_addr = PageAddr::new(self.meta.id, self.current_page);
+            let page = Page::open(&self.buffer_pool, page_addr)?;
+            // Overflow pages are read through the block that owns them
+            if page.page_type()? == PageType::Overflow {
+                self.current_page += 1;
+                continue;
+            }
+            self.cells_in_page = page.num_cells()?;
+            self.current_cell = 0;
+            return Ok(true);
         }
+    }
 
+    fn load_next_block(&mut self) -> Result<(), std::io::Error> {
         let page_addr = PageAddr::new(self.meta.id, self.current_page);
         let page = Page::open(&self.buffer_pool, page_addr)?;
-        self.cells_in_page = page.num_cells()?;
-        self.current_cell = 0;
-        Ok(true)
+        let cell = page.read_cell(self.current_cell)?;
+        self.current_cell += 1;
+        let record = read_record(&self.buffer_pool, &page, cell)?;
+        let mut entries = decode_block(&record)?;
+        if let Some(seq) = self.global_seq {
+            for entry in &mut entries {
+                entry.seq_num = seq;
+            }
+        }
+        self.block = entries.into_iter();
+        Ok(())
     }
 }
 
@@ -388,36 +708,17 @@ impl Iterator for SSTableIterator {
         }
 
         loop {
-            // Try to read from current page
+            if let Some(entry) = self.block.next() {
+                return Some(Ok(entry));
+            }
+
+            // Decode the next block in the current page
             if self.current_cell < self.cells_in_page {
-                let page_addr = PageAddr::new(self.meta.id, self.current_page);
-                match Page::open(&self.buffer_pool, page_addr) {
-                    Ok(page) => {
-                        let cell_idx = self.current_cell;
-                        self.current_cell += 1;
-                        
-                        let cell = match page.read_cell(cell_idx) {
-                            Ok(c) => c,
-                            Err(e) => return Some(Err(e)),
-                        };
-                        
-                        let tuple = TupleOnDisk::new(cell);
-                        let null_bitmap_len = 1;
-                        
-                        let (data_len, varint_size) = match crate::tuple::varint::decode_varint(&tuple.data[null_bitmap_len..]) {
-                            Ok(v) => v,
-                            Err(e) => return Some(Err(e)),
-                        };
-                        
-                        let entry_bytes = &tuple.data[null_bitmap_len + varint_size..null_bitmap_len + varint_size + data_len as usize];
-                        
-                        match Entry::read_from(entry_bytes) {
-                            Ok((entry, _)) => return Some(Ok(entry)),
-                            Err(e) => return Some(Err(e)),
-                        }
-                    }
-                    Err(e) => return Some(Err(e)),
+                if let Err(e) = self.load_next_block() {
+                    self.finished = true;
+                    return Some(Err(e));
                 }
+                continue;
             }
 
             // Move to next page
@@ -456,7 +757,7 @@ mod tests {
         ];
 
         {
-            let mut writer = SSTableWriter::new(&pool, file_id).unwrap();
+            let mut writer = SSTableWriter::new(pool.clone(), file_id).unwrap();
             for entry in &entries {
                 writer.write_entry(entry).unwrap();
             }
@@ -496,7 +797,7 @@ mod tests {
         ];
 
         {
-            let mut writer = SSTableWriter::new(&pool, file_id).unwrap();
+            let mut writer = SSTableWriter::new(pool.clone(), file_id).unwrap();
             for entry in &entries {
                 writer.write_entry(entry).unwrap();
             }
@@ -512,4 +813,209 @@ mod tests {
 
         let _ = std::fs::remove_dir_all(dir);
     }
+
+    #[test]
+    fn test_sstable_large_values() {
+        let dir = get_temp_dir();
+        let pool = Arc::new(BufferPool::new(dir.clone()).unwrap());
+        let file_id = 1;
+
+        let large = |seed: u8, len: usize| -> Value { Value::new((0..len).map(|i| seed ^ (i % 251) as u8).collect()) };
+        let entries = vec![
+            Entry::put(Key::from("a"), 1, Value::from("small")),
+            Entry::put(Key::from("b"), 2, large(1, 10 * 1024)),
+            Entry::put(Key::from("c"), 3, large(2, 3 * 1024 * 1024)),
+            Entry::put(Key::from("c"), 4, large(3, 5000)),
+            Entry::put(Key::from("d"), 5, Value::from("tail")),
+        ];
+        let mut sorted = entries.clone();
+        sorted.sort();
+
+        {
+            let mut writer = SSTableWriter::new(pool.clone(), file_id).unwrap();
+            for entry in &sorted {
+                writer.write_entry(entry).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        pool.flush().unwrap();
+
+        {
+            let reader = SSTableReader::open(pool.clone(), file_id).unwrap();
+
+            let results = reader.get(&Key::from("b")).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].value, entries[1].value);
+
+            let results = reader.get(&Key::from("c")).unwrap();
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].value, entries[3].value);
+            assert_eq!(results[1].value, entries[2].value);
+
+            assert_eq!(reader.get(&Key::from("d")).unwrap()[0].value, entries[4].value);
+
+            let all: Vec<Entry> = reader.iter().map(|r| r.unwrap()).collect();
+            assert_eq!(all.len(), sorted.len());
+            for (read, written) in all.iter().zip(&sorted) {
+                assert_eq!(read.key, written.key);
+                assert_eq!(read.value, written.value);
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_sstable_key_spanning_blocks() {
+        let dir = get_temp_dir();
+        let pool = Arc::new(BufferPool::new(dir.clone()).unwrap());
+        let file_id = 1;
+        let options = SSTableOptions {
+            block_size: 256,
+            restart_interval: 4,
+            compression: CompressionType::Lz4,
+        };
+
+        let mut entries = vec![Entry::put(Key::from("a"), 1, Value::from("first"))];
+        for seq in 2..200 {
+            entries.push(Entry::put(Key::from("dup"), seq, Value::from(format!("version {}", seq).as_str())));
+        }
+        entries.push(Entry::put(Key::from("z"), 500, Value::from("last")));
+        entries.sort();
+
+        {
+            let mut writer = SSTableWriter::with_options(pool.clone(), file_id, options).unwrap();
+            for entry in &entries {
+                writer.write_entry(entry).unwrap();
+            }
+            let meta = writer.finish().unwrap();
+            assert!(meta.properties.data_size < meta.properties.uncompressed_data_size);
+        }
+
+        {
+            let reader = SSTableReader::open(pool.clone(), file_id).unwrap();
+            let results = reader.get(&Key::from("dup")).unwrap();
+            assert_eq!(results.len(), 198);
+            assert_eq!(results[0].seq_num, 199);
+            assert_eq!(reader.get(&Key::from("z")).unwrap().len(), 1);
+            assert!(reader.get(&Key::from("m")).unwrap().is_empty());
+            assert_eq!(reader.iter().count(), entries.len());
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_sstable_multi_get() {
+        let dir = get_temp_dir();
+        let pool = Arc::new(BufferPool::new(dir.clone()).unwrap());
+        let file_id = 1;
+        let options = SSTableOptions {
+            block_size: 256,
+            restart_interval: 4,
+            compression: CompressionType::None,
+        };
+
+        let mut entries = Vec::new();
+        for i in 0..500u64 {
+            let key = Key::from(format!("key{:04}", i * 2).as_str());
+            entries.push(Entry::put(key.clone(), i + 1, Value::from(format!("value {}", i).as_str())));
+            if i % 50 == 0 {
+                for seq in 0..40 {
+                    entries.push(Entry::put(key.clone(), 1000 + i * 40 + seq, Value::from("dup")));
+                }
+            }
+        }
+        entries.sort();
+        {
+            let mut writer = SSTableWriter::with_options(pool.clone(), file_id, options).unwrap();
+            for entry in &entries {
+                writer.write_entry(entry).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let reader = SSTableReader::open(pool.clone(), file_id).unwrap();
+        let mut keys: Vec<Key> = (0..1100).step_by(7).map(|i| Key::from(format!("key{:04}", i).as_str())).collect();
+        keys.extend([Key::from("a"), Key::from("key0100"), Key::from("key0100"), Key::from("zzz")]);
+        keys.sort();
+        let key_refs: Vec<&Key> = keys.iter().collect();
+        let results = reader.multi_get(&key_refs).unwrap();
+        assert_eq!(results.len(), keys.len());
+        for (key, entries) in keys.iter().zip(&results) {
+            assert_eq!(entries, &reader.get(key).unwrap());
+        }
+        assert_eq!(results.iter().filter(|entries| entries.len() == 41).count(), 4);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_sstable_properties() {
+        let dir = get_temp_dir();
+        let pool = Arc::new(BufferPool::new(dir.clone()).unwrap());
+        let file_id = 1;
+
+        {
+            let mut writer = SSTableWriter::new(pool.clone(), file_id).unwrap();
+            writer.write_entry(&Entry::put(Key::from("a"), 1, Value::from("apple"))).unwrap();
+            writer.write_entry(&Entry::delete(Key::from("b"), 2)).unwrap();
+            writer.write_entry(&Entry::put(Key::from("c"), 3, Value::from("cherry"))).unwrap();
+            writer.finish().unwrap();
+        }
+        pool.flush().unwrap();
+
+        let reader = SSTableReader::open(pool.clone(), file_id).unwrap();
+        let properties = &reader.meta.properties;
+        assert_eq!(reader.meta.entry_count, 3);
+        assert_eq!(reader.meta.max_key, Key::from("c"));
+        assert_eq!(properties.num_deletions, 1);
+        assert_eq!(properties.num_data_blocks, 1);
+        assert_eq!(properties.raw_key_size, 3);
+        assert_eq!(properties.raw_value_size, 11);
+        assert_eq!(properties.compression, CompressionType::None);
+        assert!(properties.creation_time > 0);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_sstable_rejects_bad_meta_block() {
+        let dir = get_temp_dir();
+        let pool = Arc::new(BufferPool::new(dir.clone()).unwrap());
+
+        // Not an SSTable: page 0 is a data page
+        {
+            let mut page = PageMut::open(&pool, PageAddr::new(1, 0)).unwrap();
+            write_record(&pool, &mut page, b"hello", PageAddr::new(1, 1)).unwrap();
+        }
+        let err = SSTableReader::open(pool.clone(), 1).err().unwrap();
+        assert_eq!(Corruption::from_io_error(&err), Some(&Corruption::BadTableMagic { file_id: 1 }));
+
+        // Meta block from a newer format version
+        {
+            let mut meta_bytes = SSTABLE_MAGIC.to_vec();
+            meta_bytes.extend_from_slice(&99u32.to_le_bytes());
+            let mut page = PageMut::open_with_type(&pool, PageAddr::new(2, 0), PageType::Meta).unwrap();
+            write_record(&pool, &mut page, &meta_bytes, PageAddr::new(2, 1)).unwrap();
+        }
+        let err = SSTableReader::open(pool.clone(), 2).err().unwrap();
+        assert_eq!(
+            Corruption::from_io_error(&err),
+            Some(&Corruption::UnsupportedTableVersion { file_id: 2, version: 99 })
+        );
+
+        // Truncated property map
+        {
+            let mut meta_bytes = SSTABLE_MAGIC.to_vec();
+            meta_bytes.extend_from_slice(&SSTABLE_FORMAT_VERSION.to_le_bytes());
+            meta_bytes.push(5);
+            let mut page = PageMut::open_with_type(&pool, PageAddr::new(3, 0), PageType::Meta).unwrap();
+            write_record(&pool, &mut page, &meta_bytes, PageAddr::new(3, 1)).unwrap();
+        }
+        let err = SSTableReader::open(pool.clone(), 3).err().unwrap();
+        assert_eq!(Corruption::from_io_error(&err), Some(&Corruption::InvalidTableMeta { file_id: 3 }));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
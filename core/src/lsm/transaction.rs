@@ -0,0 +1,343 @@
+//! Optimistic transactions - conflict detection with sequence numbers.
+//!
+//! A transaction reads through a snapshot taken when it begins and buffers its
+//! writes in a `WriteBatch`, which its own reads see. Every key it reads or
+//! writes is tracked, along with the sequence number it read. `commit` fails
+//! with `TransactionError::Conflict` if a tracked key was written after the
+//! snapshot, and applies the batch otherwise. Validation and the write happen
+//! under the memtable lock, so no other write can slip in between. Keys read by
+//! `scan_range` are tracked, but keys inserted into a scanned range are not.
+//!
+//! A serializable transaction (see `ssi`) tracks the ranges it scans as well.
+//! Its commit fails only on a conflicting write to a key it writes, or when a
+//! read-write dependency cycle with concurrent serializable transactions would
+//! form, so a key it read being overwritten alone does not abort it.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::RangeBounds;
+
+use super::batch::WriteBatch;
+use super::comparator::{compare_keys, range_contains};
+use super::lsm::LsmTree;
+use super::merge::MergeFold;
+use super::snapshot::Snapshot;
+use super::ssi::SsiTxnId;
+use super::types::{Entry, Key, SeqNum, Value};
+
+/// Why a transaction operation failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionError {
+    /// `key` was written at `written_seq`, after the transaction's snapshot.
+    /// `read_seq` is the version the transaction read, if it read one.
+    Conflict {
+        key: Key,
+        read_seq: Option<SeqNum>,
+        written_seq: SeqNum,
+    },
+    /// Another transaction held a lock on `key` for longer than the lock timeout.
+    LockTimeout { key: Key },
+    /// Waiting for the lock on `key` would have deadlocked.
+    Deadlock { key: Key },
+    /// Committing would make the history unserializable: a concurrent transaction
+    /// read `key` before this one overwrote it, or the other way around.
+    Unserializable { key: Key },
+}
+
+impl TransactionError {
+    /// Returns the transaction error wrapped in `err`, if any.
+    pub fn from_io_error(err: &std::io::Error) -> Option<&TransactionError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<TransactionError>())
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Conflict { key, written_seq, .. } => {
+                write!(f, "conflict on key {:?}: written at seq {} after the snapshot", key, written_seq)
+            }
+            TransactionError::LockTimeout { key } => write!(f, "timed out waiting for the lock on key {:?}", key),
+            TransactionError::Deadlock { key } => write!(f, "waiting for the lock on key {:?} would deadlock", key),
+            TransactionError::Unserializable { key } => {
+                write!(f, "read-write dependency on key {:?} would make the commit unserializable", key)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl From<TransactionError> for std::io::Error {
+    fn from(err: TransactionError) -> Self {
+        let kind = match err {
+            TransactionError::Conflict { .. } | TransactionError::Unserializable { .. } => {
+                std::io::ErrorKind::ResourceBusy
+            }
+            TransactionError::LockTimeout { .. } => std::io::ErrorKind::TimedOut,
+            TransactionError::Deadlock { .. } => std::io::ErrorKind::Deadlock,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+/// A transaction validated at commit time. Dropping it without committing
+/// discards its writes.
+pub struct OptimisticTransaction<'a> {
+    db: &'a LsmTree,
+    snapshot: Snapshot,
+    batch: WriteBatch,
+    /// Keys read or written -> sequence number of the version read, if any.
+    tracked: BTreeMap<Key, Option<SeqNum>>,
+    /// Registration with the tree's `SsiTracker`, for serializable transactions.
+    ssi: Option<SsiTxnId>,
+}
+
+impl<'a> OptimisticTransaction<'a> {
+    pub(crate) fn new(db: &'a LsmTree, snapshot: Snapshot, serializable: bool) -> Self {
+        let ssi = serializable.then(|| db.ssi().begin(snapshot.seq_num()));
+        Self {
+            db,
+            snapshot,
+            batch: WriteBatch::new(),
+            tracked: BTreeMap::new(),
+            ssi,
+        }
+    }
+
+    /// The snapshot the transaction reads through.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// Get the value of a key, including the transaction's own writes.
+    pub fn get(&mut self, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        let mut fold = MergeFold::new(self.db.merge_operator());
+        if self.batch.fold_writes(key, &mut fold, self.db.comparator().as_ref()) {
+            return fold.finish(key);
+        }
+
+        let (value, seq) = self.db.get_at_with_seq(key, &self.snapshot)?;
+        self.track_read(key, seq);
+        match value {
+            Some(value) => {
+                fold.add(Entry::put(key.clone(), 0, value));
+            }
+            None => fold.add_deleted(),
+        }
+        fold.finish(key)
+    }
+
+    /// Get the live key-value pairs with a key in `range`, including the
+    /// transaction's own writes, in key order.
+    pub fn scan_range(&mut self, range: impl RangeBounds<Key>) -> Result<Vec<(Key, Value)>, std::io::Error> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        let stored = self.db.scan_range_at(range.clone(), &self.snapshot)?;
+        match self.ssi {
+            Some(id) => self.db.ssi().record_range(id, range.clone()),
+            None => {
+                for entry in &stored {
+                    self.track_read(&entry.key, Some(entry.seq_num));
+                }
+            }
+        }
+
+        let comparator = self.db.comparator().as_ref();
+        let stored: HashMap<Key, Value> = stored.into_iter().filter_map(|e| Some((e.key, e.value?))).collect();
+        let mut keys: Vec<&Key> = stored.keys().collect();
+        keys.extend(self.batch.written_keys().filter(|key| range_contains(comparator, &range, key)));
+        keys.sort_by(|a, b| compare_keys(comparator, a, b));
+        keys.dedup();
+        let mut pairs = Vec::with_capacity(keys.len());
+        for key in keys {
+            let mut fold = MergeFold::new(self.db.merge_operator());
+            if !self.batch.fold_writes(key, &mut fold, comparator) {
+                match stored.get(key) {
+                    Some(value) => {
+                        fold.add(Entry::put(key.clone(), 0, value.clone()));
+                    }
+                    None => fold.add_deleted(),
+                }
+            }
+            if let Some(value) = fold.finish(key)? {
+                pairs.push((key.clone(), value));
+            }
+        }
+        Ok(pairs)
+    }
+
+    fn track_read(&mut self, key: &Key, seq: Option<SeqNum>) {
+        let read_seq = self.tracked.entry(key.clone()).or_default();
+        *read_seq = read_seq.or(seq);
+        if let Some(id) = self.ssi {
+            self.db.ssi().record_read(id, key);
+        }
+    }
+
+    /// Put a key-value pair.
+    pub fn put(&mut self, key: Key, value: Value) {
+        self.tracked.entry(key.clone()).or_default();
+        self.batch.put(key, value);
+    }
+
+    /// Add a merge operand for a key.
+    pub fn merge(&mut self, key: Key, operand: Value) {
+        self.tracked.entry(key.clone()).or_default();
+        self.batch.merge(key, operand);
+    }
+
+    /// Delete a key.
+    pub fn delete(&mut self, key: Key) {
+        self.tracked.entry(key.clone()).or_default();
+        self.batch.delete(key);
+    }
+
+    /// Apply the transaction's writes atomically, unless a key it read or wrote
+    /// was written since its snapshot (for a serializable transaction: a key it
+    /// wrote, or a dependency cycle would form). Returns the sequence number of
+    /// the last write.
+    pub fn commit(mut self) -> Result<SeqNum, std::io::Error> {
+        let db = self.db;
+        let snapshot_seq = self.snapshot.seq_num();
+        let ssi = self.ssi;
+        let tracked = std::mem::take(&mut self.tracked);
+        let batch = std::mem::take(&mut self.batch);
+        let written: HashSet<Key> = batch.written_keys().cloned().collect();
+        db.write_checked(batch, |latest_seq, commit_seq| {
+            for (key, &read_seq) in &tracked {
+                if ssi.is_some() && !written.contains(key) {
+                    continue;
+                }
+                if let Some(written_seq) = latest_seq(key)?
+                    && written_seq > snapshot_seq
+                {
+                    return Err(TransactionError::Conflict {
+                        key: key.clone(),
+                        read_seq,
+                        written_seq,
+                    }
+                    .into());
+                }
+            }
+            match ssi {
+                Some(id) => db.ssi().commit(id, written, commit_seq, db.oldest_snapshot_seq()),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Discard the transaction's writes.
+    pub fn rollback(self) {}
+}
+
+impl Drop for OptimisticTransaction<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.ssi {
+            self.db.ssi().end(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::LsmConfig;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn get_temp_dir() -> PathBuf {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        PathBuf::from(format!("/tmp/thordb_txn_test_{}", since_epoch.as_nanos()))
+    }
+
+    #[test]
+    fn test_optimistic_transaction() {
+        let dir = get_temp_dir();
+        let db = LsmTree::open(LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.put(Key::from("a"), Value::from("1")).unwrap();
+        db.put(Key::from("b"), Value::from("1")).unwrap();
+
+        // Reads see the transaction's own writes, others only after commit
+        let mut txn = db.begin_optimistic_transaction();
+        assert_eq!(txn.get(&Key::from("a")).unwrap(), Some(Value::from("1")));
+        txn.put(Key::from("a"), Value::from("2"));
+        txn.delete(Key::from("b"));
+        assert_eq!(txn.get(&Key::from("a")).unwrap(), Some(Value::from("2")));
+        assert_eq!(txn.get(&Key::from("b")).unwrap(), None);
+        assert_eq!(db.get(&Key::from("a")).unwrap(), Some(Value::from("1")));
+        txn.commit().unwrap();
+        assert_eq!(db.get(&Key::from("a")).unwrap(), Some(Value::from("2")));
+        assert_eq!(db.get(&Key::from("b")).unwrap(), None);
+
+        // A key read by the transaction and written since its snapshot conflicts
+        let mut txn = db.begin_optimistic_transaction();
+        let read = txn.get(&Key::from("a")).unwrap();
+        txn.put(Key::from("c"), read.unwrap());
+        let written_seq = db.put(Key::from("a"), Value::from("3")).unwrap();
+        let err = txn.commit().unwrap_err();
+        match TransactionError::from_io_error(&err) {
+            Some(TransactionError::Conflict { key, written_seq: seq, .. }) => {
+                assert_eq!((key, *seq), (&Key::from("a"), written_seq));
+            }
+            other => panic!("Expected a conflict, got {:?}", other),
+        }
+        assert_eq!(db.get(&Key::from("c")).unwrap(), None);
+
+        // Writes to keys the transaction did not touch do not conflict
+        let mut txn = db.begin_optimistic_transaction();
+        txn.put(Key::from("d"), Value::from("1"));
+        db.put(Key::from("e"), Value::from("1")).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(db.get(&Key::from("d")).unwrap(), Some(Value::from("1")));
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_serializable_transactions() {
+        let dir = get_temp_dir();
+        let db = LsmTree::open(LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.put(Key::from("oncall/alice"), Value::from("1")).unwrap();
+        db.put(Key::from("oncall/bob"), Value::from("1")).unwrap();
+        let oncall = Key::from("oncall/")..Key::from("oncall0");
+
+        // Write skew: each sees two doctors on call and takes one off
+        let mut t1 = db.begin_serializable_transaction();
+        let mut t2 = db.begin_serializable_transaction();
+        assert_eq!(t1.scan_range(oncall.clone()).unwrap().len(), 2);
+        assert_eq!(t2.scan_range(oncall.clone()).unwrap().len(), 2);
+        t1.delete(Key::from("oncall/alice"));
+        t2.delete(Key::from("oncall/bob"));
+        assert_eq!(t1.scan_range(oncall.clone()).unwrap().len(), 1);
+        t1.commit().unwrap();
+        let err = t2.commit().unwrap_err();
+        assert!(matches!(TransactionError::from_io_error(&err), Some(TransactionError::Unserializable { .. })));
+        assert_eq!(db.get(&Key::from("oncall/bob")).unwrap(), Some(Value::from("1")));
+
+        // A key read and overwritten since is no conflict without a cycle
+        let mut t3 = db.begin_serializable_transaction();
+        t3.get(&Key::from("oncall/bob")).unwrap();
+        t3.put(Key::from("audit"), Value::from("checked"));
+        db.put(Key::from("oncall/bob"), Value::from("2")).unwrap();
+        t3.commit().unwrap();
+
+        // Concurrent writes to the same key still conflict
+        let mut t4 = db.begin_serializable_transaction();
+        t4.put(Key::from("audit"), Value::from("t4"));
+        db.put(Key::from("audit"), Value::from("other")).unwrap();
+        let err = t4.commit().unwrap_err();
+        assert!(matches!(TransactionError::from_io_error(&err), Some(TransactionError::Conflict { .. })));
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
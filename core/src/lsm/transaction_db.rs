@@ -0,0 +1,248 @@
+//! Pessimistic transactions - two-phase locking over an `LsmTree`.
+//!
+//! A `TransactionDB` transaction locks every key it writes exclusive, and keys
+//! read with `get_for_update` as well, before touching them. Locks are held
+//! until the transaction commits or rolls back, so its writes can never
+//! conflict: commit applies them as one `WriteBatch`. Writes made directly
+//! through the `TransactionDB` lock their key for the duration of the write.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::batch::WriteBatch;
+use super::lock_manager::{LockManager, LockMode, TxnId};
+use super::lsm::{LsmConfig, LsmTree};
+use super::merge::MergeFold;
+use super::types::{Entry, Key, SeqNum, Value};
+
+/// Options for a `TransactionDB`.
+#[derive(Clone, Debug)]
+pub struct TransactionDbOptions {
+    /// How long to wait for a lock held by another transaction.
+    pub lock_timeout: Duration,
+    /// Number of lock stripes; keys in different stripes never contend.
+    pub num_stripes: usize,
+}
+
+impl Default for TransactionDbOptions {
+    fn default() -> Self {
+        Self {
+            lock_timeout: Duration::from_secs(1),
+            num_stripes: 16,
+        }
+    }
+}
+
+/// An `LsmTree` whose transactions take row locks.
+pub struct TransactionDB {
+    db: LsmTree,
+    options: TransactionDbOptions,
+    locks: LockManager,
+    next_txn_id: AtomicU64,
+}
+
+impl TransactionDB {
+    pub fn open(config: LsmConfig, options: TransactionDbOptions) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            db: LsmTree::open(config)?,
+            locks: LockManager::new(options.num_stripes),
+            options,
+            next_txn_id: AtomicU64::new(1),
+        })
+    }
+
+    /// The underlying tree. Writes made through it bypass the locks.
+    pub fn db(&self) -> &LsmTree {
+        &self.db
+    }
+
+    pub fn begin_transaction(&self) -> Transaction<'_> {
+        Transaction {
+            txn_db: self,
+            id: self.next_txn_id.fetch_add(1, Ordering::Relaxed),
+            batch: WriteBatch::new(),
+            locked: HashMap::new(),
+        }
+    }
+
+    /// Get the latest committed value of a key.
+    pub fn get(&self, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        self.db.get(key)
+    }
+
+    /// Put a key-value pair, once no transaction holds the key.
+    pub fn put(&self, key: Key, value: Value) -> Result<SeqNum, std::io::Error> {
+        self.with_lock(&key.clone(), || self.db.put(key, value))
+    }
+
+    /// Delete a key, once no transaction holds it.
+    pub fn delete(&self, key: Key) -> Result<SeqNum, std::io::Error> {
+        self.with_lock(&key.clone(), || self.db.delete(key))
+    }
+
+    fn with_lock<T>(&self, key: &Key, f: impl FnOnce() -> Result<T, std::io::Error>) -> Result<T, std::io::Error> {
+        let txn = self.next_txn_id.fetch_add(1, Ordering::Relaxed);
+        self.locks.lock(txn, key, LockMode::Exclusive, self.options.lock_timeout)?;
+        let result = f();
+        self.locks.unlock(txn, key);
+        result
+    }
+}
+
+/// A two-phase locking transaction. Dropping it without committing discards
+/// its writes and releases its locks.
+pub struct Transaction<'a> {
+    txn_db: &'a TransactionDB,
+    id: TxnId,
+    batch: WriteBatch,
+    /// Keys locked by the transaction, and how.
+    locked: HashMap<Key, LockMode>,
+}
+
+impl Transaction<'_> {
+    fn lock(&mut self, key: &Key, mode: LockMode) -> Result<(), std::io::Error> {
+        if self.locked.get(key).is_some_and(|&held| held == LockMode::Exclusive || held == mode) {
+            return Ok(());
+        }
+        let txn_db = self.txn_db;
+        txn_db.locks.lock(self.id, key, mode, txn_db.options.lock_timeout)?;
+        self.locked.insert(key.clone(), mode);
+        Ok(())
+    }
+
+    /// Get the latest committed value of a key, including the transaction's own
+    /// writes, without locking it.
+    pub fn get(&self, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        let mut fold = MergeFold::new(self.txn_db.db.merge_operator());
+        if !self.batch.fold_writes(key, &mut fold, self.txn_db.db.comparator().as_ref()) {
+            match self.txn_db.db.get(key)? {
+                Some(value) => {
+                    fold.add(Entry::put(key.clone(), 0, value));
+                }
+                None => fold.add_deleted(),
+            }
+        }
+        fold.finish(key)
+    }
+
+    /// Lock a key exclusive, then get its value. No other transaction can change
+    /// the key until this one ends.
+    pub fn get_for_update(&mut self, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        self.lock(key, LockMode::Exclusive)?;
+        self.get(key)
+    }
+
+    /// Lock a key shared, then get its value. Other transactions can read the
+    /// key the same way, but not change it until this one ends.
+    pub fn get_for_share(&mut self, key: &Key) -> Result<Option<Value>, std::io::Error> {
+        self.lock(key, LockMode::Shared)?;
+        self.get(key)
+    }
+
+    /// Lock a key exclusive and put a key-value pair.
+    pub fn put(&mut self, key: Key, value: Value) -> Result<(), std::io::Error> {
+        self.lock(&key, LockMode::Exclusive)?;
+        self.batch.put(key, value);
+        Ok(())
+    }
+
+    /// Lock a key exclusive and add a merge operand for it.
+    pub fn merge(&mut self, key: Key, operand: Value) -> Result<(), std::io::Error> {
+        self.lock(&key, LockMode::Exclusive)?;
+        self.batch.merge(key, operand);
+        Ok(())
+    }
+
+    /// Lock a key exclusive and delete it.
+    pub fn delete(&mut self, key: Key) -> Result<(), std::io::Error> {
+        self.lock(&key, LockMode::Exclusive)?;
+        self.batch.delete(key);
+        Ok(())
+    }
+
+    /// Apply the transaction's writes atomically and release its locks.
+    /// Returns the sequence number of the last write.
+    pub fn commit(mut self) -> Result<SeqNum, std::io::Error> {
+        self.txn_db.db.write(std::mem::take(&mut self.batch))
+    }
+
+    /// Discard the transaction's writes and release its locks.
+    pub fn rollback(self) {}
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        for key in self.locked.keys() {
+            self.txn_db.locks.unlock(self.id, key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsm::TransactionError;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn get_temp_dir() -> PathBuf {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        PathBuf::from(format!("/tmp/thordb_txn_db_test_{}", since_epoch.as_nanos()))
+    }
+
+    #[test]
+    fn test_pessimistic_transactions() {
+        let dir = get_temp_dir();
+        let config = LsmConfig {
+            data_dir: dir.clone(),
+            ..Default::default()
+        };
+        let options = TransactionDbOptions {
+            lock_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let txn_db = Arc::new(TransactionDB::open(config, options).unwrap());
+        let counter = Key::from("counter");
+        txn_db.put(counter.clone(), Value::from("0")).unwrap();
+
+        // Increments under get_for_update never lose an update
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let txn_db = txn_db.clone();
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        loop {
+                            let mut txn = txn_db.begin_transaction();
+                            let Ok(value) = txn.get_for_update(&counter) else { continue };
+                            let n: u64 = String::from_utf8_lossy(value.unwrap().as_bytes()).parse().unwrap();
+                            txn.put(counter.clone(), Value::from((n + 1).to_string().as_str())).unwrap();
+                            txn.commit().unwrap();
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(txn_db.get(&counter).unwrap(), Some(Value::from("40")));
+
+        // A held lock times out other writers, and is released on rollback
+        let mut txn = txn_db.begin_transaction();
+        txn.put(counter.clone(), Value::from("mine")).unwrap();
+        assert_eq!(txn.get(&counter).unwrap(), Some(Value::from("mine")));
+        let err = txn_db.put(counter.clone(), Value::from("other")).unwrap_err();
+        assert!(matches!(TransactionError::from_io_error(&err), Some(TransactionError::LockTimeout { .. })));
+        txn.rollback();
+        txn_db.put(counter.clone(), Value::from("other")).unwrap();
+        assert_eq!(txn_db.get(&counter).unwrap(), Some(Value::from("other")));
+
+        drop(txn_db);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
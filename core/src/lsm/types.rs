@@ -5,6 +5,8 @@ use std::io::Write;
 
 use crate::tuple::varint::{decode_varint, encode_varint, varint_len};
 
+use super::blob::BlobRef;
+
 /// Sequence number for ordering entries with the same key.
 /// Higher sequence numbers are newer.
 pub type SeqNum = u64;
@@ -109,18 +111,42 @@ impl From<Vec<u8>> for Value {
     }
 }
 
+/// How the value of a put entry is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    /// `value` holds the value itself.
+    Inline,
+    /// `value` holds an encoded `BlobRef` to a value stored in a blob file.
+    BlobIndex,
+    /// `value` holds a merge operand, applied by the `MergeOperator`.
+    Merge,
+}
+
 /// An entry in the LSM tree.
 /// 
 /// - `key`: The key bytes
 /// - `seq_num`: Sequence number for ordering (higher = newer)
 /// - `value`: Some(value) for a put, None for a delete (tombstone)
+/// - `value_kind`: Whether `value` is the value itself or a blob reference
+/// - `expires_at`: For a put with a TTL, seconds since the Unix epoch after which
+///   the entry reads as deleted
 #[derive(Clone, Debug)]
 pub struct Entry {
     pub key: Key,
     pub seq_num: SeqNum,
     pub value: Option<Value>,
+    pub value_kind: ValueKind,
+    pub expires_at: Option<u64>,
 }
 
+/// Entry encoding markers, stored in the byte following the sequence number.
+const ENTRY_INLINE: u8 = 0;
+const ENTRY_TOMBSTONE: u8 = 1;
+const ENTRY_BLOB_INDEX: u8 = 2;
+const ENTRY_MERGE: u8 = 3;
+/// Flag on the entry type: an expiry timestamp (u64) follows the type byte.
+const ENTRY_HAS_EXPIRY: u8 = 0x80;
+
 impl Entry {
     /// Create a new put entry.
     pub fn put(key: Key, seq_num: SeqNum, value: Value) -> Self {
@@ -128,6 +154,8 @@ impl Entry {
             key,
             seq_num,
             value: Some(value),
+            value_kind: ValueKind::Inline,
+            expires_at: None,
         }
     }
 
@@ -137,20 +165,67 @@ impl Entry {
             key,
             seq_num,
             value: None,
+            value_kind: ValueKind::Inline,
+            expires_at: None,
+        }
+    }
+
+    /// Create a put entry whose value lives in a blob file.
+    pub fn blob_index(key: Key, seq_num: SeqNum, blob_ref: &BlobRef) -> Self {
+        Self {
+            key,
+            seq_num,
+            value: Some(blob_ref.encode()),
+            value_kind: ValueKind::BlobIndex,
+            expires_at: None,
+        }
+    }
+
+    /// Create a merge operand entry.
+    pub fn merge(key: Key, seq_num: SeqNum, operand: Value) -> Self {
+        Self {
+            key,
+            seq_num,
+            value: Some(operand),
+            value_kind: ValueKind::Merge,
+            expires_at: None,
         }
     }
 
+    /// Set when the entry expires (seconds since the Unix epoch).
+    pub fn with_expiry(mut self, expires_at: Option<u64>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
     /// Returns true if this is a tombstone (delete marker).
     pub fn is_tombstone(&self) -> bool {
         self.value.is_none()
     }
 
+    /// Returns true if the entry's TTL has passed at `now` (seconds since the Unix epoch).
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Returns the blob reference if the value lives in a blob file.
+    pub fn blob_ref(&self) -> Result<Option<BlobRef>, std::io::Error> {
+        match (&self.value, self.value_kind) {
+            (Some(value), ValueKind::BlobIndex) => BlobRef::decode(value).map(Some),
+            _ => Ok(None),
+        }
+    }
+
     /// Serialized size in bytes.
     pub fn serialized_size(&self) -> usize {
-        // Format: key_len (varint) + key + seq_num (8) + tombstone (1) + [value_len (varint) + value]
+        // Format: key_len (varint) + key + seq_num (8) + entry type (1) + [expires_at (8)]
+        //         + [value_len (varint) + value]
         let mut size = varint_len(self.key.len() as u64) + self.key.len();
         size += 8; // seq_num
-        size += 1; // tombstone flag
+        size += 1; // entry type
+        if self.expires_at.is_some() {
+            size += 8;
+        }
         if let Some(ref value) = self.value {
             size += varint_len(value.len() as u64) + value.len();
         }
@@ -166,56 +241,107 @@ impl Entry {
         writer.write_all(self.key.as_bytes())?;
         written += self.key.len();
 
+        written += self.write_body_to(writer)?;
+        Ok(written)
+    }
+
+    /// Serialize everything but the key: sequence number, entry type, expiry and value.
+    /// Used by encodings that store keys separately (e.g. prefix-compressed blocks).
+    pub fn write_body_to<W: Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut written = 0;
+
         // Write sequence number
         writer.write_all(&self.seq_num.to_le_bytes())?;
         written += 8;
 
-        // Write tombstone flag and value
+        // Write entry type and value
         if let Some(ref value) = self.value {
-            writer.write_all(&[0u8])?; // not a tombstone
-            written += 1;
+            let entry_type = match self.value_kind {
+                ValueKind::Inline => ENTRY_INLINE,
+                ValueKind::BlobIndex => ENTRY_BLOB_INDEX,
+                ValueKind::Merge => ENTRY_MERGE,
+            };
+            match self.expires_at {
+                Some(expires_at) => {
+                    writer.write_all(&[entry_type | ENTRY_HAS_EXPIRY])?;
+                    writer.write_all(&expires_at.to_le_bytes())?;
+                    written += 9;
+                }
+                None => {
+                    writer.write_all(&[entry_type])?;
+                    written += 1;
+                }
+            }
             written += encode_varint(value.len() as u64, writer)?;
             writer.write_all(value.as_bytes())?;
             written += value.len();
         } else {
-            writer.write_all(&[1u8])?; // tombstone
+            writer.write_all(&[ENTRY_TOMBSTONE])?;
             written += 1;
         }
 
         Ok(written)
     }
 
+    /// Decode just the key from the leading bytes of a serialized entry.
+    /// Returns None if `data` is too short to contain the whole key.
+    pub fn peek_key(data: &[u8]) -> Option<Key> {
+        let (key_len, key_len_size) = decode_varint(data).ok()?;
+        let key_end = key_len_size.checked_add(key_len as usize)?;
+        data.get(key_len_size..key_end).map(Key::from_slice)
+    }
+
     /// Deserialize entry from bytes.
     pub fn read_from(data: &[u8]) -> Result<(Self, usize), std::io::Error> {
-        let mut offset = 0;
-
         // Read key length + key
-        let (key_len, key_len_size) = decode_varint(&data[offset..])?;
-        offset += key_len_size;
-        let key = Key::from_slice(&data[offset..offset + key_len as usize]);
-        offset += key_len as usize;
+        let (key_len, key_len_size) = decode_varint(data)?;
+        let key_end = key_len_size + key_len as usize;
+        let key_bytes = data.get(key_len_size..key_end).ok_or_else(invalid_entry)?;
+        let key = Key::from_slice(key_bytes);
+
+        let (entry, body_len) = Self::read_body(key, &data[key_end..])?;
+        Ok((entry, key_end + body_len))
+    }
+
+    /// Deserialize an entry body written by `write_body_to`, for the given key.
+    pub fn read_body(key: Key, data: &[u8]) -> Result<(Self, usize), std::io::Error> {
+        let mut offset = 0;
 
         // Read sequence number
         let seq_num = u64::from_le_bytes(
-            data[offset..offset + 8]
-                .try_into()
-                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid seq_num"))?
+            data.get(offset..offset + 8)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid seq_num"))?
         );
         offset += 8;
 
-        // Read tombstone flag
-        let is_tombstone = data[offset] != 0;
+        // Read entry type
+        let entry_type = *data.get(offset).ok_or_else(invalid_entry)?;
         offset += 1;
+        let expires_at = if entry_type & ENTRY_HAS_EXPIRY != 0 {
+            let bytes = data.get(offset..offset + 8).ok_or_else(invalid_entry)?;
+            offset += 8;
+            Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+        } else {
+            None
+        };
+        let entry_type = entry_type & !ENTRY_HAS_EXPIRY;
+        let value_kind = match entry_type {
+            ENTRY_INLINE | ENTRY_TOMBSTONE => ValueKind::Inline,
+            ENTRY_BLOB_INDEX => ValueKind::BlobIndex,
+            ENTRY_MERGE => ValueKind::Merge,
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid entry type")),
+        };
 
         // Read value if not tombstone
-        let value = if is_tombstone {
+        let value = if entry_type == ENTRY_TOMBSTONE {
             None
         } else {
             let (value_len, value_len_size) = decode_varint(&data[offset..])?;
             offset += value_len_size;
-            let value = Value::from_slice(&data[offset..offset + value_len as usize]);
+            let value_bytes = data.get(offset..offset + value_len as usize).ok_or_else(invalid_entry)?;
             offset += value_len as usize;
-            Some(value)
+            Some(Value::from_slice(value_bytes))
         };
 
         Ok((
@@ -223,12 +349,18 @@ impl Entry {
                 key,
                 seq_num,
                 value,
+                value_kind,
+                expires_at,
             },
             offset,
         ))
     }
 }
 
+fn invalid_entry() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "Truncated entry")
+}
+
 /// Ordering for entries: first by key ascending, then by seq_num descending.
 /// This ensures that for the same key, newer entries come first.
 impl Ord for Entry {
@@ -290,6 +422,35 @@ mod tests {
         assert_eq!(decoded.seq_num, entry.seq_num);
     }
 
+    #[test]
+    fn test_expiring_entry_serialization() {
+        let entry = Entry::put(Key::from("session"), 9, Value::from("token")).with_expiry(Some(1_700_000_060));
+
+        let mut buffer = Vec::new();
+        let written = entry.write_to(&mut buffer).unwrap();
+        assert_eq!(written, entry.serialized_size());
+
+        let (decoded, read) = Entry::read_from(&buffer).unwrap();
+        assert_eq!(read, buffer.len());
+        assert_eq!(decoded.value, entry.value);
+        assert_eq!(decoded.expires_at, Some(1_700_000_060));
+        assert!(!decoded.is_expired(1_700_000_059));
+        assert!(decoded.is_expired(1_700_000_060));
+    }
+
+    #[test]
+    fn test_blob_index_serialization() {
+        let blob_ref = BlobRef { file_id: 3, offset: 4096, len: 1 << 20 };
+        let entry = Entry::blob_index(Key::from("large"), 7, &blob_ref);
+
+        let mut buffer = Vec::new();
+        entry.write_to(&mut buffer).unwrap();
+
+        let (decoded, _) = Entry::read_from(&buffer).unwrap();
+        assert_eq!(decoded.value_kind, ValueKind::BlobIndex);
+        assert_eq!(decoded.blob_ref().unwrap(), Some(blob_ref));
+    }
+
     #[test]
     fn test_entry_ordering() {
         let e1 = Entry::put(Key::from("a"), 1, Value::from("v1"));
@@ -0,0 +1,35 @@
+//! Super versions - immutable views of a column family's tables for readers.
+//!
+//! A super version lists the sealed memtables being flushed and the SSTables of
+//! a column family. Readers clone the `Arc` of the current one under a briefly
+//! held lock and probe it without holding any lock, while flush and compaction
+//! install a new one in its place.
+//!
+//! The active memtable is written in place under its own lock. Readers copy what
+//! they need from it and take the super version under its read lock, then release
+//! it; flush seals it while holding its write lock, so every write is seen in
+//! exactly one of them.
+//!
+//! Tables leaving the current super version are marked obsolete; their file is
+//! deleted when the last super version referencing them is dropped.
+
+use std::sync::Arc;
+
+use super::memtable::MemTable;
+use super::sstable::SSTableReader;
+
+/// The sealed memtables and SSTables of a column family at one point in time.
+#[derive(Default)]
+pub(crate) struct SuperVersion {
+    /// Memtables sealed by a flush and not yet written out, newest first.
+    pub(crate) immutable_memtables: Vec<Arc<MemTable>>,
+    /// Immutable SSTables (newest first).
+    pub(crate) sstables: Vec<Arc<SSTableReader>>,
+}
+
+impl SuperVersion {
+    /// The memtables a read goes through, newest first, starting with the active one.
+    pub(crate) fn memtables<'a>(&'a self, active: &'a MemTable) -> Vec<&'a MemTable> {
+        std::iter::once(active).chain(self.immutable_memtables.iter().map(Arc::as_ref)).collect()
+    }
+}
@@ -2,27 +2,60 @@
 //!
 //! All writes are logged to the WAL before being applied to the memtable.
 //! On crash recovery, the WAL is replayed to restore the memtable state.
+//!
+//! A tree's WAL is split into numbered segment files, `wal-<number>.log`, shared
+//! by all column families. Appends go to the newest segment; an older one is
+//! deleted once every column family has flushed all the writes it logged there.
 
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use super::types::{Entry, Key, SeqNum, Value};
+use super::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use super::range_tombstone::RangeTombstone;
+use super::types::{Entry, Key, SeqNum, Value, ValueKind};
 
 /// Write-ahead log for durability.
 pub struct Wal {
     writer: BufWriter<File>,
-    path: String,
 }
 
 /// WAL entry type markers.
 const WAL_PUT: u8 = 1;
 const WAL_DELETE: u8 = 2;
+const WAL_DELETE_RANGE: u8 = 3;
+const WAL_PUT_WITH_EXPIRY: u8 = 4;
+const WAL_MERGE: u8 = 5;
+const WAL_BATCH: u8 = 6;
+const WAL_COLUMN_FAMILY: u8 = 7;
+
+/// A record replayed from the WAL.
+#[derive(Clone, Debug)]
+pub enum WalRecord {
+    /// A put or point delete.
+    Entry(Entry),
+    /// A range deletion.
+    DeleteRange(RangeTombstone),
+    /// A record of the column family with the given id. Records of the default
+    /// column family are not wrapped.
+    ColumnFamily(u32, Box<WalRecord>),
+}
+
+impl WalRecord {
+    /// Sequence number of the write.
+    pub fn seq_num(&self) -> SeqNum {
+        match self {
+            WalRecord::Entry(entry) => entry.seq_num,
+            WalRecord::DeleteRange(tombstone) => tombstone.seq_num,
+            WalRecord::ColumnFamily(_, record) => record.seq_num(),
+        }
+    }
+}
 
 impl Wal {
     /// Create or open a WAL file.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        let path_str = path.as_ref().to_string_lossy().to_string();
         let file = OpenOptions::new()
             .create(true)
             .append(true)
@@ -30,44 +63,140 @@ impl Wal {
         
         Ok(Self {
             writer: BufWriter::new(file),
-            path: path_str,
         })
     }
 
     /// Log a put operation.
     pub fn log_put(&mut self, key: &Key, value: &Value, seq_num: SeqNum) -> Result<(), std::io::Error> {
-        // Format: type (1) + seq_num (8) + key_len (4) + key + value_len (4) + value
-        self.writer.write_all(&[WAL_PUT])?;
-        self.writer.write_all(&seq_num.to_le_bytes())?;
-        self.writer.write_all(&(key.len() as u32).to_le_bytes())?;
-        self.writer.write_all(key.as_bytes())?;
-        self.writer.write_all(&(value.len() as u32).to_le_bytes())?;
-        self.writer.write_all(value.as_bytes())?;
-        self.writer.flush()?;
-        Ok(())
+        write_put(&mut self.writer, key, value, seq_num)?;
+        self.writer.flush()
+    }
+
+    /// Log a put that expires at `expires_at` (seconds since the Unix epoch).
+    pub fn log_put_with_expiry(
+        &mut self,
+        key: &Key,
+        value: &Value,
+        expires_at: u64,
+        seq_num: SeqNum,
+    ) -> Result<(), std::io::Error> {
+        write_put_with_expiry(&mut self.writer, key, value, expires_at, seq_num)?;
+        self.writer.flush()
+    }
+
+    /// Log a merge operand.
+    pub fn log_merge(&mut self, key: &Key, operand: &Value, seq_num: SeqNum) -> Result<(), std::io::Error> {
+        write_merge(&mut self.writer, key, operand, seq_num)?;
+        self.writer.flush()
     }
 
     /// Log a delete operation.
     pub fn log_delete(&mut self, key: &Key, seq_num: SeqNum) -> Result<(), std::io::Error> {
-        // Format: type (1) + seq_num (8) + key_len (4) + key
-        self.writer.write_all(&[WAL_DELETE])?;
-        self.writer.write_all(&seq_num.to_le_bytes())?;
-        self.writer.write_all(&(key.len() as u32).to_le_bytes())?;
-        self.writer.write_all(key.as_bytes())?;
-        self.writer.flush()?;
-        Ok(())
+        write_delete(&mut self.writer, key, seq_num)?;
+        self.writer.flush()
+    }
+
+    /// Log a range delete operation.
+    pub fn log_delete_range(&mut self, start: &Key, end: &Key, seq_num: SeqNum) -> Result<(), std::io::Error> {
+        write_delete_range(&mut self.writer, start, end, seq_num)?;
+        self.writer.flush()
+    }
+
+    /// Log records that must be recovered together or not at all.
+    pub fn log_batch(&mut self, records: &[WalRecord]) -> Result<(), std::io::Error> {
+        // Format: type (1) + count (4) + payload_len (4) + payload (the records)
+        let mut payload = Vec::new();
+        for record in records {
+            write_record(&mut payload, record)?;
+        }
+        self.writer.write_all(&[WAL_BATCH])?;
+        self.writer.write_all(&(records.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&payload)?;
+        self.writer.flush()
     }
 
     /// Sync the WAL to disk.
-    #[allow(dead_code)]
     pub fn sync(&mut self) -> Result<(), std::io::Error> {
         self.writer.flush()?;
         self.writer.get_ref().sync_all()
     }
+}
+
+fn write_put(w: &mut impl Write, key: &Key, value: &Value, seq_num: SeqNum) -> Result<(), std::io::Error> {
+    // Format: type (1) + seq_num (8) + key_len (4) + key + value_len (4) + value
+    w.write_all(&[WAL_PUT])?;
+    w.write_all(&seq_num.to_le_bytes())?;
+    w.write_all(&(key.len() as u32).to_le_bytes())?;
+    w.write_all(key.as_bytes())?;
+    w.write_all(&(value.len() as u32).to_le_bytes())?;
+    w.write_all(value.as_bytes())
+}
+
+fn write_put_with_expiry(
+    w: &mut impl Write,
+    key: &Key,
+    value: &Value,
+    expires_at: u64,
+    seq_num: SeqNum,
+) -> Result<(), std::io::Error> {
+    // Format: type (1) + seq_num (8) + key_len (4) + key + expires_at (8) + value_len (4) + value
+    w.write_all(&[WAL_PUT_WITH_EXPIRY])?;
+    w.write_all(&seq_num.to_le_bytes())?;
+    w.write_all(&(key.len() as u32).to_le_bytes())?;
+    w.write_all(key.as_bytes())?;
+    w.write_all(&expires_at.to_le_bytes())?;
+    w.write_all(&(value.len() as u32).to_le_bytes())?;
+    w.write_all(value.as_bytes())
+}
+
+fn write_merge(w: &mut impl Write, key: &Key, operand: &Value, seq_num: SeqNum) -> Result<(), std::io::Error> {
+    // Format: type (1) + seq_num (8) + key_len (4) + key + operand_len (4) + operand
+    w.write_all(&[WAL_MERGE])?;
+    w.write_all(&seq_num.to_le_bytes())?;
+    w.write_all(&(key.len() as u32).to_le_bytes())?;
+    w.write_all(key.as_bytes())?;
+    w.write_all(&(operand.len() as u32).to_le_bytes())?;
+    w.write_all(operand.as_bytes())
+}
+
+fn write_delete(w: &mut impl Write, key: &Key, seq_num: SeqNum) -> Result<(), std::io::Error> {
+    // Format: type (1) + seq_num (8) + key_len (4) + key
+    w.write_all(&[WAL_DELETE])?;
+    w.write_all(&seq_num.to_le_bytes())?;
+    w.write_all(&(key.len() as u32).to_le_bytes())?;
+    w.write_all(key.as_bytes())
+}
 
-    /// Get the WAL file path.
-    pub fn path(&self) -> &str {
-        &self.path
+fn write_delete_range(w: &mut impl Write, start: &Key, end: &Key, seq_num: SeqNum) -> Result<(), std::io::Error> {
+    // Format: type (1) + seq_num (8) + start_len (4) + start + end_len (4) + end
+    w.write_all(&[WAL_DELETE_RANGE])?;
+    w.write_all(&seq_num.to_le_bytes())?;
+    w.write_all(&(start.len() as u32).to_le_bytes())?;
+    w.write_all(start.as_bytes())?;
+    w.write_all(&(end.len() as u32).to_le_bytes())?;
+    w.write_all(end.as_bytes())
+}
+
+fn write_record(w: &mut impl Write, record: &WalRecord) -> Result<(), std::io::Error> {
+    match record {
+        WalRecord::Entry(entry) => match (&entry.value, entry.value_kind, entry.expires_at) {
+            (None, _, _) => write_delete(w, &entry.key, entry.seq_num),
+            (Some(value), ValueKind::Merge, _) => write_merge(w, &entry.key, value, entry.seq_num),
+            (Some(value), _, Some(expires_at)) => {
+                write_put_with_expiry(w, &entry.key, value, expires_at, entry.seq_num)
+            }
+            (Some(value), _, None) => write_put(w, &entry.key, value, entry.seq_num),
+        },
+        WalRecord::DeleteRange(tombstone) => {
+            write_delete_range(w, &tombstone.start, &tombstone.end, tombstone.seq_num)
+        }
+        WalRecord::ColumnFamily(cf_id, record) => {
+            // Format: type (1) + cf_id (4) + record
+            w.write_all(&[WAL_COLUMN_FAMILY])?;
+            w.write_all(&cf_id.to_le_bytes())?;
+            write_record(w, record)
+        }
     }
 }
 
@@ -85,13 +214,13 @@ impl WalReader {
         })
     }
 
-    /// Read all entries from the WAL.
-    pub fn read_all(&mut self) -> Result<Vec<Entry>, std::io::Error> {
+    /// Read all records from the WAL.
+    pub fn read_all(&mut self) -> Result<Vec<WalRecord>, std::io::Error> {
         let mut entries = Vec::new();
         
         loop {
-            match self.read_entry() {
-                Ok(Some(entry)) => entries.push(entry),
+            match read_record(&mut self.reader) {
+                Ok(Some(records)) => entries.extend(records),
                 Ok(None) => break,
                 Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
                 Err(e) => return Err(e),
@@ -100,51 +229,110 @@ impl WalReader {
         
         Ok(entries)
     }
+}
 
-    fn read_entry(&mut self) -> Result<Option<Entry>, std::io::Error> {
-        // Read type byte
-        let mut type_buf = [0u8; 1];
-        match self.reader.read_exact(&mut type_buf) {
-            Ok(_) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
-            Err(e) => return Err(e),
-        }
+/// Read the next record from `reader`; a batch yields all its records.
+fn read_record(reader: &mut impl Read) -> Result<Option<Vec<WalRecord>>, std::io::Error> {
+    // Read type byte
+    let mut type_buf = [0u8; 1];
+    match reader.read_exact(&mut type_buf) {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
 
-        // Read seq_num
-        let mut seq_buf = [0u8; 8];
-        self.reader.read_exact(&mut seq_buf)?;
-        let seq_num = u64::from_le_bytes(seq_buf);
+    if type_buf[0] == WAL_BATCH {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let count = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let payload_len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        // A torn batch ends the log here, so none of its records are replayed
+        let mut payload = vec![0u8; payload_len];
+        reader.read_exact(&mut payload)?;
 
-        // Read key
-        let mut key_len_buf = [0u8; 4];
-        self.reader.read_exact(&mut key_len_buf)?;
-        let key_len = u32::from_le_bytes(key_len_buf) as usize;
-        
-        let mut key_buf = vec![0u8; key_len];
-        self.reader.read_exact(&mut key_buf)?;
-        let key = Key::new(key_buf);
-
-        match type_buf[0] {
-            WAL_PUT => {
-                // Read value
-                let mut value_len_buf = [0u8; 4];
-                self.reader.read_exact(&mut value_len_buf)?;
-                let value_len = u32::from_le_bytes(value_len_buf) as usize;
-                
-                let mut value_buf = vec![0u8; value_len];
-                self.reader.read_exact(&mut value_buf)?;
-                let value = Value::new(value_buf);
-
-                Ok(Some(Entry::put(key, seq_num, value)))
+        let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid WAL batch");
+        let mut cursor = payload.as_slice();
+        let mut records = Vec::with_capacity(count);
+        while records.len() < count {
+            match read_record(&mut cursor) {
+                Ok(Some(record)) if record.len() == 1 => records.extend(record),
+                Ok(_) => return Err(invalid()),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Err(invalid()),
+                Err(e) => return Err(e),
             }
-            WAL_DELETE => {
-                Ok(Some(Entry::delete(key, seq_num)))
+        }
+        return Ok(Some(records));
+    }
+
+    if type_buf[0] == WAL_COLUMN_FAMILY {
+        let mut cf_id_buf = [0u8; 4];
+        reader.read_exact(&mut cf_id_buf)?;
+        let cf_id = u32::from_le_bytes(cf_id_buf);
+        return match read_record(reader)? {
+            Some(mut records) if records.len() == 1 => {
+                Ok(Some(vec![WalRecord::ColumnFamily(cf_id, Box::new(records.remove(0)))]))
             }
-            _ => Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid WAL entry type",
-            )),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Torn WAL record")),
+        };
+    }
+
+    // Read seq_num
+    let mut seq_buf = [0u8; 8];
+    reader.read_exact(&mut seq_buf)?;
+    let seq_num = u64::from_le_bytes(seq_buf);
+
+    // Read key
+    let mut key_len_buf = [0u8; 4];
+    reader.read_exact(&mut key_len_buf)?;
+    let key_len = u32::from_le_bytes(key_len_buf) as usize;
+    
+    let mut key_buf = vec![0u8; key_len];
+    reader.read_exact(&mut key_buf)?;
+    let key = Key::new(key_buf);
+
+    match type_buf[0] {
+        entry_type @ (WAL_PUT | WAL_PUT_WITH_EXPIRY | WAL_MERGE) => {
+            let expires_at = if entry_type == WAL_PUT_WITH_EXPIRY {
+                let mut expires_buf = [0u8; 8];
+                reader.read_exact(&mut expires_buf)?;
+                Some(u64::from_le_bytes(expires_buf))
+            } else {
+                None
+            };
+
+            // Read value
+            let mut value_len_buf = [0u8; 4];
+            reader.read_exact(&mut value_len_buf)?;
+            let value_len = u32::from_le_bytes(value_len_buf) as usize;
+            
+            let mut value_buf = vec![0u8; value_len];
+            reader.read_exact(&mut value_buf)?;
+            let value = Value::new(value_buf);
+
+            let entry = match entry_type {
+                WAL_MERGE => Entry::merge(key, seq_num, value),
+                _ => Entry::put(key, seq_num, value).with_expiry(expires_at),
+            };
+            Ok(Some(vec![WalRecord::Entry(entry)]))
         }
+        WAL_DELETE => {
+            Ok(Some(vec![WalRecord::Entry(Entry::delete(key, seq_num))]))
+        }
+        WAL_DELETE_RANGE => {
+            // The key read above is the range start
+            let mut end_len_buf = [0u8; 4];
+            reader.read_exact(&mut end_len_buf)?;
+            let end_len = u32::from_le_bytes(end_len_buf) as usize;
+
+            let mut end_buf = vec![0u8; end_len];
+            reader.read_exact(&mut end_buf)?;
+
+            Ok(Some(vec![WalRecord::DeleteRange(RangeTombstone::new(key, Key::new(end_buf), seq_num))]))
+        }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Invalid WAL entry type",
+        )),
     }
 }
 
@@ -153,6 +341,145 @@ pub fn delete_wal<P: AsRef<Path>>(path: P) -> Result<(), std::io::Error> {
     std::fs::remove_file(path)
 }
 
+/// WAL file of trees written before the WAL was split into segments.
+const LEGACY_WAL_FILE: &str = "wal.log";
+
+/// The WAL of a tree, as numbered segment files in its data directory.
+pub(crate) struct WalSegments {
+    dir: PathBuf,
+    /// The newest segment, taking appends.
+    active: Wal,
+    active_number: u64,
+    /// Highest sequence number logged per column family id, by segment number.
+    max_seqs: BTreeMap<u64, HashMap<u32, SeqNum>>,
+}
+
+impl WalSegments {
+    /// Open the WAL segments in `dir`, returning them with all records logged so
+    /// far, oldest segment first. Appends go to a new segment.
+    pub(crate) fn open(dir: impl AsRef<Path>) -> Result<(Self, Vec<WalRecord>), std::io::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        // The single WAL of an older tree comes before any segment
+        let legacy = dir.join(LEGACY_WAL_FILE);
+        if legacy.exists() {
+            std::fs::rename(&legacy, segment_path(&dir, 0))?;
+        }
+
+        let mut records = Vec::new();
+        let mut max_seqs = BTreeMap::new();
+        for number in segment_numbers(&dir)? {
+            let segment_records = WalReader::open(segment_path(&dir, number))?.read_all()?;
+            let seqs = max_seqs.entry(number).or_insert_with(HashMap::new);
+            for record in &segment_records {
+                note_record(seqs, record);
+            }
+            records.extend(segment_records);
+        }
+
+        let active_number = max_seqs.keys().last().map_or(1, |number| number + 1);
+        let active = Wal::open(segment_path(&dir, active_number))?;
+        max_seqs.insert(active_number, HashMap::new());
+        let segments = Self {
+            dir,
+            active,
+            active_number,
+            max_seqs,
+        };
+        Ok((segments, records))
+    }
+
+    /// The segment taking appends, for logging a write of `seq_num` to column
+    /// family `cf_id`.
+    pub(crate) fn active(&mut self, cf_id: u32, seq_num: SeqNum) -> &mut Wal {
+        note_seq(self.max_seqs.get_mut(&self.active_number).unwrap(), cf_id, seq_num);
+        &mut self.active
+    }
+
+    /// Log records that must be recovered together or not at all.
+    pub(crate) fn log_batch(&mut self, records: &[WalRecord]) -> Result<(), std::io::Error> {
+        let seqs = self.max_seqs.get_mut(&self.active_number).unwrap();
+        for record in records {
+            note_record(seqs, record);
+        }
+        self.active.log_batch(records)
+    }
+
+    /// Sync the active segment to disk, and return the paths of the live segments
+    /// with their lengths, oldest first. Appends past these lengths may follow.
+    pub(crate) fn sync_segments(&mut self) -> Result<Vec<(PathBuf, u64)>, std::io::Error> {
+        self.active.sync()?;
+        self.max_seqs
+            .keys()
+            .map(|&number| {
+                let path = segment_path(&self.dir, number);
+                let len = std::fs::metadata(&path)?.len();
+                Ok((path, len))
+            })
+            .collect()
+    }
+
+    /// Send further appends to a new segment, unless the active one is empty.
+    pub(crate) fn rotate(&mut self) -> Result<(), std::io::Error> {
+        if self.max_seqs[&self.active_number].is_empty() {
+            return Ok(());
+        }
+        let number = self.active_number + 1;
+        self.active = Wal::open(segment_path(&self.dir, number))?;
+        self.active_number = number;
+        self.max_seqs.insert(number, HashMap::new());
+        Ok(())
+    }
+
+    /// Delete the segments whose writes are all flushed. `flushed_seq` gives the
+    /// flushed sequence number of a column family, or None if it no longer exists.
+    pub(crate) fn purge(&mut self, flushed_seq: impl Fn(u32) -> Option<SeqNum>) -> Result<(), std::io::Error> {
+        let obsolete: Vec<u64> = self
+            .max_seqs
+            .iter()
+            .filter(|&(&number, seqs)| {
+                number != self.active_number
+                    && seqs.iter().all(|(&cf_id, &max_seq)| flushed_seq(cf_id).is_none_or(|seq| max_seq <= seq))
+            })
+            .map(|(&number, _)| number)
+            .collect();
+        for number in obsolete {
+            delete_wal(segment_path(&self.dir, number))?;
+            self.max_seqs.remove(&number);
+        }
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("wal-{:06}.log", number))
+}
+
+/// Numbers of the segment files in `dir`, ascending.
+fn segment_numbers(dir: &Path) -> Result<Vec<u64>, std::io::Error> {
+    let mut numbers = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let number = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("wal-")?.strip_suffix(".log")?.parse::<u64>().ok());
+        numbers.extend(number);
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+fn note_record(seqs: &mut HashMap<u32, SeqNum>, record: &WalRecord) {
+    match record {
+        WalRecord::ColumnFamily(cf_id, record) => note_seq(seqs, *cf_id, record.seq_num()),
+        record => note_seq(seqs, DEFAULT_COLUMN_FAMILY_ID, record.seq_num()),
+    }
+}
+
+fn note_seq(seqs: &mut HashMap<u32, SeqNum>, cf_id: u32, seq_num: SeqNum) {
+    let max_seq = seqs.entry(cf_id).or_default();
+    *max_seq = (*max_seq).max(seq_num);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -173,13 +500,29 @@ mod tests {
             wal.log_put(&Key::from("key1"), &Value::from("value1"), 1).unwrap();
             wal.log_put(&Key::from("key2"), &Value::from("value2"), 2).unwrap();
             wal.log_delete(&Key::from("key1"), 3).unwrap();
+            wal.log_delete_range(&Key::from("a"), &Key::from("m"), 4).unwrap();
         }
 
         // Read entries
         {
             let mut reader = WalReader::open(&path).unwrap();
-            let entries = reader.read_all().unwrap();
-            
+            let mut records = reader.read_all().unwrap();
+
+            assert_eq!(records.len(), 4);
+            match records.pop().unwrap() {
+                WalRecord::DeleteRange(tombstone) => {
+                    assert_eq!(tombstone, RangeTombstone::new(Key::from("a"), Key::from("m"), 4));
+                }
+                other => panic!("Expected a range delete, got {:?}", other),
+            }
+
+            let entries: Vec<Entry> = records
+                .into_iter()
+                .map(|record| match record {
+                    WalRecord::Entry(entry) => entry,
+                    other => panic!("Expected an entry, got {:?}", other),
+                })
+                .collect();
             assert_eq!(entries.len(), 3);
             
             assert_eq!(entries[0].key.as_bytes(), b"key1");
@@ -196,4 +539,29 @@ mod tests {
 
         let _ = std::fs::remove_file(path);
     }
+
+    #[test]
+    fn test_wal_batch() {
+        let path = get_temp_path();
+        let batch = [
+            WalRecord::Entry(Entry::put(Key::from("a"), 2, Value::from("1"))),
+            WalRecord::Entry(Entry::merge(Key::from("b"), 3, Value::from("2"))),
+            WalRecord::DeleteRange(RangeTombstone::new(Key::from("c"), Key::from("d"), 4)),
+        ];
+        {
+            let mut wal = Wal::open(&path).unwrap();
+            wal.log_delete(&Key::from("x"), 1).unwrap();
+            wal.log_batch(&batch).unwrap();
+        }
+        let records = WalReader::open(&path).unwrap().read_all().unwrap();
+        assert_eq!(records.len(), 4);
+
+        // A torn batch is dropped as a whole
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 1).unwrap();
+        let records = WalReader::open(&path).unwrap().read_all().unwrap();
+        assert_eq!(records.len(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
 }
@@ -1,11 +1,17 @@
 use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
 
 use crate::{
-    bufferpool::{BufferPool, BufferSlot, PageAddr}, constants::PAGE_SIZE,
+    bufferpool::{BufferPool, BufferSlot, PageAddr}, constants::PAGE_SIZE, error::Corruption,
 };
 
 /**
  * Page format:
+ * - header:
+ *   - format_version: u8
+ *   - page_type: u8
+ *   - reserved: u16
+ *   - checksum: u32 (crc32 of the page with this field zeroed)
+ *   - lsn: u64
  * - free_start: u16
  * - free_end: u16
  * - cell_pointers:
@@ -13,12 +19,86 @@ use crate::{
  *   - cell_size: u16
  * - free space
  * - cells: [u8;]
+ *
+ * Overflow pages use the same layout with a single cell:
+ * - next_page: u64 (u64::MAX ends the chain)
+ * - chunk: [u8;]
  */
 
-const FREE_START_OFFSET_OFFSET: usize = 0;
-const FREE_END_OFFSET_OFFSET: usize = 2;
-const CELL_POINTERS_OFFSET: usize = 4;
+pub(crate) const PAGE_FORMAT_VERSION: u8 = 1;
+
+const FORMAT_VERSION_OFFSET: usize = 0;
+const PAGE_TYPE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 4;
+const LSN_OFFSET: usize = 8;
+const FREE_START_OFFSET_OFFSET: usize = 16;
+const FREE_END_OFFSET_OFFSET: usize = 18;
+const CELL_POINTERS_OFFSET: usize = 20;
 const CELL_POINTER_SIZE: usize = 4;
+const NO_NEXT_PAGE: u64 = u64::MAX;
+
+/// Largest cell that fits in an empty page.
+pub(crate) const MAX_CELL_SIZE: usize = PAGE_SIZE - CELL_POINTERS_OFFSET - CELL_POINTER_SIZE;
+/// Bytes of payload carried by each overflow page.
+pub(crate) const OVERFLOW_CHUNK_SIZE: usize = MAX_CELL_SIZE - 8;
+
+/// What a page is used for, stored in the page header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PageType {
+    /// Slotted page holding data cells.
+    Data = 1,
+    /// Slotted page holding table metadata.
+    Meta = 2,
+    /// Continuation page holding one chunk of a record that does not fit in a cell.
+    Overflow = 3,
+}
+
+impl TryFrom<u8> for PageType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(PageType::Data),
+            2 => Ok(PageType::Meta),
+            3 => Ok(PageType::Overflow),
+            other => Err(other),
+        }
+    }
+}
+
+/// Compute the checksum of a page, treating the checksum field as zero.
+pub(crate) fn compute_checksum(page_data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&page_data[..CHECKSUM_OFFSET]);
+    hasher.update(&[0u8; 4]);
+    hasher.update(&page_data[CHECKSUM_OFFSET + 4..]);
+    hasher.finalize()
+}
+
+/// Store the checksum of the page in its header. Called before a page is written back.
+pub(crate) fn stamp_checksum(page_data: &mut [u8]) {
+    let checksum = compute_checksum(page_data);
+    page_data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Verify the checksum stored in the page header. All-zero pages have never been
+/// written and are accepted as is.
+pub(crate) fn verify_checksum(page_address: PageAddr, page_data: &[u8]) -> Result<(), Corruption> {
+    let stored = u32::from_le_bytes(page_data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].try_into().unwrap());
+    if stored == 0 && page_data.iter().all(|&b| b == 0) {
+        return Ok(());
+    }
+    let computed = compute_checksum(page_data);
+    if stored != computed {
+        return Err(Corruption::PageChecksumMismatch {
+            page: page_address,
+            stored,
+            computed,
+        });
+    }
+    Ok(())
+}
 
 pub struct Page<'a> {
     slot: RwLockReadGuard<'a, Box<BufferSlot>>,
@@ -31,16 +111,76 @@ pub struct PageMut<'a> {
 pub trait PageRead {
     fn page_data(&self) -> &[u8];
 
+    fn page_address(&self) -> PageAddr;
+
     fn read_u16(&self, offset: usize) -> Result<u16, std::io::Error> {
         let bytes = &self.page_data()[offset..offset + 2];
         Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
     }
 
+    fn read_u64(&self, offset: usize) -> Result<u64, std::io::Error> {
+        let bytes = &self.page_data()[offset..offset + 8];
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn page_type(&self) -> Result<PageType, std::io::Error> {
+        let page_type = self.page_data()[PAGE_TYPE_OFFSET];
+        PageType::try_from(page_type).map_err(|page_type| {
+            Corruption::UnknownPageType {
+                page: self.page_address(),
+                page_type,
+            }
+            .into()
+        })
+    }
+
+    /// Log sequence number of the newest change stored in this page.
+    fn lsn(&self) -> Result<u64, std::io::Error> {
+        self.read_u64(LSN_OFFSET)
+    }
+
+    /// Check the header fields that every other accessor relies on.
+    fn validate_header(&self) -> Result<(), std::io::Error> {
+        let version = self.page_data()[FORMAT_VERSION_OFFSET];
+        if version != PAGE_FORMAT_VERSION {
+            return Err(Corruption::UnsupportedPageVersion {
+                page: self.page_address(),
+                version,
+            }
+            .into());
+        }
+        self.page_type()?;
+        let free_start: usize = self.read_u16(FREE_START_OFFSET_OFFSET)? as usize;
+        let free_end: usize = self.read_u16(FREE_END_OFFSET_OFFSET)? as usize;
+        if free_start < CELL_POINTERS_OFFSET
+            || !(free_start - CELL_POINTERS_OFFSET).is_multiple_of(CELL_POINTER_SIZE)
+            || free_start > free_end
+            || free_end > PAGE_SIZE
+        {
+            return Err(Corruption::InvalidFreeSpace {
+                page: self.page_address(),
+                free_start,
+                free_end,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
     fn read_cell_pointer(&self, entry_index: usize) -> Result<(usize, usize), std::io::Error> {
         let cell_pointer_offset: usize = CELL_POINTERS_OFFSET + entry_index * CELL_POINTER_SIZE;
         let cell_start_offset: usize = self.read_u16(cell_pointer_offset)? as usize;
         let cell_end_offset: usize = cell_start_offset + self.read_u16(cell_pointer_offset + 2)? as usize;
-        assert!(cell_start_offset < cell_end_offset && cell_end_offset <= PAGE_SIZE);
+        let free_end_offset: usize = self.read_u16(FREE_END_OFFSET_OFFSET)? as usize;
+        if cell_start_offset < free_end_offset || cell_start_offset >= cell_end_offset || cell_end_offset > PAGE_SIZE {
+            return Err(Corruption::InvalidCellPointer {
+                page: self.page_address(),
+                cell: entry_index,
+                start: cell_start_offset,
+                end: cell_end_offset,
+            }
+            .into());
+        }
         Ok((cell_start_offset, cell_end_offset))
     }
 
@@ -53,40 +193,86 @@ pub trait PageRead {
         let free_start_offset: usize = self.read_u16(FREE_START_OFFSET_OFFSET)? as usize;
         Ok((free_start_offset - CELL_POINTERS_OFFSET) / CELL_POINTER_SIZE)
     }
+
+    /// Read the chunk stored in an overflow page and the id of the next page in the chain.
+    fn read_overflow_chunk(&self) -> Result<(Option<u64>, &[u8]), std::io::Error> {
+        if self.page_type()? != PageType::Overflow || self.num_cells()? != 1 {
+            return Err(Corruption::BrokenOverflowChain {
+                page: self.page_address(),
+            }
+            .into());
+        }
+        let cell = self.read_cell(0)?;
+        if cell.len() < 8 {
+            return Err(Corruption::BrokenOverflowChain {
+                page: self.page_address(),
+            }
+            .into());
+        }
+        let next_page = u64::from_le_bytes(cell[..8].try_into().unwrap());
+        let next_page = if next_page == NO_NEXT_PAGE { None } else { Some(next_page) };
+        Ok((next_page, &cell[8..]))
+    }
 }
 
 impl<'a> PageRead for Page<'a> {
     fn page_data(&self) -> &[u8] {
         &self.slot.page_data
     }
+
+    fn page_address(&self) -> PageAddr {
+        self.slot.page_address
+    }
 }
 
 impl<'a> PageRead for PageMut<'a> {
     fn page_data(&self) -> &[u8] {
         &self.slot.page_data
     }
+
+    fn page_address(&self) -> PageAddr {
+        self.slot.page_address
+    }
 }
 
 impl<'a> Page<'a> {
     pub fn open(buffer_pool: &'a BufferPool, page_address: PageAddr) -> Result<Self, std::io::Error> {
         let slot = buffer_pool.pin_read(page_address)?;
-        Ok(Self { slot })
+        let page = Self { slot };
+        page.validate_header()?;
+        Ok(page)
     }
 }
 
 impl<'a> PageMut<'a> {
     pub fn open(buffer_pool: &'a BufferPool, page_address: PageAddr) -> Result<Self, std::io::Error> {
+        Self::open_with_type(buffer_pool, page_address, PageType::Data)
+    }
+
+    /// Open a page for writing, initializing it as `page_type` if it is empty (new page).
+    pub fn open_with_type(
+        buffer_pool: &'a BufferPool,
+        page_address: PageAddr,
+        page_type: PageType,
+    ) -> Result<Self, std::io::Error> {
         let slot = buffer_pool.pin_write(page_address)?;
         let mut page = Self { slot };
         // Initialize page if it's empty (new page)
-        if page.read_u16(FREE_START_OFFSET_OFFSET)? == 0 && page.read_u16(FREE_END_OFFSET_OFFSET)? == 0 {
-            page.init()?;
+        if page.page_data()[FORMAT_VERSION_OFFSET] == 0
+            && page.read_u16(FREE_START_OFFSET_OFFSET)? == 0
+            && page.read_u16(FREE_END_OFFSET_OFFSET)? == 0
+        {
+            page.init(page_type)?;
         }
+        page.validate_header()?;
         Ok(page)
     }
 
-    /// Initialize an empty page with proper free space pointers
-    fn init(&mut self) -> Result<(), std::io::Error> {
+    /// Initialize an empty page with a header and proper free space pointers
+    fn init(&mut self, page_type: PageType) -> Result<(), std::io::Error> {
+        self.slot.page_data[FORMAT_VERSION_OFFSET] = PAGE_FORMAT_VERSION;
+        self.slot.page_data[PAGE_TYPE_OFFSET] = page_type as u8;
+        self.set_lsn(0)?;
         // free_start points to where the next cell pointer will be written
         self.write_u16(FREE_START_OFFSET_OFFSET, CELL_POINTERS_OFFSET as u16)?;
         // free_end points to where the next cell data will be written (end of page)
@@ -100,6 +286,12 @@ impl<'a> PageMut<'a> {
         Ok(())
     }
 
+    /// Record the log sequence number of the newest change stored in this page.
+    pub fn set_lsn(&mut self, lsn: u64) -> Result<(), std::io::Error> {
+        self.slot.page_data[LSN_OFFSET..LSN_OFFSET + 8].copy_from_slice(&lsn.to_le_bytes());
+        Ok(())
+    }
+
     pub fn has_space_for_cell(&self, len: usize) -> Result<bool, std::io::Error> {
         let free_start_offset: usize = self.read_u16(FREE_START_OFFSET_OFFSET)? as usize;
         let free_end_offset: usize = self.read_u16(FREE_END_OFFSET_OFFSET)? as usize;
@@ -122,4 +314,70 @@ impl<'a> PageMut<'a> {
         // Return cell data
         Ok(&mut self.slot.page_data[free_end_offset - cell_len..free_end_offset])
     }
+
+    /// Fill an empty overflow page with one chunk of a record.
+    pub fn write_overflow_chunk(&mut self, next_page: Option<u64>, chunk: &[u8]) -> Result<(), std::io::Error> {
+        assert_eq!(self.page_type()?, PageType::Overflow);
+        assert_eq!(self.num_cells()?, 0);
+        let cell = self.allocate_cell(8 + chunk.len())?;
+        cell[..8].copy_from_slice(&next_page.unwrap_or(NO_NEXT_PAGE).to_le_bytes());
+        cell[8..].copy_from_slice(chunk);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn get_temp_dir() -> String {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        format!("/tmp/thordb_page_test_{}", since_epoch.as_nanos())
+    }
+
+    #[test]
+    fn test_page_header_init() {
+        let dir = get_temp_dir();
+        let pool = BufferPool::new(dir.clone()).unwrap();
+        let page_addr = PageAddr::new(1, 3);
+
+        {
+            let mut page = PageMut::open_with_type(&pool, page_addr, PageType::Meta).unwrap();
+            page.set_lsn(42).unwrap();
+            page.allocate_cell(3).unwrap().copy_from_slice(b"abc");
+        }
+
+        let page = Page::open(&pool, page_addr).unwrap();
+        assert_eq!(page.page_type().unwrap(), PageType::Meta);
+        assert_eq!(page.lsn().unwrap(), 42);
+        assert_eq!(page.num_cells().unwrap(), 1);
+        assert_eq!(page.read_cell(0).unwrap(), b"abc");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_corrupt_cell_pointer_returns_error() {
+        let dir = get_temp_dir();
+        let pool = BufferPool::new(dir.clone()).unwrap();
+        let page_addr = PageAddr::new(1, 0);
+
+        {
+            let mut page = PageMut::open(&pool, page_addr).unwrap();
+            page.allocate_cell(3).unwrap().copy_from_slice(b"abc");
+            // Point the cell past the end of the page
+            page.write_u16(CELL_POINTERS_OFFSET, (PAGE_SIZE - 1) as u16).unwrap();
+        }
+
+        let page = Page::open(&pool, page_addr).unwrap();
+        let err = page.read_cell(0).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            Corruption::from_io_error(&err),
+            Some(Corruption::InvalidCellPointer { cell: 0, .. })
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
@@ -11,17 +11,37 @@ pub(crate) struct PageFile {
 }
 
 impl PageFile {
-    pub(crate) fn new(file: &PathBuf) -> Result<Self, std::io::Error> {
-        let file_handle = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(file)?;
+    /// Open (or create) a page file.
+    ///
+    /// With `direct_io` the file is opened with `O_DIRECT` so reads and writes bypass the
+    /// kernel page cache. Callers must then pass buffers aligned to `PAGE_SIZE`.
+    pub(crate) fn new(file: &PathBuf, direct_io: bool) -> Result<Self, std::io::Error> {
+        let mut options = std::fs::OpenOptions::new();
+        options.read(true).write(true).create(true);
+        if direct_io {
+            Self::enable_direct_io(&mut options)?;
+        }
+        let file_handle = options.open(file)?;
         Ok(Self {
             file_handle: Mutex::new(file_handle),
         })
     }
 
+    #[cfg(target_os = "linux")]
+    fn enable_direct_io(options: &mut std::fs::OpenOptions) -> Result<(), std::io::Error> {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_DIRECT);
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn enable_direct_io(_options: &mut std::fs::OpenOptions) -> Result<(), std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Direct I/O is only supported on Linux",
+        ))
+    }
+
     pub(crate) fn read_page(&self, page_id: u64, buffer: &mut [u8], create: bool) -> Result<(), std::io::Error> {
         let offset = page_id * PAGE_SIZE as u64;
         tracing::info!("Reading page {}", page_id);
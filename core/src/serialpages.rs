@@ -1,12 +1,42 @@
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
 use crate::{
     bufferpool::{BufferPool, PageAddr},
-    page::{Page, PageMut, PageRead}, tuple::tuple::{Tuple, TupleOnDisk},
+    error::Corruption,
+    page::{MAX_CELL_SIZE, OVERFLOW_CHUNK_SIZE, Page, PageMut, PageRead, PageType},
+    tuple::{
+        tuple::{Tuple, TupleOnDisk},
+        types::{TupleDescriptor, TupleFieldDescriptor, TupleFieldType, TupleValue},
+    },
 };
 
+/// Largest record payload that is stored inline in a single cell.
+/// Tuple overhead: null bitmap (1) + varint length (2).
+pub const MAX_INLINE_RECORD: usize = MAX_CELL_SIZE - 3;
+
+/// Bytes of a spilled record kept inline, so that a record's leading bytes
+/// (e.g. an entry key) can usually be read without following the chain.
+const SPILLED_PREFIX_LEN: usize = 512;
+
+/// Record cell layout:
+/// - payload: VarBytes (the whole record, or its prefix when spilled)
+/// - overflow_page: Int64 (first page of the overflow chain, null when inline)
+/// - total_len: Int64 (length of the whole record, null when inline)
+static RECORD_DESCRIPTOR: LazyLock<TupleDescriptor> = LazyLock::new(|| {
+    let mut descriptor = TupleDescriptor::new();
+    descriptor.add_field(TupleFieldDescriptor::new("payload".to_string(), TupleFieldType::VarBytes));
+    descriptor.add_field(TupleFieldDescriptor::new("overflow_page".to_string(), TupleFieldType::Int64));
+    descriptor.add_field(TupleFieldDescriptor::new("total_len".to_string(), TupleFieldType::Int64));
+    descriptor
+});
+
 pub struct SerialWriter<'a> {
     buffer_pool: &'a BufferPool,
     page_writer: PageMut<'a>,
     page_address: PageAddr,
+    /// First page not yet used by data or overflow pages.
+    next_free_page: PageAddr,
 }
 
 /// Reader for sequentially reading tuples across multiple pages.
@@ -35,28 +65,211 @@ impl<'a> SerialWriter<'a> {
             buffer_pool,
             page_writer,
             page_address,
+            next_free_page: page_address.next_page(),
         })
     }
 
+    /// Continue writing at `page_address`, whose overflow chains end before `next_free_page`.
+    pub fn resume(
+        buffer_pool: &'a BufferPool,
+        page_address: PageAddr,
+        next_free_page: PageAddr,
+    ) -> Result<Self, std::io::Error> {
+        let page_writer = PageMut::open(buffer_pool, page_address)?;
+        Ok(Self {
+            buffer_pool,
+            page_writer,
+            page_address,
+            next_free_page,
+        })
+    }
+
+    /// The data page currently being filled.
+    pub fn page_address(&self) -> PageAddr {
+        self.page_address
+    }
+
+    /// Mutable access to the data page currently being filled.
+    pub fn page_mut(&mut self) -> &mut PageMut<'a> {
+        &mut self.page_writer
+    }
+
+    /// First page not yet used by data or overflow pages.
+    pub fn next_free_page(&self) -> PageAddr {
+        self.next_free_page
+    }
+
+    /// Last page written so far, including overflow pages.
+    pub fn last_page(&self) -> PageAddr {
+        PageAddr::new(self.page_address.file_id(), self.next_free_page.page_id() - 1)
+    }
+
     fn switch_page(&mut self, new_page: PageAddr) -> Result<(), std::io::Error> {
         self.page_writer = PageMut::open(self.buffer_pool, new_page)?;
         self.page_address = new_page;
+        self.next_free_page = new_page.next_page();
         Ok(())
     }
 
-    pub fn append_tuple(&mut self, tuple: &Tuple) -> Result<(), std::io::Error> {
+    fn write_tuple(&mut self, tuple: &Tuple) -> Result<(), std::io::Error> {
         if !self.page_writer.has_space_for_cell(tuple.len())? {
-            self.switch_page(self.page_address.next_page())?;
+            self.switch_page(self.next_free_page)?;
+        }
+        write_tuple_to_page(&mut self.page_writer, tuple)
+    }
+
+    pub fn append_tuple(&mut self, tuple: &Tuple) -> Result<(), std::io::Error> {
+        assert!(tuple.len() <= MAX_CELL_SIZE, "Tuple does not fit in a page");
+        self.write_tuple(tuple)
+    }
+
+    /// Append a record of arbitrary size.
+    ///
+    /// Records up to `MAX_INLINE_RECORD` bytes are stored in a single cell. Larger records
+    /// keep a prefix inline and continue in a chain of overflow pages placed after the
+    /// current page. Read them back with `read_record`.
+    pub fn append_record(&mut self, record: &[u8]) -> Result<(), std::io::Error> {
+        // Switch pages before placing the chain, so the chain never lands on the page
+        // that ends up holding the record cell.
+        if !self.page_writer.has_space_for_cell(record_cell_len(record))? {
+            self.switch_page(self.next_free_page)?;
         }
-        let tuple_len = tuple.len();
-        let cell_buffer = self.page_writer.allocate_cell(tuple_len)?;
-        let mut cursor = std::io::Cursor::new(cell_buffer);
-        let bytes_written = tuple.write_to_stream(&mut cursor)?;
-        assert_eq!(bytes_written, tuple_len);
+        let pages_used = write_record(self.buffer_pool, &mut self.page_writer, record, self.next_free_page)?;
+        self.next_free_page = PageAddr::new(
+            self.next_free_page.file_id(),
+            self.next_free_page.page_id() + pages_used,
+        );
         Ok(())
     }
 }
 
+fn write_tuple_to_page(page: &mut PageMut, tuple: &Tuple) -> Result<(), std::io::Error> {
+    let tuple_len = tuple.len();
+    let cell_buffer = page.allocate_cell(tuple_len)?;
+    let mut cursor = std::io::Cursor::new(cell_buffer);
+    let bytes_written = tuple.write_to_stream(&mut cursor)?;
+    assert_eq!(bytes_written, tuple_len);
+    Ok(())
+}
+
+/// Size of the cell `write_record` uses for `record`.
+pub fn record_cell_len(record: &[u8]) -> usize {
+    if record.len() <= MAX_INLINE_RECORD {
+        Tuple::new(vec![TupleValue::VarBytes(record), TupleValue::Null, TupleValue::Null]).len()
+    } else {
+        Tuple::new(vec![
+            TupleValue::VarBytes(&record[..SPILLED_PREFIX_LEN]),
+            TupleValue::Int64(0),
+            TupleValue::Int64(0),
+        ])
+        .len()
+    }
+}
+
+/// Write a record as a cell of `page`, which must have room for `record_cell_len(record)`
+/// bytes. Records larger than `MAX_INLINE_RECORD` continue in an overflow chain starting
+/// at `overflow_start`. Returns the number of overflow pages used.
+pub fn write_record(
+    buffer_pool: &BufferPool,
+    page: &mut PageMut,
+    record: &[u8],
+    overflow_start: PageAddr,
+) -> Result<u64, std::io::Error> {
+    if record.len() <= MAX_INLINE_RECORD {
+        let tuple = Tuple::new(vec![TupleValue::VarBytes(record), TupleValue::Null, TupleValue::Null]);
+        write_tuple_to_page(page, &tuple)?;
+        return Ok(0);
+    }
+
+    let pages_used = write_overflow_chain(buffer_pool, overflow_start, &record[SPILLED_PREFIX_LEN..])?;
+    let tuple = Tuple::new(vec![
+        TupleValue::VarBytes(&record[..SPILLED_PREFIX_LEN]),
+        TupleValue::Int64(overflow_start.page_id() as i64),
+        TupleValue::Int64(record.len() as i64),
+    ]);
+    write_tuple_to_page(page, &tuple)?;
+    Ok(pages_used)
+}
+
+/// Write `data` into consecutive overflow pages starting at `start`.
+/// Returns the number of pages used.
+pub fn write_overflow_chain(buffer_pool: &BufferPool, start: PageAddr, data: &[u8]) -> Result<u64, std::io::Error> {
+    let num_pages = data.len().div_ceil(OVERFLOW_CHUNK_SIZE) as u64;
+    for (i, chunk) in data.chunks(OVERFLOW_CHUNK_SIZE).enumerate() {
+        let page_id = start.page_id() + i as u64;
+        let next_page = if (i as u64) + 1 < num_pages { Some(page_id + 1) } else { None };
+        let mut page = PageMut::open_with_type(
+            buffer_pool,
+            PageAddr::new(start.file_id(), page_id),
+            PageType::Overflow,
+        )?;
+        page.write_overflow_chunk(next_page, chunk)?;
+    }
+    Ok(num_pages)
+}
+
+/// Read `len` bytes from the overflow chain starting at `start`.
+pub fn read_overflow_chain(buffer_pool: &BufferPool, start: PageAddr, len: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut data = Vec::with_capacity(len);
+    let mut next_page = Some(start.page_id());
+    while data.len() < len {
+        let Some(page_id) = next_page else {
+            return Err(Corruption::BrokenOverflowChain { page: start }.into());
+        };
+        let page = Page::open(buffer_pool, PageAddr::new(start.file_id(), page_id))?;
+        let (next, chunk) = page.read_overflow_chunk()?;
+        data.extend_from_slice(chunk);
+        next_page = next;
+    }
+    if data.len() != len {
+        return Err(Corruption::BrokenOverflowChain { page: start }.into());
+    }
+    Ok(data)
+}
+
+/// The inline part of a record cell: the whole record, or its prefix when spilled.
+pub fn record_prefix<'c>(page: &impl PageRead, cell: &'c [u8]) -> Result<&'c [u8], std::io::Error> {
+    match TupleOnDisk::new(cell).read_field(&RECORD_DESCRIPTOR, 0)? {
+        TupleValue::VarBytes(payload) => Ok(payload),
+        _ => Err(Corruption::InvalidRecord {
+            page: page.page_address(),
+        }
+        .into()),
+    }
+}
+
+/// Read a record cell written by `SerialWriter::append_record`, reassembling it
+/// from its overflow chain if needed.
+pub fn read_record<'c>(
+    buffer_pool: &BufferPool,
+    page: &impl PageRead,
+    cell: &'c [u8],
+) -> Result<Cow<'c, [u8]>, std::io::Error> {
+    let tuple = TupleOnDisk::new(cell);
+    let prefix = record_prefix(page, cell)?;
+    let (first_page, total_len) = match (
+        tuple.read_field(&RECORD_DESCRIPTOR, 1)?,
+        tuple.read_field(&RECORD_DESCRIPTOR, 2)?,
+    ) {
+        (TupleValue::Null, TupleValue::Null) => return Ok(Cow::Borrowed(prefix)),
+        (TupleValue::Int64(first_page), TupleValue::Int64(total_len)) if total_len as usize >= prefix.len() => {
+            (first_page as u64, total_len as usize)
+        }
+        _ => {
+            return Err(Corruption::InvalidRecord {
+                page: page.page_address(),
+            }
+            .into());
+        }
+    };
+
+    let mut record = Vec::with_capacity(total_len);
+    record.extend_from_slice(prefix);
+    let chain_start = PageAddr::new(page.page_address().file_id(), first_page);
+    record.extend(read_overflow_chain(buffer_pool, chain_start, total_len - prefix.len())?);
+    Ok(Cow::Owned(record))
+}
+
 impl<'a> SerialReader<'a> {
     pub fn new(buffer_pool: &'a BufferPool, start_page_address: PageAddr, end_page_address: PageAddr) -> Result<Self, std::io::Error> {
         let page_reader = Page::open(buffer_pool, start_page_address)?;
@@ -75,7 +288,12 @@ impl<'a> SerialReader<'a> {
     fn switch_page(&mut self, new_page: PageAddr) -> Result<(), std::io::Error> {
         self.page_reader = Page::open(self.buffer_pool, new_page)?;
         self.page_address = new_page;
-        self.num_cells_in_page = self.page_reader.num_cells()?;
+        // Overflow pages are reached through their record cells, not by scanning
+        self.num_cells_in_page = if self.page_reader.page_type()? == PageType::Overflow {
+            0
+        } else {
+            self.page_reader.num_cells()?
+        };
         self.current_cell_index = 0;
         Ok(())
     }
@@ -86,7 +304,7 @@ impl<'a> StreamingIterator<'a> for SerialReader<'a> {
     type Item = Result<TupleOnDisk<'a>, std::io::Error>;
 
     fn next(&'a mut self) -> Option<Self::Item> {
-        if self.current_cell_index >= self.num_cells_in_page {
+        while self.current_cell_index >= self.num_cells_in_page {
             if self.page_address == self.end_page_address {
                 return None;
             }
@@ -113,7 +331,7 @@ impl<'a> StreamingIterator<'a> for SerialReader<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tuple::types::{TupleDescriptor, TupleFieldDescriptor, TupleFieldType, TupleValue};
+    use crate::constants::PAGE_SIZE;
     use std::sync::Arc;
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -249,4 +467,36 @@ mod tests {
 
         let _ = std::fs::remove_dir_all(dir);
     }
+
+    #[test]
+    fn test_records_with_overflow_chain() {
+        let dir = get_temp_dir();
+        let pool = Arc::new(BufferPool::new(dir.clone()).unwrap());
+        let page_addr = PageAddr::new(1, 0);
+
+        // Larger than the buffer pool, so chain pages are evicted while writing
+        let large: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let records: Vec<&[u8]> = vec![b"small", &large, b"after"];
+
+        let last_page = {
+            let mut writer = SerialWriter::new(&pool, page_addr).unwrap();
+            for record in &records {
+                writer.append_record(record).unwrap();
+            }
+            writer.last_page()
+        };
+        assert!(last_page.page_id() > (large.len() / PAGE_SIZE) as u64);
+
+        // All three records stay in the first page, the chain follows it
+        let page = Page::open(&pool, page_addr).unwrap();
+        assert_eq!(page.num_cells().unwrap(), 3);
+        for (i, expected) in records.iter().enumerate() {
+            let cell = page.read_cell(i).unwrap();
+            assert_eq!(read_record(&pool, &page, cell).unwrap().as_ref(), *expected);
+        }
+        let spilled_cell = page.read_cell(1).unwrap();
+        assert_eq!(record_prefix(&page, spilled_cell).unwrap(), &large[..SPILLED_PREFIX_LEN]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }